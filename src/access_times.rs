@@ -1,6 +1,6 @@
 use crate::action_paths::get_action_cache_dir;
 use crate::node::path::Path;
-use crate::{dir_tree, node, nonce, warning, Error};
+use crate::{debug, dir_tree, node, nonce, warning, Error};
 use async_trait::async_trait;
 
 const WAIT_ATIME_UPDATED_MS: u64 = 5;
@@ -14,6 +14,17 @@ fn default_access_time_offset() -> chrono::Duration {
 
 pub struct RevertAccessTime {
     duration: chrono::Duration,
+    num_failures: usize,
+}
+
+impl RevertAccessTime {
+    async fn revert(&mut self, path: &Path, is_file: bool) {
+        // An item we cannot update should not prevent us from updating the rest
+        if let Err(e) = set_atime_behind_mtime(path, &self.duration, is_file).await {
+            debug!("Unable to revert access time of {}: {}", path, e);
+            self.num_failures += 1;
+        }
+    }
 }
 
 #[async_trait(?Send)]
@@ -22,13 +33,16 @@ impl dir_tree::Visitor for RevertAccessTime {
         Ok(())
     }
 
-    async fn exit_folder(&mut self, _: &Path) -> Result<(), Error> {
+    async fn exit_folder(&mut self, path: &Path) -> Result<(), Error> {
+        // Listing the folder during the walk updates its access time, so it can
+        // only be reverted once the walk has left it
+        self.revert(path, false).await;
         Ok(())
     }
 
     async fn visit_entry(&mut self, path: &Path, is_file: bool) -> Result<(), Error> {
         if is_file {
-            set_atime_behind_mtime(path, &self.duration).await?;
+            self.revert(path, true).await;
         } else {
             panic!("Expected to descend into all directories");
         }
@@ -39,9 +53,17 @@ impl dir_tree::Visitor for RevertAccessTime {
 pub async fn revert_folder(path: &Path) -> Result<(), Error> {
     let mut visitor = RevertAccessTime {
         duration: default_access_time_offset(),
+        num_failures: 0,
     };
     let ignores = dir_tree::Ignores::default();
     dir_tree::apply_visitor(path, &ignores, &mut visitor).await?;
+    if visitor.num_failures > 0 {
+        warning!(
+            "Unable to revert access times of {} item(s) under {}. These may be pruned from the cache even if used.",
+            visitor.num_failures,
+            path
+        );
+    }
     Ok(())
 }
 
@@ -51,11 +73,19 @@ async fn get_atime_check_dir() -> Result<Path, Error> {
     Ok(dir)
 }
 
-async fn set_atime_behind_mtime(path: &Path, duration: &chrono::Duration) -> Result<(), Error> {
+/// Sets the access time of `path` to `duration` before its modification
+/// time. Files are not followed if they are symbolic links, since the target
+/// may lie outside the cached folder. Directories reached by the walk are
+/// never symbolic links, so following them makes no difference.
+async fn set_atime_behind_mtime(path: &Path, duration: &chrono::Duration, is_file: bool) -> Result<(), Error> {
     let metadata = node::fs::symlink_metadata(path).await?;
     let m_time = metadata.modified();
     let a_time = m_time - *duration;
-    node::fs::lutimes(path, &a_time, &m_time).await?;
+    if is_file {
+        node::fs::lutimes(path, &a_time, &m_time).await?;
+    } else {
+        node::fs::utimes(path, &a_time, &m_time).await?;
+    }
     Ok(())
 }
 
@@ -69,7 +99,7 @@ pub async fn supports_atime() -> Result<bool, Error> {
     };
     let data = [0u8; 1];
     node::fs::write_file(&file_path, &data).await?;
-    set_atime_behind_mtime(&file_path, &default_access_time_offset()).await?;
+    set_atime_behind_mtime(&file_path, &default_access_time_offset(), true).await?;
     {
         let metadata = node::fs::symlink_metadata(&file_path).await?;
         if metadata.accessed() >= metadata.modified() {
@@ -86,3 +116,29 @@ pub async fn supports_atime() -> Result<bool, Error> {
     // This needs to be >= and not > since times are discrete
     Ok(metadata.accessed() >= metadata.modified())
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{node, Error};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    async fn probe_atime_support() -> Result<(), Error> {
+        // The result depends on the filesystem so we only check the probe runs
+        let _supported: bool = super::supports_atime().await?;
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn directory_access_time_is_reverted() -> Result<(), Error> {
+        let root = node::fs::mkdtemp(node::os::temp_dir().join("ferrous-actions-atime-test-")).await?;
+        let dir = root.join("dir");
+        node::fs::create_dir_all(&dir).await?;
+        node::fs::write_file(&dir.join("file"), b"data").await?;
+        super::revert_folder(&root).await?;
+        let metadata = node::fs::symlink_metadata(&dir).await?;
+        assert!(metadata.accessed() < metadata.modified());
+        crate::actions::io::rm_rf(&root).await?;
+        Ok(())
+    }
+}
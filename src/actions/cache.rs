@@ -1,12 +1,67 @@
-use crate::node;
+use crate::actions::core;
 use crate::node::path::Path;
+use crate::{debug, dir_tree, node, warning};
+use async_trait::async_trait;
 use js_sys::JsString;
+use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::convert::Into;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use wasm_bindgen::prelude::*;
 
 const WORKSPACE_ENV_VAR: &str = "GITHUB_WORKSPACE";
 const WORKSPACE_OVERRIDDEN_TAG: &str = "#WORKSPACE_OVERRIDEN";
+const DEFAULT_API_URL: &str = "https://api.github.com";
+const CACHE_LISTING_PAGE_SIZE: usize = 100;
+const HTTP_FORBIDDEN: u16 = 403;
+const HTTP_NOT_FOUND: u16 = 404;
+
+// The ID `saveCache` returns when it did not save the entry
+const UNSAVED_CACHE_ID: i64 = -1;
+// The size limit `@actions/cache` places on an archive
+const CACHE_SIZE_LIMIT_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+
+static DEFAULT_BACKEND: Mutex<BackendKind> = parking_lot::const_mutex(BackendKind::GitHub);
+static CACHE_REQUIRED: Mutex<bool> = parking_lot::const_mutex(false);
+static DEFAULT_TRANSFER_OPTIONS: Mutex<TransferOptions> = parking_lot::const_mutex(TransferOptions {
+    upload_chunk_size: None,
+    upload_concurrency: None,
+    download_concurrency: None,
+    timeout: None,
+    segment_timeout: None,
+});
+static WARNED_UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+static OVERSIZE_SKIPPED_KEYS: Mutex<Vec<String>> = parking_lot::const_mutex(Vec::new());
+
+/// The keys of entries which were not saved because they were too large for
+/// the cache
+pub fn oversize_skipped_keys() -> Vec<String> {
+    OVERSIZE_SKIPPED_KEYS.lock().clone()
+}
+
+/// Whether files totalling `num_bytes` could have been rejected for
+/// exceeding the cache size limit. Compression only makes archives smaller,
+/// so anything under the limit cannot have been.
+fn may_exceed_size_limit(num_bytes: u64) -> bool {
+    num_bytes > CACHE_SIZE_LIMIT_BYTES
+}
+
+/// The result of attempting to save a cache entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveOutcome {
+    /// The entry was saved with the given ID
+    Saved(i64),
+    /// The cache backend is unavailable and caching is not required
+    Unavailable,
+    /// The entry was updated since it was restored, so was not saved
+    Superseded,
+    /// The entry was not saved and is larger than the cache size limit
+    TooLarge,
+    /// The backend did not save the entry, e.g. because another job is saving
+    /// an entry with the same key
+    NotSaved,
+}
 
 // Actually getting caching to work cross platform is complicated. First of all,
 // the action takes patterns not paths (which is unhelpful for apps that don't
@@ -81,12 +136,366 @@ impl Drop for ScopedWorkspace {
     }
 }
 
+/// A store that cache entries can be saved to and restored from. Paths are
+/// supplied as patterns relative to the current directory.
+#[async_trait(?Send)]
+pub trait Backend {
+    /// Whether the store can currently be reached. Operations on an
+    /// unavailable store are expected to fail.
+    fn is_available(&self) -> bool;
+
+    /// Saves the files matching `patterns` under `key`
+    async fn save(
+        &self,
+        patterns: Vec<JsString>,
+        key: &JsString,
+        options: &TransferOptions,
+        cross_os_archive: bool,
+    ) -> Result<SaveOutcome, JsValue>;
+
+    /// Restores the files matching `patterns` from the entry found using `key`
+    /// and then `restore_keys`, returning the key of the restored entry
+    async fn restore(
+        &self,
+        patterns: Vec<JsString>,
+        key: &JsString,
+        restore_keys: Vec<JsString>,
+        options: &TransferOptions,
+        cross_os_archive: bool,
+    ) -> Result<Option<String>, JsValue>;
+
+    /// Returns the key of the entry that would be restored using `keys`
+    /// without restoring it
+    async fn peek(
+        &self,
+        patterns: Vec<JsString>,
+        keys: Vec<JsString>,
+        cross_os_archive: bool,
+    ) -> Result<Option<String>, JsValue>;
+
+    /// Deletes the entry stored under `key` and any entries whose keys begin
+    /// with one of `restore_keys`, returning the number deleted
+    async fn delete(&self, key: &JsString, restore_keys: Vec<JsString>, token: &str) -> Result<usize, JsValue>;
+}
+
+#[derive(Clone, Copy, Debug, strum::EnumString)]
+pub enum BackendKind {
+    #[strum(serialize = "github")]
+    GitHub,
+}
+
+impl BackendKind {
+    fn build(self) -> Box<dyn Backend> {
+        match self {
+            BackendKind::GitHub => Box::new(GitHub),
+        }
+    }
+}
+
+/// Sets the backend used by all subsequently constructed entries
+pub fn set_default_backend(kind: BackendKind) {
+    *DEFAULT_BACKEND.lock() = kind;
+}
+
+/// Tuning options for transfers to and from the cache. Unset values use the
+/// defaults of the backend.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TransferOptions {
+    pub upload_chunk_size: Option<u32>,
+    pub upload_concurrency: Option<u32>,
+    pub download_concurrency: Option<u32>,
+    pub timeout: Option<Duration>,
+    pub segment_timeout: Option<Duration>,
+}
+
+impl TransferOptions {
+    fn upload_options(&self) -> js_sys::Object {
+        Self::build_object([
+            ("uploadChunkSize", self.upload_chunk_size.map(f64::from)),
+            ("uploadConcurrency", self.upload_concurrency.map(f64::from)),
+        ])
+    }
+
+    fn download_options(&self) -> js_sys::Object {
+        Self::build_object([
+            ("downloadConcurrency", self.download_concurrency.map(f64::from)),
+            ("timeoutInMs", self.timeout.as_ref().map(Self::duration_to_ms)),
+            (
+                "segmentTimeoutInMs",
+                self.segment_timeout.as_ref().map(Self::duration_to_ms),
+            ),
+        ])
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn duration_to_ms(duration: &Duration) -> f64 {
+        duration.as_millis() as f64
+    }
+
+    fn build_object<const N: usize>(values: [(&str, Option<f64>); N]) -> js_sys::Object {
+        let options = js_sys::Map::new();
+        for (name, value) in values {
+            if let Some(value) = value {
+                options.set(&name.into(), &value.into());
+            }
+        }
+        js_sys::Object::from_entries(&options).expect("Failed to convert options map to object")
+    }
+}
+
+/// Sets the transfer options used by all subsequently constructed entries
+pub fn set_default_transfer_options(options: TransferOptions) {
+    *DEFAULT_TRANSFER_OPTIONS.lock() = options;
+}
+
+/// Sets whether an unavailable cache backend is an error. If not, cache
+/// operations become no-ops when the backend is unavailable.
+pub fn set_cache_required(required: bool) {
+    *CACHE_REQUIRED.lock() = required;
+}
+
+/// The GitHub Actions cache service, via `@actions/cache`
+#[derive(Debug, Default)]
+pub struct GitHub;
+
+#[derive(Debug, serde::Deserialize)]
+struct CacheListing {
+    actions_caches: Vec<CacheListingEntry>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CacheListingEntry {
+    id: i64,
+    key: String,
+}
+
+impl GitHub {
+    fn caches_url() -> Result<String, JsValue> {
+        let env = node::process::get_env();
+        let api_url = env.get("GITHUB_API_URL").map_or(DEFAULT_API_URL, String::as_str);
+        let repository = env
+            .get("GITHUB_REPOSITORY")
+            .ok_or_else(|| JsError::new("Unable to determine repository from GITHUB_REPOSITORY"))?;
+        Ok(format!("{}/repos/{}/actions/caches", api_url, repository))
+    }
+
+    fn rest_headers(token: &str) -> js_sys::Object {
+        let headers = js_sys::Map::new();
+        headers.set(&"authorization".into(), &format!("Bearer {}", token).into());
+        headers.set(&"accept".into(), &"application/vnd.github+json".into());
+        js_sys::Object::from_entries(&headers).expect("Failed to convert headers map to object")
+    }
+
+    fn status_code(value: &JsValue) -> Option<u16> {
+        js_sys::Reflect::get(value, &"statusCode".into())
+            .ok()
+            .and_then(|code| code.as_f64())
+            .map(|code| {
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let code = code as u16;
+                code
+            })
+    }
+
+    fn explain_rest_error(error: JsValue) -> JsValue {
+        if Self::status_code(&error) == Some(HTTP_FORBIDDEN) {
+            JsError::new(concat!(
+                "The supplied GitHub token is not permitted to delete cache entries. ",
+                "It requires the `actions: write` permission."
+            ))
+            .into()
+        } else {
+            error
+        }
+    }
+
+    /// Lists the IDs of entries whose keys satisfy `matches`, using `prefix` to
+    /// narrow down the listing
+    async fn list_entries<F: Fn(&str) -> bool>(
+        client: &ffi::HttpClient,
+        caches_url: &str,
+        headers: &js_sys::Object,
+        prefix: &str,
+        matches: F,
+    ) -> Result<Vec<i64>, JsValue> {
+        let mut result = Vec::new();
+        for page in 1.. {
+            let url = format!(
+                "{}?key={}&per_page={}&page={}",
+                caches_url,
+                js_sys::encode_uri_component(prefix),
+                CACHE_LISTING_PAGE_SIZE,
+                page
+            );
+            let response = client
+                .get_json(&url.into(), headers)
+                .await
+                .map_err(Self::explain_rest_error)?;
+            let listing = js_sys::Reflect::get(&response, &"result".into())?;
+            let listing = js_sys::JSON::stringify(&listing)?;
+            let listing: CacheListing = serde_json::from_str(&String::from(listing))
+                .map_err(|e| JsError::new(&format!("Unable to parse cache listing: {}", e)))?;
+            let num_entries = listing.actions_caches.len();
+            result.extend(
+                listing
+                    .actions_caches
+                    .into_iter()
+                    .filter(|entry| matches(&entry.key))
+                    .map(|entry| entry.id),
+            );
+            if num_entries < CACHE_LISTING_PAGE_SIZE {
+                break;
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[async_trait(?Send)]
+impl Backend for GitHub {
+    fn is_available(&self) -> bool {
+        // This checks for the environment variables which locate the cache
+        // service. These are absent on some self-hosted runners and under `act`.
+        ffi::is_feature_available()
+    }
+
+    async fn save(
+        &self,
+        patterns: Vec<JsString>,
+        key: &JsString,
+        options: &TransferOptions,
+        cross_os_archive: bool,
+    ) -> Result<SaveOutcome, JsValue> {
+        use wasm_bindgen::JsCast;
+
+        let result = ffi::save_cache(patterns, key, Some(options.upload_options()), cross_os_archive).await?;
+        let id = result
+            .dyn_ref::<js_sys::Number>()
+            .ok_or_else(|| JsError::new("saveCache didn't return a number"))
+            .map(|n| {
+                #[allow(clippy::cast_possible_truncation)]
+                let id = n.value_of() as i64;
+                id
+            })?;
+        // `saveCache` reports most failures, including an archive being too large,
+        // by logging them rather than throwing
+        let outcome = if id == UNSAVED_CACHE_ID {
+            SaveOutcome::NotSaved
+        } else {
+            SaveOutcome::Saved(id)
+        };
+        Ok(outcome)
+    }
+
+    async fn restore(
+        &self,
+        patterns: Vec<JsString>,
+        key: &JsString,
+        restore_keys: Vec<JsString>,
+        options: &TransferOptions,
+        cross_os_archive: bool,
+    ) -> Result<Option<String>, JsValue> {
+        let result = ffi::restore_cache(
+            patterns,
+            key,
+            restore_keys,
+            Some(options.download_options()),
+            cross_os_archive,
+        )
+        .await?;
+        if result == JsValue::NULL || result == JsValue::UNDEFINED {
+            Ok(None)
+        } else {
+            let result: JsString = result.into();
+            Ok(Some(result.into()))
+        }
+    }
+
+    async fn peek(
+        &self,
+        patterns: Vec<JsString>,
+        keys: Vec<JsString>,
+        cross_os_archive: bool,
+    ) -> Result<Option<String>, JsValue> {
+        use js_sys::Object;
+
+        let compression_method: JsString = ffi::get_compression_method().await?.into();
+        let options = {
+            let options = js_sys::Map::new();
+            options.set(&"compressionMethod".into(), &compression_method.into());
+            options.set(&"enableCrossOsArchive".into(), &cross_os_archive.into());
+            Object::from_entries(&options).expect("Failed to convert options map to object")
+        };
+        let result = ffi::get_cache_entry(keys, patterns, Some(options)).await?;
+        if result == JsValue::NULL || result == JsValue::UNDEFINED {
+            Ok(None)
+        } else {
+            let result: Object = result.into();
+            let entries = Object::entries(&result);
+            let mut entries: HashMap<String, JsValue> = entries
+                .iter()
+                .map(Into::<js_sys::Array>::into)
+                .map(|e| (e.get(0), e.get(1)))
+                .map(|(k, v)| (Into::<JsString>::into(k), v))
+                .map(|(k, v)| (Into::<String>::into(k), v))
+                .collect();
+            Ok(entries
+                .remove("cacheKey")
+                .map(Into::<JsString>::into)
+                .map(Into::<String>::into))
+        }
+    }
+
+    async fn delete(&self, key: &JsString, restore_keys: Vec<JsString>, token: &str) -> Result<usize, JsValue> {
+        use wasm_bindgen::JsCast as _;
+
+        // `@actions/cache` has no support for deletion so we use the REST API
+        let client = ffi::HttpClient::new(&"ferrous-actions".into());
+        let caches_url = Self::caches_url()?;
+        let headers = Self::rest_headers(token);
+        let key = String::from(key);
+        let mut ids = Self::list_entries(&client, &caches_url, &headers, &key, |k| k == key).await?;
+        for restore_key in restore_keys {
+            let restore_key = String::from(restore_key);
+            let matches = |k: &str| k.starts_with(&restore_key);
+            ids.extend(Self::list_entries(&client, &caches_url, &headers, &restore_key, matches).await?);
+        }
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut num_deleted = 0;
+        for id in ids {
+            let url = format!("{}/{}", caches_url, id);
+            let response: ffi::HttpClientResponse = client
+                .del(&url.into(), &headers)
+                .await
+                .map_err(Self::explain_rest_error)?
+                .unchecked_into();
+            // The body must be consumed for the connection to be released
+            response.read_body().await?;
+            match Self::status_code(&response.message()) {
+                Some(code) if (200..300).contains(&code) => num_deleted += 1,
+                // Presumably deleted concurrently by someone else
+                Some(HTTP_NOT_FOUND) => {}
+                Some(HTTP_FORBIDDEN) => return Err(Self::explain_rest_error(response.message())),
+                code => {
+                    let message = format!("Deleting cache entry {} failed with status {:?}", id, code);
+                    return Err(JsError::new(&message).into());
+                }
+            }
+        }
+        Ok(num_deleted)
+    }
+}
+
 pub struct Entry {
     key: JsString,
     paths: Vec<Path>,
     restore_keys: Vec<JsString>,
     cross_os_archive: bool,
     relative_to: Option<Path>,
+    transfer_options: TransferOptions,
+    backend: Box<dyn Backend>,
 }
 
 impl Entry {
@@ -97,6 +506,8 @@ impl Entry {
             restore_keys: Vec::new(),
             cross_os_archive: false,
             relative_to: None,
+            transfer_options: *DEFAULT_TRANSFER_OPTIONS.lock(),
+            backend: DEFAULT_BACKEND.lock().build(),
         }
     }
 
@@ -132,40 +543,98 @@ impl Entry {
         self.restore_keys(std::iter::once(restore_key.into()))
     }
 
-    pub async fn save(&self) -> Result<i64, JsValue> {
-        use wasm_bindgen::JsCast;
-        let patterns = self.build_patterns();
-        let result = {
+    /// Saves the entry. An entry which is too large to be cached is skipped
+    /// with a warning rather than treated as an error.
+    pub async fn save(&self) -> Result<SaveOutcome, JsValue> {
+        if !self.check_backend_available()? {
+            return Ok(SaveOutcome::Unavailable);
+        }
+        let cwd = node::process::cwd();
+        let paths: Vec<Path> = self.paths.iter().map(|p| cwd.join(p)).collect();
+        let patterns = self.build_patterns()?;
+        let outcome = {
             let _caching_scope = self.build_action_scope()?;
-            ffi::save_cache(patterns, &self.key, None, self.cross_os_archive).await?
+            if core::is_debug() {
+                Self::verify_patterns_match(&patterns).await;
+            }
+            self.backend
+                .save(patterns, &self.key, &self.transfer_options, self.cross_os_archive)
+                .await?
         };
-        let result = result
-            .dyn_ref::<js_sys::Number>()
-            .ok_or_else(|| JsError::new("saveCache didn't return a number"))
-            .map(|n| {
-                #[allow(clippy::cast_possible_truncation)]
-                let id = n.value_of() as i64;
-                id
-            })?;
-        Ok(result)
+        if outcome != SaveOutcome::NotSaved {
+            return Ok(outcome);
+        }
+        // The backend does not tell us why nothing was saved, but the size of the
+        // files shows whether the size limit could have been the reason
+        let mut num_bytes = 0;
+        for path in &paths {
+            match dir_tree::total_file_size(path).await {
+                Ok(size) => num_bytes += size,
+                Err(e) => {
+                    debug!("Unable to find size of {}: {}", path, e);
+                    return Ok(outcome);
+                }
+            }
+        }
+        if may_exceed_size_limit(num_bytes) {
+            let key = String::from(&self.key);
+            warning!(
+                "Cache entry {} was not saved, most likely because its {} MiB of files exceed the cache size limit",
+                key,
+                num_bytes / (1024 * 1024)
+            );
+            OVERSIZE_SKIPPED_KEYS.lock().push(key);
+            Ok(SaveOutcome::TooLarge)
+        } else {
+            Ok(outcome)
+        }
     }
 
-    pub async fn save_if_update(&self, old_restore_key: Option<&str>) -> Result<Option<i64>, JsValue> {
+    /// Deletes stored entries that could be restored by this entry, returning
+    /// the number deleted. `token` must grant write access to the Actions of
+    /// the repository.
+    pub async fn delete(&self, token: &str) -> Result<usize, JsValue> {
+        if !self.check_backend_available()? {
+            return Ok(0);
+        }
+        self.backend.delete(&self.key, self.restore_keys.clone(), token).await
+    }
+
+    pub async fn save_if_update(&self, old_restore_key: Option<&str>) -> Result<SaveOutcome, JsValue> {
         let new_restore_key = self.peek_restore().await?;
         if new_restore_key.is_none() || new_restore_key.as_deref() == old_restore_key {
-            self.save().await.map(Some)
+            self.save().await
         } else {
-            Ok(None)
+            Ok(SaveOutcome::Superseded)
+        }
+    }
+
+    fn check_backend_available(&self) -> Result<bool, JsValue> {
+        if self.backend.is_available() {
+            Ok(true)
+        } else if *CACHE_REQUIRED.lock() {
+            Err(JsError::new("The cache service is unavailable but caching is required").into())
+        } else {
+            if !WARNED_UNAVAILABLE.swap(true, Ordering::Relaxed) {
+                warning!("The cache service is unavailable. Nothing will be restored from or saved to the cache.");
+            }
+            Ok(false)
         }
     }
 
-    fn build_patterns(&self) -> Vec<JsString> {
+    fn build_patterns(&self) -> Result<Vec<JsString>, JsValue> {
         let cwd = node::process::cwd();
         let mut result = Vec::with_capacity(self.paths.len());
         for path in &self.paths {
             // Rewrite path to be relative if we have a root
             let path = if let Some(relative_to) = &self.relative_to {
                 let absolute = cwd.join(path);
+                // A path outside the root would be archived relative to a location we do not
+                // control
+                if !absolute.is_inside(relative_to) {
+                    let message = format!("Cache path {} is not inside cache root {}", absolute, relative_to);
+                    return Err(JsError::new(&message).into());
+                }
                 absolute.relative_to(relative_to)
             } else {
                 path.clone()
@@ -179,13 +648,40 @@ impl Entry {
             // incorporated into the path hash.
             result.push(WORKSPACE_OVERRIDDEN_TAG.into());
         }
-        result
+        Ok(result)
+    }
+
+    /// Warns about any pattern which matches nothing, since the entry would
+    /// then be saved without the path it was derived from. This must be called
+    /// inside the action scope so patterns are resolved as they would be when
+    /// saving. It checks the patterns were derived correctly so is only done
+    /// when debugging.
+    async fn verify_patterns_match(patterns: &[JsString]) {
+        for pattern in patterns {
+            let pattern_string = String::from(pattern);
+            if pattern_string == WORKSPACE_OVERRIDDEN_TAG {
+                continue;
+            }
+            match ffi::resolve_paths(vec![pattern.clone()]).await {
+                Ok(matched) if js_sys::Array::from(&matched).length() > 0 => {}
+                Ok(_) => warning!("Cache pattern {} does not match any paths", pattern_string),
+                Err(e) => warning!(
+                    "Unable to resolve cache pattern {}: {}",
+                    pattern_string,
+                    crate::Error::Js(e)
+                ),
+            }
+        }
     }
 
     fn path_to_glob(path: &Path) -> String {
-        let path = path.to_string();
-        // This should be valid even for absolute paths on Windows
-        let path = path.replace(node::path::separator().as_ref(), "/");
+        // This should be valid even for absolute paths on Windows. Elsewhere, a
+        // backslash is a valid file name character rather than a separator.
+        let path = if node::path::separator() == "\\" {
+            core::to_posix_path(path)
+        } else {
+            path.to_string()
+        };
         // We do not escape ']' as it would close the character set
         let mut result = String::with_capacity(path.len());
         let is_windows = node::os::platform() == "windows";
@@ -213,62 +709,33 @@ impl Entry {
     }
 
     pub async fn restore(&self) -> Result<Option<String>, JsValue> {
-        let patterns = self.build_patterns();
-        let result = {
-            let _caching_scope = self.build_action_scope()?;
-            ffi::restore_cache(
+        if !self.check_backend_available()? {
+            return Ok(None);
+        }
+        let patterns = self.build_patterns()?;
+        let _caching_scope = self.build_action_scope()?;
+        self.backend
+            .restore(
                 patterns,
                 &self.key,
                 self.restore_keys.clone(),
-                None,
+                &self.transfer_options,
                 self.cross_os_archive,
             )
-            .await?
-        };
-        if result == JsValue::NULL || result == JsValue::UNDEFINED {
-            Ok(None)
-        } else {
-            let result: JsString = result.into();
-            Ok(Some(result.into()))
-        }
+            .await
     }
 
     async fn peek_restore(&self) -> Result<Option<String>, JsValue> {
-        use js_sys::Object;
-
-        let compression_method: JsString = ffi::get_compression_method().await?.into();
+        if !self.check_backend_available()? {
+            return Ok(None);
+        }
         let keys: Vec<JsString> = std::iter::once(&self.key)
             .chain(self.restore_keys.iter())
             .cloned()
             .collect();
-        let options = {
-            let options = js_sys::Map::new();
-            options.set(&"compressionMethod".into(), &compression_method.into());
-            options.set(&"enableCrossOsArchive".into(), &self.cross_os_archive.into());
-            Object::from_entries(&options).expect("Failed to convert options map to object")
-        };
-        let patterns = self.build_patterns();
-        let result = {
-            let _caching_scope = self.build_action_scope()?;
-            ffi::get_cache_entry(keys, patterns, Some(options)).await?
-        };
-        if result == JsValue::NULL || result == JsValue::UNDEFINED {
-            Ok(None)
-        } else {
-            let result: Object = result.into();
-            let entries = Object::entries(&result);
-            let mut entries: HashMap<String, JsValue> = entries
-                .iter()
-                .map(Into::<js_sys::Array>::into)
-                .map(|e| (e.get(0), e.get(1)))
-                .map(|(k, v)| (Into::<JsString>::into(k), v))
-                .map(|(k, v)| (Into::<String>::into(k), v))
-                .collect();
-            Ok(entries
-                .remove("cacheKey")
-                .map(Into::<JsString>::into)
-                .map(Into::<String>::into))
-        }
+        let patterns = self.build_patterns()?;
+        let _caching_scope = self.build_action_scope()?;
+        self.backend.peek(patterns, keys, self.cross_os_archive).await
     }
 }
 
@@ -278,6 +745,9 @@ pub mod ffi {
 
     #[wasm_bindgen(module = "@actions/cache")]
     extern "C" {
+        #[wasm_bindgen(js_name = "isFeatureAvailable")]
+        pub fn is_feature_available() -> bool;
+
         #[wasm_bindgen(js_name = "saveCache", catch)]
         pub async fn save_cache(
             paths: Vec<JsString>,
@@ -296,10 +766,47 @@ pub mod ffi {
         ) -> Result<JsValue, JsValue>;
     }
 
+    #[wasm_bindgen(module = "@actions/http-client")]
+    extern "C" {
+        #[derive(Debug)]
+        #[wasm_bindgen(js_name = "HttpClient")]
+        pub(super) type HttpClient;
+
+        #[wasm_bindgen(constructor)]
+        pub(super) fn new(user_agent: &JsString) -> HttpClient;
+
+        #[wasm_bindgen(method, js_name = "getJson", catch)]
+        pub(super) async fn get_json(
+            this: &HttpClient,
+            request_url: &JsString,
+            additional_headers: &Object,
+        ) -> Result<JsValue, JsValue>;
+
+        #[wasm_bindgen(method, catch)]
+        pub(super) async fn del(
+            this: &HttpClient,
+            request_url: &JsString,
+            additional_headers: &Object,
+        ) -> Result<JsValue, JsValue>;
+
+        #[derive(Debug)]
+        #[wasm_bindgen(js_name = "HttpClientResponse")]
+        pub(super) type HttpClientResponse;
+
+        #[wasm_bindgen(method, getter)]
+        pub(super) fn message(this: &HttpClientResponse) -> JsValue;
+
+        #[wasm_bindgen(method, js_name = "readBody", catch)]
+        pub(super) async fn read_body(this: &HttpClientResponse) -> Result<JsValue, JsValue>;
+    }
+
     #[wasm_bindgen(module = "@actions/cache/lib/internal/cacheUtils")]
     extern "C" {
         #[wasm_bindgen(js_name = "getCompressionMethod", catch)]
         pub(super) async fn get_compression_method() -> Result<JsValue, JsValue>;
+
+        #[wasm_bindgen(js_name = "resolvePaths", catch)]
+        pub(super) async fn resolve_paths(patterns: Vec<JsString>) -> Result<JsValue, JsValue>;
     }
 
     #[wasm_bindgen(module = "@actions/cache/lib/internal/cacheHttpClient")]
@@ -312,3 +819,16 @@ pub mod ffi {
         ) -> Result<JsValue, JsValue>;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{may_exceed_size_limit, CACHE_SIZE_LIMIT_BYTES};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn only_entries_over_the_limit_may_be_too_large() {
+        assert!(!may_exceed_size_limit(0));
+        assert!(!may_exceed_size_limit(CACHE_SIZE_LIMIT_BYTES));
+        assert!(may_exceed_size_limit(CACHE_SIZE_LIMIT_BYTES + 1));
+    }
+}
@@ -1,18 +1,30 @@
+use crate::node;
 use crate::node::path::Path;
 use js_sys::{JsString, Number, Object};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 use wasm_bindgen::JsValue;
 
+const ENV_FILE_VAR: &str = "GITHUB_ENV";
+const FORCED_DEBUG_PREFIX: &str = "[debug] ";
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Normal as u8);
+static OPEN_GROUPS: AtomicUsize = AtomicUsize::new(0);
+
 #[macro_export]
 macro_rules! debug {
     ($($arg:tt)*) => {{
-        $crate::actions::core::debug(std::format!($($arg)*).as_str());
+        if $crate::actions::core::is_debug() {
+            $crate::actions::core::debug(std::format!($($arg)*).as_str());
+        }
     }};
 }
 
 #[macro_export]
 macro_rules! info {
     ($($arg:tt)*) => {{
-        $crate::actions::core::info(std::format!($($arg)*).as_str());
+        if $crate::actions::core::log_level() >= $crate::actions::core::LogLevel::Normal {
+            $crate::actions::core::info(std::format!($($arg)*).as_str());
+        }
     }};
 }
 
@@ -37,8 +49,50 @@ macro_rules! error {
     }};
 }
 
+/// Controls which messages are logged. Notices, warnings and errors are
+/// always logged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, strum::EnumString)]
+#[repr(u8)]
+pub enum LogLevel {
+    /// Informational and debug messages are suppressed
+    #[strum(serialize = "quiet")]
+    Quiet,
+
+    /// Debug messages are only logged if enabled on the runner
+    #[strum(serialize = "normal")]
+    Normal,
+
+    /// Debug messages are always logged
+    #[strum(serialize = "debug")]
+    Debug,
+}
+
+impl LogLevel {
+    fn from_u8(value: u8) -> LogLevel {
+        match value {
+            0 => LogLevel::Quiet,
+            1 => LogLevel::Normal,
+            _ => LogLevel::Debug,
+        }
+    }
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn log_level() -> LogLevel {
+    LogLevel::from_u8(LOG_LEVEL.load(Ordering::Relaxed))
+}
+
 pub fn debug<S: Into<JsString>>(message: S) {
-    ffi::debug(&message.into());
+    if ffi::is_debug() {
+        ffi::debug(&message.into());
+    } else if log_level() == LogLevel::Debug {
+        // The runner would hide debug messages so we log them as information
+        let message = JsString::from(FORCED_DEBUG_PREFIX).concat(&message.into());
+        ffi::info(&message);
+    }
 }
 
 pub fn info<S: Into<JsString>>(message: S) {
@@ -57,6 +111,12 @@ pub fn error<A: Into<Annotation>>(message: A) {
     message.into().error();
 }
 
+/// Whether debug messages will be logged, either because the runner has
+/// enabled them or the log level forces them
+pub fn is_debug() -> bool {
+    log_level() == LogLevel::Debug || ffi::is_debug()
+}
+
 pub fn set_output<N: Into<JsString>, V: Into<JsString>>(name: N, value: V) {
     ffi::set_output(&name.into(), &value.into());
 }
@@ -225,14 +285,67 @@ pub fn set_failed<M: Into<JsString>>(message: M) {
     ffi::set_failed(&message.into());
 }
 
+/// Masks `secret` wherever it would appear in the log
+pub fn set_secret<S: Into<JsString>>(secret: S) {
+    ffi::set_secret(&secret.into());
+}
+
 pub fn add_path(path: &Path) {
     ffi::add_path(&path.into());
 }
 
+/// Exports a variable to subsequent steps. The variable is also set in the
+/// environment of this process so that commands run later by this action
+/// see it.
 pub fn export_variable<N: Into<JsString>, V: Into<JsString>>(name: N, value: V) {
     let name = name.into();
     let value = value.into();
     ffi::export_variable(&name, &value);
+    // `@actions/core` currently does this too, but we rely on it
+    node::process::set_var(&String::from(&name), &String::from(&value));
+}
+
+/// Returns the value most recently exported for `name` by the current step via
+/// the file named by `$GITHUB_ENV`. Such values only appear in the process
+/// environment of subsequent steps.
+pub fn get_exported_variable(name: &str) -> Option<String> {
+    let env_file = node::process::get_env().get(ENV_FILE_VAR)?.clone();
+    let contents = node::fs::read_file_sync(env_file.as_str()).ok()?;
+    let contents = String::from_utf8_lossy(&contents);
+    parse_exported_variable(&contents, name)
+}
+
+fn parse_exported_variable(contents: &str, name: &str) -> Option<String> {
+    let mut result = None;
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        // As in the runner, whichever of `=` and `<<` occurs first decides the form
+        let heredoc = line.find("<<").filter(|h| !matches!(line.find('='), Some(e) if e < *h));
+        if let Some(heredoc) = heredoc {
+            // Multi-line values are written using a heredoc-style delimiter
+            let (key, delimiter) = (&line[..heredoc], &line[heredoc + 2..]);
+            let mut value = Vec::new();
+            for line in lines.by_ref() {
+                if line == delimiter {
+                    break;
+                }
+                value.push(line);
+            }
+            if key == name {
+                result = Some(value.join("\n"));
+            }
+        } else if let Some((key, value)) = line.split_once('=') {
+            if key == name {
+                result = Some(value.to_string());
+            }
+        }
+    }
+    result
+}
+
+/// Converts all separators in `path` to forward slashes
+pub fn to_posix_path<P: Into<JsString>>(path: P) -> String {
+    ffi::to_posix_path(&path.into()).into()
 }
 
 pub fn save_state<N: Into<JsString>, V: Into<JsString>>(name: N, value: V) {
@@ -253,11 +366,44 @@ pub fn get_state<N: Into<JsString>>(name: N) -> Option<String> {
 }
 
 pub fn start_group<N: Into<JsString>>(name: N) {
-    ffi::start_group(&name.into());
+    let name = name.into();
+    if open_groups() != 0 {
+        // The runner does not nest groups, so this will end the open one
+        crate::debug!("Starting log group {} while another is still open", name);
+    }
+    ffi::start_group(&name);
+    OPEN_GROUPS.fetch_add(1, Ordering::Relaxed);
 }
 
 pub fn end_group() {
     ffi::end_group();
+    let _ = OPEN_GROUPS.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+}
+
+/// The number of groups started but not yet ended
+fn open_groups() -> usize {
+    OPEN_GROUPS.load(Ordering::Relaxed)
+}
+
+/// A log group which is ended when dropped, so that it is closed even if an
+/// error causes an early return
+#[derive(Debug)]
+#[must_use]
+pub struct Group {
+    _private: (),
+}
+
+impl Group {
+    pub fn new<N: Into<JsString>>(name: N) -> Group {
+        start_group(name);
+        Group { _private: () }
+    }
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        end_group();
+    }
 }
 
 #[allow(clippy::drop_non_drop)]
@@ -287,6 +433,10 @@ pub mod ffi {
         #[wasm_bindgen]
         pub fn debug(message: &JsString);
 
+        /// Whether the runner has step debug logging enabled
+        #[wasm_bindgen(js_name = "isDebug")]
+        pub fn is_debug() -> bool;
+
         /// Writes an error with an optional annotation
         #[wasm_bindgen]
         pub fn error(message: &JsString, annotation: Option<Object>);
@@ -308,6 +458,9 @@ pub mod ffi {
         #[wasm_bindgen(js_name = "setOutput")]
         pub fn set_output(name: &JsString, value: &JsString);
 
+        #[wasm_bindgen(js_name = "setSecret")]
+        pub fn set_secret(secret: &JsString);
+
         #[wasm_bindgen(js_name = "addPath")]
         pub fn add_path(path: &JsString);
 
@@ -325,5 +478,57 @@ pub mod ffi {
 
         #[wasm_bindgen(js_name = "endGroup")]
         pub fn end_group();
+
+        #[wasm_bindgen(js_name = "toPosixPath")]
+        pub fn to_posix_path(path: &JsString) -> JsString;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{export_variable, open_groups, parse_exported_variable, to_posix_path, Group};
+    use crate::node;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn exported_variable_is_visible_in_process() {
+        let name = "FERROUS_ACTIONS_EXPORT_TEST";
+        export_variable(name, "value");
+        assert_eq!(node::process::get_env().get(name).map(String::as_str), Some("value"));
+        node::process::remove_var(name);
+    }
+
+    #[wasm_bindgen_test]
+    fn path_separators_become_posix() {
+        let posix = "a/b/c";
+        assert_eq!(to_posix_path("a\\b\\c"), posix);
+        assert_eq!(to_posix_path("a\\b/c"), posix);
+        assert_eq!(to_posix_path(posix), posix);
+    }
+
+    #[wasm_bindgen_test]
+    fn group_is_ended_on_early_return() {
+        fn grouped(fail: bool) -> Result<(), ()> {
+            let _group = Group::new("Test group");
+            if fail {
+                return Err(());
+            }
+            Ok(())
+        }
+
+        let open = open_groups();
+        assert!(grouped(true).is_err());
+        assert_eq!(open_groups(), open);
+        assert!(grouped(false).is_ok());
+        assert_eq!(open_groups(), open);
+    }
+
+    #[wasm_bindgen_test]
+    fn exported_variable_parsing() {
+        let contents = "A=1\nB<<EOF\nx=y\nz\nEOF\nA=2\nC=a<<b\n";
+        assert_eq!(parse_exported_variable(contents, "A").as_deref(), Some("2"));
+        assert_eq!(parse_exported_variable(contents, "B").as_deref(), Some("x=y\nz"));
+        assert_eq!(parse_exported_variable(contents, "C").as_deref(), Some("a<<b"));
+        assert_eq!(parse_exported_variable(contents, "x"), None);
     }
 }
@@ -1,8 +1,10 @@
 use super::push_line_splitter::PushLineSplitter;
+use crate::actions::core;
 use crate::node::path::Path;
-use crate::{node, noop_stream};
+use crate::{debug, node, noop_stream, Error};
 use js_sys::{JsString, Object};
 use parking_lot::Mutex;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsValue;
@@ -34,13 +36,13 @@ impl Stdio {
 struct StreamToLines {
     splitter: Arc<Mutex<PushLineSplitter>>,
     #[allow(clippy::type_complexity)]
-    callback: Arc<Box<dyn Fn(&str)>>,
+    callback: Arc<Box<dyn Fn(&[u8])>>,
     closure: Closure<dyn Fn(JsValue)>,
 }
 
 impl StreamToLines {
     #[allow(clippy::type_complexity)]
-    pub fn new(callback: Arc<Box<dyn Fn(&str)>>) -> StreamToLines {
+    pub fn new(callback: Arc<Box<dyn Fn(&[u8])>>) -> StreamToLines {
         let splitter: Arc<Mutex<PushLineSplitter>> = Arc::default();
         let closure = {
             let splitter = splitter.clone();
@@ -51,8 +53,8 @@ impl StreamToLines {
                 let mut write_buffer = splitter.write_via_buffer(data.length() as usize);
                 data.copy_to(write_buffer.as_mut());
                 drop(write_buffer);
-                while let Some(line) = splitter.next_line() {
-                    callback(&line);
+                while let Some(line) = splitter.next_line_bytes() {
+                    callback(line);
                 }
             })
         };
@@ -68,8 +70,8 @@ impl Drop for StreamToLines {
     fn drop(&mut self) {
         let mut splitter = self.splitter.lock();
         splitter.close();
-        while let Some(line) = splitter.next_line() {
-            (self.callback)(&line);
+        while let Some(line) = splitter.next_line_bytes() {
+            (self.callback)(line);
         }
     }
 }
@@ -84,12 +86,13 @@ pub struct Command {
     command: Path,
     args: Vec<JsString>,
     #[allow(clippy::type_complexity)]
-    outline: Option<Arc<Box<dyn Fn(&str)>>>,
+    outline: Option<Arc<Box<dyn Fn(&[u8])>>>,
     #[allow(clippy::type_complexity)]
-    errline: Option<Arc<Box<dyn Fn(&str)>>>,
+    errline: Option<Arc<Box<dyn Fn(&[u8])>>>,
     stdout: Stdio,
     stderr: Stdio,
     cwd: Path,
+    env: BTreeMap<String, String>,
 }
 
 impl Command {
@@ -125,39 +128,126 @@ impl Command {
         }
 
         options.set(&"cwd".into(), &self.cwd.to_js_string());
+        if !self.env.is_empty() {
+            // The child's environment is replaced rather than extended
+            let mut env = node::process::get_env();
+            env.extend(self.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+            let env_map = js_sys::Map::new();
+            for (name, value) in env {
+                env_map.set(&name.into(), &value.into());
+            }
+            let env = Object::from_entries(&env_map).expect("Failed to convert environment map to object");
+            options.set(&"env".into(), &env);
+        }
+        // When debugging, discarded output is kept so it can be shown if the command
+        // fails, since it is otherwise the only record of what went wrong.
         let sink = noop_stream::Sink::default();
+        let capturing_sink = core::is_debug().then(noop_stream::CapturingSink::default);
+        let discard_to: &JsValue = capturing_sink.as_ref().map_or(sink.as_ref(), AsRef::as_ref);
         if let StdioEnum::Null = self.stdout.inner {
-            options.set(&"outStream".into(), sink.as_ref());
+            options.set(&"outStream".into(), discard_to);
         }
         if let StdioEnum::Null = self.stderr.inner {
-            options.set(&"errStream".into(), sink.as_ref());
+            options.set(&"errStream".into(), discard_to);
         }
 
         let listeners = Object::from_entries(&listeners).expect("Failed to convert listeners map to object");
         options.set(&"listeners".into(), &listeners);
         let options = Object::from_entries(&options).expect("Failed to convert options map to object");
-        let result = ffi::exec(&command, Some(args), &options).await.map(|r| {
+        let result = ffi::exec(&command, Some(args), &options).await;
+
+        // Be explicit about line-buffer flushing. This must happen before anything
+        // else can fail so that a final unterminated line (e.g. the last diagnostic
+        // of a process that was killed) is never lost.
+        drop(outline_adapter);
+        drop(errline_adapter);
+        let code = result.map(|r| {
             #[allow(clippy::cast_possible_truncation)]
             let code = r.as_f64().expect("exec didn't return a number") as i32;
             code
-        });
+        })?;
+        if let Some(capturing_sink) = capturing_sink.filter(|_| code != 0) {
+            let discarded = capturing_sink.contents();
+            if !discarded.is_empty() {
+                debug!(
+                    "Discarded output of {} which exited with code {}:\n{}",
+                    self.command,
+                    code,
+                    String::from_utf8_lossy(&discarded)
+                );
+            }
+        }
+        Ok(code)
+    }
 
-        // Be explict about line-buffer flushing
-        drop(outline_adapter);
-        drop(errline_adapter);
-        result
+    /// Like `exec()` but also treats a non-zero exit code as an error
+    pub async fn exec_checked(&mut self) -> Result<(), Error> {
+        let code = self.exec().await?;
+        if code == 0 {
+            Ok(())
+        } else {
+            Err(Error::CommandFailed {
+                command: self.command.to_string(),
+                code,
+            })
+        }
+    }
+
+    /// Runs the command to completion and returns its standard output. A
+    /// non-zero exit code is treated as an error.
+    pub async fn output(&mut self) -> Result<String, Error> {
+        let output: Arc<Mutex<String>> = Arc::default();
+        {
+            let output = Arc::clone(&output);
+            self.outline(move |line| {
+                let mut output = output.lock();
+                *output += line;
+                *output += "\n";
+            })
+            .stdout(Stdio::null())
+            .exec_checked()
+            .await?;
+        }
+        let output = std::mem::take(&mut *output.lock());
+        Ok(output)
     }
 
     pub fn outline<F: Fn(&str) + 'static>(&mut self, callback: F) -> &mut Command {
-        self.outline = Some(Arc::new(Box::new(callback)));
-        self
+        self.outline_bytes(move |line| callback(&String::from_utf8_lossy(line)))
     }
 
     pub fn errline<F: Fn(&str) + 'static>(&mut self, callback: F) -> &mut Command {
-        self.errline = Some(Arc::new(Box::new(callback)));
+        self.errline_bytes(move |line| callback(&String::from_utf8_lossy(line)))
+    }
+
+    /// Like `outline()` but supplies each line as raw bytes. If multiple
+    /// callbacks are added, they are invoked in the order they were added.
+    pub fn outline_bytes<F: Fn(&[u8]) + 'static>(&mut self, callback: F) -> &mut Command {
+        self.outline = Some(Self::chain_callback(self.outline.take(), callback));
+        self
+    }
+
+    /// Like `errline()` but supplies each line as raw bytes. If multiple
+    /// callbacks are added, they are invoked in the order they were added.
+    pub fn errline_bytes<F: Fn(&[u8]) + 'static>(&mut self, callback: F) -> &mut Command {
+        self.errline = Some(Self::chain_callback(self.errline.take(), callback));
         self
     }
 
+    #[allow(clippy::type_complexity)]
+    fn chain_callback<F: Fn(&[u8]) + 'static>(
+        existing: Option<Arc<Box<dyn Fn(&[u8])>>>,
+        callback: F,
+    ) -> Arc<Box<dyn Fn(&[u8])>> {
+        match existing {
+            Some(existing) => Arc::new(Box::new(move |line| {
+                existing(line);
+                callback(line);
+            })),
+            None => Arc::new(Box::new(callback)),
+        }
+    }
+
     pub fn stdout(&mut self, redirect: Stdio) -> &mut Command {
         self.stdout = redirect;
         self
@@ -173,6 +263,48 @@ impl Command {
         self
     }
 
+    /// Sets an environment variable for the command, in addition to those
+    /// inherited from this process
+    pub fn env<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> &mut Command {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Prepends `dir` to the `PATH` of the command. Unlike
+    /// `core::add_path`, this does not affect this process or later steps.
+    pub fn prepend_path(&mut self, dir: &Path) -> &mut Command {
+        let name = Self::path_var_name();
+        let existing = self
+            .env
+            .get(&name)
+            .cloned()
+            .or_else(|| node::process::get_env().remove(&name))
+            .unwrap_or_default();
+        let value = if existing.is_empty() {
+            dir.to_string()
+        } else {
+            format!("{}{}{}", dir, node::path::delimiter(), existing)
+        };
+        self.env.insert(name, value);
+        self
+    }
+
+    fn path_var_name() -> String {
+        let name = "PATH";
+        // Windows environment variable names are case insensitive and this is
+        // usually `Path`. Setting a differently cased duplicate would leave it
+        // unclear which the child sees.
+        if node::os::platform() == "win32" {
+            if let Some(existing) = node::process::get_env()
+                .into_keys()
+                .find(|k| k.eq_ignore_ascii_case(name))
+            {
+                return existing;
+            }
+        }
+        name.into()
+    }
+
     // Some bright spark had the idea of making an exec function that could both
     // handle execvp and shell command style invocations rather than have two
     // functions or some sort of flag to handle these different use cases.
@@ -208,6 +340,7 @@ impl<'a> From<&'a Path> for Command {
             stdout: Stdio::inherit(),
             stderr: Stdio::inherit(),
             cwd: node::process::cwd(),
+            env: BTreeMap::new(),
         }
     }
 }
@@ -226,3 +359,48 @@ pub mod ffi {
         ) -> Result<JsValue, JsValue>;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Command, StreamToLines};
+    use crate::node::path::Path;
+    use crate::node::{self};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use wasm_bindgen::{JsCast as _, JsValue};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn unterminated_line_is_flushed() {
+        let lines: Arc<Mutex<Vec<String>>> = Arc::default();
+        let adapter = {
+            let lines = lines.clone();
+            StreamToLines::new(Arc::new(Box::new(move |line: &[u8]| {
+                lines.lock().push(String::from_utf8_lossy(line).into_owned());
+            })))
+        };
+        let callback: &js_sys::Function = adapter.as_ref().unchecked_ref();
+        let data = js_sys::Uint8Array::from(b"first\nsecond".as_slice());
+        callback
+            .call1(&JsValue::null(), &data)
+            .expect("Unable to invoke stream callback");
+        assert_eq!(*lines.lock(), vec!["first"]);
+        drop(adapter);
+        assert_eq!(*lines.lock(), vec!["first", "second"]);
+    }
+
+    #[wasm_bindgen_test]
+    fn path_is_prepended() {
+        let first = node::os::temp_dir().join("first");
+        let second = node::os::temp_dir().join("second");
+        let mut command = Command::from(&Path::from("cargo"));
+        command.prepend_path(&first).prepend_path(&second);
+        let name = Command::path_var_name();
+        let path = command.env.get(&name).expect("PATH was not set");
+        let delimiter = node::path::delimiter();
+        assert!(path.starts_with(&format!("{}{}{}", second, delimiter, first)));
+        if let Some(original) = node::process::get_env().get(&name).filter(|p| !p.is_empty()) {
+            assert!(path.ends_with(&format!("{}{}", delimiter, original)));
+        }
+    }
+}
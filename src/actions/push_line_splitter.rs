@@ -1,4 +1,3 @@
-use std::borrow::Cow;
 use std::collections::VecDeque;
 
 /// A platform-agnostic line splitter.
@@ -78,12 +77,15 @@ impl PushLineSplitter {
         }
     }
 
-    pub fn next_line(&mut self) -> Option<Cow<str>> {
+    /// Returns the next line as raw bytes, without the delimiter.
+    ///
+    /// No UTF-8 conversion is performed, so callers that parse lines (e.g. as
+    /// JSON) are never handed replacement characters.
+    pub fn next_line_bytes(&mut self) -> Option<&[u8]> {
         if let Some((line_len, delim_len)) = self.lines.pop_front() {
-            let slice = &self.buffer[self.taken..(self.taken + line_len)];
-            let line = String::from_utf8_lossy(slice);
+            let start = self.taken;
             self.taken += line_len + delim_len;
-            Some(line)
+            Some(&self.buffer[start..(start + line_len)])
         } else {
             None
         }
@@ -167,21 +169,21 @@ mod tests {
                     Mode::AllAtOnce => {
                         splitter.write(bytes);
                         splitter.close();
-                        while let Some(line) = splitter.next_line() {
-                            lines.push(line.into_owned());
+                        while let Some(line) = splitter.next_line_bytes() {
+                            lines.push(String::from_utf8_lossy(line).into_owned());
                         }
                     }
                     Mode::Bytes => {
                         for byte in bytes.iter().copied() {
                             let byte = [byte];
                             splitter.write(&byte[..]);
-                            while let Some(line) = splitter.next_line() {
-                                lines.push(line.into_owned());
+                            while let Some(line) = splitter.next_line_bytes() {
+                                lines.push(String::from_utf8_lossy(line).into_owned());
                             }
                         }
                         splitter.close();
-                        while let Some(line) = splitter.next_line() {
-                            lines.push(line.into_owned());
+                        while let Some(line) = splitter.next_line_bytes() {
+                            lines.push(String::from_utf8_lossy(line).into_owned());
                         }
                     }
                 }
@@ -201,4 +203,24 @@ mod tests {
     fn byte_at_a_time_write() {
         test_reconstruction(Mode::Bytes);
     }
+
+    #[wasm_bindgen_test]
+    fn multibyte_split_across_writes() {
+        let string = "caf\u{e9} \u{1f980}\nna\u{ef}ve";
+        let bytes = string.as_bytes();
+        let mut splitter = PushLineSplitter::default();
+        let mut lines = Vec::new();
+        for byte in bytes.iter().copied() {
+            splitter.write(&[byte]);
+            while let Some(line) = splitter.next_line_bytes() {
+                lines.push(line.to_vec());
+            }
+        }
+        splitter.close();
+        while let Some(line) = splitter.next_line_bytes() {
+            lines.push(line.to_vec());
+        }
+        let expected: Vec<Vec<u8>> = string.lines().map(|l| l.as_bytes().to_vec()).collect();
+        assert_eq!(lines, expected);
+    }
 }
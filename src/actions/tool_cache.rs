@@ -1,15 +1,85 @@
+use crate::actions::core;
+use crate::debug;
 use crate::node::path::Path;
 use crate::node::process;
 use js_sys::JsString;
+use parking_lot::Mutex;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::convert::Into;
 use wasm_bindgen::prelude::*;
 
+// Hosts serving GitHub content which accept a GitHub token
+const GITHUB_HOSTS: &[&str] = &[
+    "api.github.com",
+    "codeload.github.com",
+    "github.com",
+    "objects.githubusercontent.com",
+    "raw.githubusercontent.com",
+];
+
+static GITHUB_TOKEN: Mutex<Option<String>> = parking_lot::const_mutex(None);
+
+/// Sets the token used to authenticate downloads from GitHub. The token is
+/// masked in the log.
+pub fn set_github_token(token: Option<String>) {
+    if let Some(token) = &token {
+        core::set_secret(token.as_str());
+    }
+    *GITHUB_TOKEN.lock() = token;
+}
+
+/// Returns the host of an HTTPS URL. Credentials must never be sent over
+/// anything else, so other URLs have no host.
+fn https_host(url: &str) -> Option<&str> {
+    let rest = url.strip_prefix("https://")?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+    if host.is_empty() {
+        None
+    } else {
+        Some(host)
+    }
+}
+
+fn is_github_host(host: &str) -> bool {
+    // This covers GitHub Enterprise Server
+    let server_url = process::get_env().remove("GITHUB_SERVER_URL");
+    let server_host = server_url.as_deref().and_then(https_host);
+    GITHUB_HOSTS.iter().any(|known| known.eq_ignore_ascii_case(host))
+        || matches!(server_host, Some(server_host) if server_host.eq_ignore_ascii_case(host))
+}
+
+/// The HTTP status code of a failed download, if it failed due to an
+/// unexpected response
+pub fn http_status(error: &JsValue) -> Option<u16> {
+    js_sys::Reflect::get(error, &"httpStatusCode".into())
+        .ok()
+        .and_then(|code| code.as_f64())
+        .map(|code| {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let code = code as u16;
+            code
+        })
+}
+
+/// The authorization to use for a download from `url`, if any
+fn github_auth(url: &str) -> Option<JsString> {
+    let host = https_host(url).filter(|host| is_github_host(host))?;
+    let token = GITHUB_TOKEN.lock();
+    token.as_ref().map(|token| {
+        debug!("Authenticating download from {} with the GitHub token", host);
+        JsString::from(format!("token {}", token))
+    })
+}
+
 #[derive(Debug)]
 pub struct DownloadTool {
     url: JsString,
     dest: Option<Path>,
     auth: Option<JsString>,
+    headers: BTreeMap<String, String>,
 }
 
 impl<U: Into<JsString>> From<U> for DownloadTool {
@@ -18,6 +88,7 @@ impl<U: Into<JsString>> From<U> for DownloadTool {
             url: url.into(),
             dest: None,
             auth: None,
+            headers: BTreeMap::new(),
         }
     }
 }
@@ -33,13 +104,27 @@ impl DownloadTool {
         self
     }
 
+    /// Adds a header to the request. Any response other than 200 is an
+    /// error, including 304 for conditional requests.
+    pub fn header<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) -> &mut Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
     pub async fn download(&mut self) -> Result<Path, JsValue> {
         let dest = self.dest.as_ref().map(|dest| {
             let mut resolved = process::cwd();
             resolved.push(dest.clone());
             JsString::from(&resolved)
         });
-        ffi::download_tool(&self.url, dest.as_ref(), self.auth.as_ref(), None)
+        let auth = self.auth.clone().or_else(|| github_auth(&String::from(&self.url)));
+        let headers = (!self.headers.is_empty()).then(|| {
+            let headers: js_sys::Map = self.headers.iter().fold(js_sys::Map::new(), |map, (name, value)| {
+                map.set(&name.into(), &value.into())
+            });
+            js_sys::Object::from_entries(&headers).expect("Failed to convert headers map to object")
+        });
+        ffi::download_tool(&self.url, dest.as_ref(), auth.as_ref(), headers.as_ref())
             .await
             .map(Into::<JsString>::into)
             .map(Into::<Path>::into)
@@ -70,10 +155,20 @@ impl StreamCompression {
     }
 }
 
-pub async fn extract_tar(path: &Path, compression: StreamCompression, dest: Option<&Path>) -> Result<Path, JsValue> {
+/// Extracts a tarball. The first `strip_components` leading path components
+/// of each member are removed, as with tar's `--strip-components`.
+pub async fn extract_tar(
+    path: &Path,
+    compression: StreamCompression,
+    dest: Option<&Path>,
+    strip_components: usize,
+) -> Result<Path, JsValue> {
     let mut tar_option = String::from("x");
     tar_option += &compression.tar_flag();
-    let tar_option = vec![JsString::from(tar_option)];
+    let mut tar_option = vec![JsString::from(tar_option)];
+    if strip_components > 0 {
+        tar_option.push(format!("--strip-components={}", strip_components).into());
+    }
 
     let path: JsString = path.into();
     let dest = dest.map(Into::<JsString>::into);
@@ -93,7 +188,7 @@ pub async fn cache_dir(tool: &str, version: &str, path: &Path, arch: Option<&str
 }
 
 pub mod ffi {
-    use js_sys::{JsString, Map};
+    use js_sys::{JsString, Object};
     use wasm_bindgen::prelude::*;
 
     #[wasm_bindgen(module = "@actions/tool-cache")]
@@ -103,7 +198,7 @@ pub mod ffi {
             url: &JsString,
             dest: Option<&JsString>,
             auth: Option<&JsString>,
-            headers: Option<&Map>,
+            headers: Option<&Object>,
         ) -> Result<JsValue, JsValue>;
 
         #[wasm_bindgen(js_name = "cacheDir", catch)]
@@ -122,3 +217,34 @@ pub mod ffi {
         ) -> Result<JsValue, JsValue>;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{http_status, https_host, is_github_host};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn host_is_extracted_from_https_urls() {
+        assert_eq!(https_host("https://github.com/a/b"), Some("github.com"));
+        assert_eq!(https_host("https://user@example.com:8443?x"), Some("example.com"));
+        assert_eq!(https_host("http://github.com/a/b"), None);
+        assert_eq!(https_host("https:///a"), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn rust_hosts_are_not_github_hosts() {
+        assert!(is_github_host("raw.githubusercontent.com"));
+        assert!(is_github_host("GitHub.com"));
+        assert!(!is_github_host("static.rust-lang.org"));
+        assert!(!is_github_host("sh.rustup.rs"));
+        assert!(!is_github_host("github.com.example.com"));
+    }
+
+    #[wasm_bindgen_test]
+    fn http_status_is_read_from_errors() {
+        let error = js_sys::Error::new("Unexpected HTTP response: 404");
+        js_sys::Reflect::set(&error, &"httpStatusCode".into(), &404.into()).expect("Failed to set status code");
+        assert_eq!(http_status(&error.into()), Some(404));
+        assert_eq!(http_status(&js_sys::Error::new("Network unreachable").into()), None);
+    }
+}
@@ -1,18 +1,19 @@
 use crate::action_paths::get_action_cache_dir;
-use crate::actions::cache::Entry as CacheEntry;
+use crate::actions::cache::{Entry as CacheEntry, SaveOutcome};
 use crate::actions::core;
 use crate::agnostic_path::AgnosticPath;
-use crate::delta::{render_list as render_delta_list, Action as DeltaAction};
+use crate::delta::{render_list as render_delta_list, render_summary as render_delta_summary, Action as DeltaAction};
 use crate::dir_tree::match_relative_paths;
-use crate::fingerprinting::{fingerprint_path_with_ignores, Fingerprint, Ignores};
+use crate::error::Context as _;
+use crate::fingerprinting::{self, fingerprint_path_with_ignores, Fingerprint, Ignores};
 use crate::hasher::Blake3 as Blake3Hasher;
 use crate::input_manager::{self, Input};
 use crate::job::Job;
 use crate::node::os::homedir;
 use crate::node::path::Path;
-use crate::{actions, error, info, node, notice, safe_encoding, warning, Error};
+use crate::{actions, dir_tree, error, info, node, notice, safe_encoding, warning, Error};
 use chrono::{DateTime, Utc};
-use lazy_static::lazy_static;
+use parking_lot::Mutex;
 use rustup_toolchain_manifest::HashValue;
 use serde::{Deserialize, Serialize};
 use simple_path_match::{PathMatch, PathMatchBuilder};
@@ -25,18 +26,21 @@ use strum::{Display, EnumIter, EnumString, IntoEnumIterator, IntoStaticStr};
 const ATIMES_SUPPORTED_KEY: &str = "ACCESS_TIMES_SUPPORTED";
 const DEFAULT_CROSS_OS_SHARING: CrossPlatformSharing = CrossPlatformSharing::All;
 const SCOPE_HASH_KEY: &str = "SCOPE_HASH";
+const LOCKFILE_SCOPE_HASH_KEY: &str = "LOCKFILE_SCOPE_HASH";
+const MAX_DELTA_LIST_LENGTH: usize = 50;
+const DELTA_SUMMARY_ITEMS_PER_ACTION: usize = 10;
+const CONTENT_ENTRY_ROLE: &str = "content";
+const DEPENDENCIES_ENTRY_ROLE: &str = "dependencies";
+const CACHE_HIT_OUTPUT: &str = "cache-hit";
+const SAVE_LOCK_FILE_NAME: &str = "cache-save.lock";
+const SAVE_LOCK_TIMEOUT_SECS: u64 = 600;
+const SAVE_LOCK_STALE_AFTER_SECS: u64 = 1800;
 
-lazy_static! {
-    static ref CARGO_HOME: String = {
-        node::process::get_env()
-            .get("CARGO_HOME")
-            .map(String::as_str)
-            .map_or_else(|| homedir().join(".cargo"), Path::from)
-            .to_string()
-    };
-}
+const CARGO_HOME_VAR: &str = "CARGO_HOME";
 
-#[derive(Clone, Copy, Debug, EnumString)]
+static CARGO_HOME_OVERRIDE: Mutex<Option<String>> = parking_lot::const_mutex(None);
+
+#[derive(Clone, Copy, Debug, Display, EnumString)]
 enum CrossPlatformSharing {
     #[strum(serialize = "none")]
     None,
@@ -64,6 +68,67 @@ impl CrossPlatformSharing {
     }
 }
 
+#[derive(Clone, Copy, Debug, EnumString)]
+enum ScopeStrategy {
+    #[strum(serialize = "lockfile")]
+    Lockfile,
+
+    #[strum(serialize = "metadata")]
+    Metadata,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct PruneStats {
+    num_entries: usize,
+    num_bytes: u64,
+}
+
+/// Why a cached group was or was not saved
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RecacheDecision {
+    New,
+    Changed,
+    WithinInterval {
+        outdated_by: std::time::Duration,
+        interval: std::time::Duration,
+    },
+    Unchanged,
+}
+
+impl RecacheDecision {
+    fn should_save(self) -> bool {
+        matches!(self, RecacheDecision::New | RecacheDecision::Changed)
+    }
+
+    /// A short description used when counting decisions
+    fn summary(self) -> &'static str {
+        match self {
+            RecacheDecision::New => "new",
+            RecacheDecision::Changed => "changed",
+            RecacheDecision::WithinInterval { .. } => "within recache window",
+            RecacheDecision::Unchanged => "unchanged",
+        }
+    }
+}
+
+impl std::fmt::Display for RecacheDecision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use humantime::format_duration;
+
+        match self {
+            RecacheDecision::New => write!(f, "WILL SAVE (new)"),
+            RecacheDecision::Changed => write!(f, "WILL SAVE (changed)"),
+            RecacheDecision::WithinInterval { outdated_by, interval } => write!(
+                f,
+                "SKIPPED (outdated by {}, within {} recache window)",
+                format_duration(*outdated_by),
+                format_duration(*interval)
+            ),
+            RecacheDecision::Unchanged => write!(f, "SKIPPED (unchanged)"),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct Group {
     restore_key: Option<String>,
@@ -102,20 +167,23 @@ struct GroupIdentifier {
 }
 
 impl Cache {
-    pub async fn new(cache_type: CacheType) -> Result<Cache, Error> {
+    pub async fn new(cache_type: CacheType, cache_registry_src: bool) -> Result<Cache, Error> {
         let sources = HashMap::new();
-        Self::new_with_sources(cache_type, sources).await
+        Self::new_with_sources(cache_type, sources, cache_registry_src).await
     }
 
     async fn new_with_sources(
         cache_type: CacheType,
         mut sources: HashMap<AgnosticPath, String>,
+        cache_registry_src: bool,
     ) -> Result<Cache, Error> {
         // Delete derived content at any paths we want to build the cache at
-        for delete_path in find_additional_delete_paths(cache_type).await? {
+        for delete_path in find_additional_delete_paths(cache_type, cache_registry_src).await? {
             if delete_path.exists().await {
                 info!("Pruning redundant cache element: {}", delete_path);
-                actions::io::rm_rf(&delete_path).await?;
+                actions::io::rm_rf(&delete_path)
+                    .await
+                    .with_context(|| format!("while pruning {}", delete_path))?;
             }
         }
         let grouping_depth = cache_type.grouping_depth();
@@ -164,70 +232,227 @@ impl Cache {
         }
     }
 
+    /// Restores the cached item, also returning whether its dependency list
+    /// was found
     pub async fn restore_from_env(
         cache_type: CacheType,
         scope: &HashValue,
         cross_platform_sharing: CrossPlatformSharing,
-    ) -> Result<Cache, Error> {
+        verify: bool,
+        cache_registry_src: bool,
+        merge: bool,
+    ) -> Result<(Cache, bool), Error> {
         use crate::access_times::revert_folder;
         use itertools::Itertools as _;
 
         let job = Job::from_env()?;
 
-        // Delete existing cache
+        // Delete or set aside existing cache
         let folder_path = find_path(cache_type);
+        let mut preserved_path = None;
         if folder_path.exists().await {
-            warning!(
-                concat!(
-                    "Cache action will delete existing contents of {} and derived information. ",
-                    "To avoid this warning, place this action earlier or delete this before running the action."
-                ),
-                folder_path
-            );
-            actions::io::rm_rf(&folder_path).await?;
+            if merge {
+                let preserved = preserved_folder_path(&folder_path);
+                info!(
+                    "Existing contents of {} will be merged with restored items",
+                    folder_path
+                );
+                actions::io::rm_rf(&preserved.to_namespaced()).await?;
+                node::fs::rename(&folder_path, &preserved).await?;
+                preserved_path = Some(preserved);
+            } else {
+                warning!(
+                    concat!(
+                        "Cache action will delete existing contents of {} and derived information. ",
+                        "To avoid this warning, place this action earlier, delete this before running the action ",
+                        "or enable merge-restore."
+                    ),
+                    folder_path
+                );
+                actions::io::rm_rf(&folder_path.to_namespaced()).await?;
+            }
         }
 
-        let entry = build_cache_entry_dependencies(cache_type, scope, &job)?;
-        let restore_key = entry.restore().await.map_err(Error::Js)?;
+        // Any error is held until pre-existing content has been put back, so it
+        // is not stranded if restoring fails
+        let verify_before_merge = verify && preserved_path.is_some();
+        let restored = async {
+            let entry = build_cache_entry_dependencies(cache_type, scope, &job)?;
+            let restore_key = entry.restore().await.map_err(Error::Js)?;
+            let mut restore_keys = HashMap::new();
+            let mut expected_groups = Vec::new();
+            let dependencies_found = restore_key.is_some();
+            if let Some(restore_key) = restore_key {
+                info!(
+                    "Located dependencies list for {} in cache using key {}.",
+                    cache_type.friendly_name(),
+                    restore_key
+                );
+                let dep_file_path = dependency_file_path(cache_type, scope, &job)?;
+                let groups = Self::read_dependency_list(cache_type, &dep_file_path).await?;
+                // Groups are deleted if they fail verification so we must not trust their paths
+                for group in &groups {
+                    let group_path = folder_path.join(&group.path);
+                    if !group_path.is_inside(&folder_path) {
+                        return Err(Error::PathNotInside {
+                            path: group_path.to_string(),
+                            root: folder_path.to_string(),
+                        });
+                    }
+                }
+                let group_list_string = groups.iter().map(|g| &g.path).join(", ");
+                info!(
+                    "The following groups will be restored for cache type {}: {}",
+                    cache_type.friendly_name(),
+                    group_list_string
+                );
+                restore_keys = Self::restore_groups(cache_type, &folder_path, &groups, cross_platform_sharing).await?;
+                expected_groups = groups;
+            } else {
+                info!("No existing dependency list for {} found.", cache_type.friendly_name());
+            }
+            // Ensure we at least have an empty folder
+            node::fs::create_dir_all(&folder_path).await?;
+            if verify_before_merge {
+                // Verify before merging so pre-existing content is never evicted
+                let mut restored = Self::new_with_sources(cache_type, restore_keys.clone(), cache_registry_src).await?;
+                restored.verify(&expected_groups).await?;
+            }
+            Ok::<_, Error>((restore_keys, expected_groups, dependencies_found))
+        }
+        .await;
+        if let Some(preserved_path) = preserved_path {
+            // Pre-existing files take precedence over restored ones
+            let merged = dir_tree::overlay_and_move_dir(&preserved_path, &folder_path)
+                .await
+                .context("while merging existing contents with restored items");
+            match merged {
+                Err(e) if restored.is_err() => {
+                    warning!("Unable to return existing contents of {}: {}", folder_path, e);
+                }
+                merged => merged?,
+            }
+        }
+        let (restore_keys, expected_groups, dependencies_found) = restored?;
+        let verify = verify && !verify_before_merge;
+        // Revert access times
+        revert_folder(&folder_path).await?;
+        let mut cache = Self::new_with_sources(cache_type, restore_keys, cache_registry_src).await?;
+        if verify {
+            cache.verify(&expected_groups).await?;
+        }
+        Ok((cache, dependencies_found))
+    }
+
+    /// Restores each of `groups`, returning the keys they were restored from.
+    /// Groups which fail to restore are logged and left missing.
+    async fn restore_groups(
+        cache_type: CacheType,
+        folder_path: &Path,
+        groups: &[GroupIdentifier],
+        cross_platform_sharing: CrossPlatformSharing,
+    ) -> Result<HashMap<AgnosticPath, String>, Error> {
         let mut restore_keys = HashMap::new();
-        if let Some(restore_key) = restore_key {
-            info!(
-                "Located dependencies list for {} in cache using key {}.",
-                cache_type.friendly_name(),
-                restore_key
-            );
-            let dep_file_path = dependency_file_path(cache_type, scope, &job)?;
-            let groups: Vec<GroupIdentifier> = {
-                let file_contents = node::fs::read_file(&dep_file_path).await?;
-                postcard::from_bytes(&file_contents)?
-            };
-            let group_list_string = groups.iter().map(|g| &g.path).join(", ");
-            info!(
-                "The following groups will be restored for cache type {}: {}",
-                cache_type.friendly_name(),
-                group_list_string
-            );
-            for group in &groups {
-                let entry = Self::group_identifier_to_cache_entry(cache_type, group, cross_platform_sharing);
-                if let Some(name) = entry.restore().await? {
+        let mut num_failed = 0usize;
+        for group in groups {
+            let entry = Self::group_identifier_to_cache_entry(cache_type, group, cross_platform_sharing);
+            match entry.restore().await {
+                Ok(Some(name)) => {
                     info!("Restored cache key: {}", name);
                     restore_keys.insert(group.path.clone(), name);
-                } else {
+                }
+                Ok(None) => {
                     info!(
                         "Failed to find {} cache entry for {}",
                         cache_type.friendly_name(),
                         group.path
                     );
                 }
+                Err(e) => {
+                    // The group is treated as missing, so it will be rebuilt and saved
+                    warning!(
+                        "Failed to restore {} cache entry for {}: {}",
+                        cache_type.friendly_name(),
+                        group.path,
+                        Error::from(e)
+                    );
+                    actions::io::rm_rf(&folder_path.join(&group.path).to_namespaced()).await?;
+                    num_failed += 1;
+                }
+            }
+        }
+        if num_failed != 0 {
+            warning!(
+                "Failed to restore {} of {} {} cache groups",
+                num_failed,
+                groups.len(),
+                cache_type.friendly_name()
+            );
+        }
+        Ok(restore_keys)
+    }
+
+    async fn verify(&mut self, expected_groups: &[GroupIdentifier]) -> Result<(), Error> {
+        let root_path = self.get_root_path();
+        let mut num_verified = 0usize;
+        let mut num_evicted = 0usize;
+        for expected in expected_groups {
+            let restored = self
+                .root
+                .get(&expected.path)
+                .and_then(|group| group.restore_key.as_ref())
+                .is_some();
+            if !restored {
+                continue;
+            }
+            let actual = self.build_group_identifier(&expected.path);
+            if actual == *expected {
+                num_verified += 1;
+            } else {
+                warning!(
+                    concat!(
+                        "Restored {} cache group {} does not match what was cached ",
+                        "(expected {} entries, found {}). Evicting it so it will be rebuilt."
+                    ),
+                    self.cache_type.friendly_name(),
+                    expected.path,
+                    expected.num_entries,
+                    actual.num_entries
+                );
+                actions::io::rm_rf(&root_path.join(&expected.path).to_namespaced()).await?;
+                // We retain the group and its restore key but with no entries. This causes
+                // the group to appear changed when the cache is saved.
+                if let Some(group) = self.root.get_mut(&expected.path) {
+                    group.entries.clear();
+                }
+                num_evicted += 1;
+            }
+        }
+        info!(
+            "Verified {} restored {} cache group(s), evicted {}.",
+            num_verified,
+            self.cache_type.friendly_name(),
+            num_evicted
+        );
+        Ok(())
+    }
+
+    /// Reads a list of groups. A list which cannot be deserialized is treated
+    /// as empty, so that nothing is restored and a new list is saved.
+    async fn read_dependency_list(cache_type: CacheType, path: &Path) -> Result<Vec<GroupIdentifier>, Error> {
+        let file_contents = node::fs::read_file(path).await?;
+        match postcard::from_bytes(&file_contents).map_err(Error::SerdePostcard) {
+            Ok(groups) => Ok(groups),
+            Err(e) => {
+                warning!(
+                    "Ignoring unreadable {} dependency list at {}: {}",
+                    cache_type.friendly_name(),
+                    path,
+                    e
+                );
+                Ok(Vec::new())
             }
-        } else {
-            info!("No existing dependency list for {} found.", cache_type.friendly_name());
         }
-        // Ensure we at least have an empty folder
-        node::fs::create_dir_all(&folder_path).await?;
-        // Revert access times
-        revert_folder(&folder_path).await?;
-        Self::new_with_sources(cache_type, restore_keys).await
     }
 
     pub async fn save_changes(
@@ -240,8 +465,7 @@ impl Cache {
         let job = Job::from_env()?;
         let dep_file_path = dependency_file_path(self.cache_type, scope_hash, &job)?;
         let old_groups = if dep_file_path.exists().await {
-            let file_contents = node::fs::read_file(&dep_file_path).await?;
-            postcard::from_bytes(&file_contents)?
+            Self::read_dependency_list(self.cache_type, &dep_file_path).await?
         } else {
             Vec::new()
         };
@@ -251,7 +475,7 @@ impl Cache {
             info!("{} dependency list is unchanged.", self.cache_type.friendly_name());
         } else {
             info!("{} dependency list changed:", self.cache_type.friendly_name());
-            info!("{}", render_delta_list(&group_list_delta));
+            info!("{}", render_delta(&group_list_delta));
             let serialized_groups = postcard::to_stdvec(&new_groups)?;
             {
                 let parent = dep_file_path.parent();
@@ -259,73 +483,105 @@ impl Cache {
             }
             node::fs::write_file(&dep_file_path, &serialized_groups).await?;
             let dependencies_entry = build_cache_entry_dependencies(self.cache_type, scope_hash, &job)?;
-            dependencies_entry.save().await?;
-            info!("{} dependency list was successfully saved.", self.cache_type);
+            if let SaveOutcome::Saved(_) = dependencies_entry.save().await? {
+                info!("{} dependency list was successfully saved.", self.cache_type);
+            }
         }
 
+        let mut to_save = Vec::new();
+        let mut decision_counts = BTreeMap::new();
         for (path, group) in &self.root {
-            let (attempt_save, old_restore_key) = if let Some(old_group) = old.root.get(path) {
-                let group_delta = Self::compare_groups(&old_group.entries, &group.entries);
-                let attempt_save = if group_delta.is_empty() {
-                    // The group's content is unchanged
-                    false
-                } else {
-                    // The modification time is dubious because we cannot track when file deletions
-                    // occur and modifications times could be preserved from some sort of archive.
-                    // It should work fine for changes to Git repos however, which are our main
-                    // concern.
-                    let old_modification = old_group.last_modified().unwrap_or_default();
-                    // Be robust against our delta being negative.
-                    let modification_delta = chrono::Utc::now() - old_modification;
-                    let modification_delta = std::cmp::max(chrono::Duration::zero(), modification_delta);
-
-                    let interval_is_sufficient = modification_delta > *min_recache_interval;
-                    if interval_is_sufficient {
-                        info!("Cached {} group {} has changed:", self.cache_type.friendly_name(), path);
-                        info!("{}", render_delta_list(&group_delta));
-                        true
-                    } else {
-                        use humantime::format_duration;
-                        info!(
-                            "Cached {} group {} outdated by {}, but not updating cache since minimum recache interval is {}.",
-                            self.cache_type,
-                            path,
-                            format_duration(modification_delta.to_std()?),
-                            format_duration(min_recache_interval.to_std()?),
-                        );
-                        false
-                    }
-                };
-                (attempt_save, old_group.restore_key.as_deref())
-            } else {
-                // The group did not previously exist in the cache
-                (true, None)
+            let old_group = old.root.get(path);
+            let decision = match old_group {
+                Some(old_group) => Self::recache_decision(old_group, group, min_recache_interval)?,
+                None => RecacheDecision::New,
             };
+            info!("{} group {}: {}", self.cache_type.friendly_name(), path, decision);
+            *decision_counts.entry(decision.summary()).or_insert(0usize) += 1;
+            if decision.should_save() {
+                to_save.push((path, old_group.and_then(|g| g.restore_key.as_deref())));
+            }
+        }
+        if !decision_counts.is_empty() {
+            info!(
+                "{} groups: {} to save ({}).",
+                self.cache_type.friendly_name(),
+                to_save.len(),
+                decision_counts
+                    .iter()
+                    .map(|(summary, count)| format!("{} {}", count, summary))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        self.save_groups(to_save, cross_platform_sharing).await
+    }
 
-            if attempt_save {
-                let identifier = self.build_group_identifier(path);
-                let entry = Self::group_identifier_to_cache_entry(self.cache_type, &identifier, cross_platform_sharing);
-                info!(
-                    "Saving modified {} cache group {}",
+    /// Decides whether `group` needs to be saved given the group at the same
+    /// path when the cache was restored
+    fn recache_decision(
+        old_group: &Group,
+        group: &Group,
+        min_recache_interval: &chrono::Duration,
+    ) -> Result<RecacheDecision, Error> {
+        let group_delta = Self::compare_groups(&old_group.entries, &group.entries);
+        if group_delta.is_empty() {
+            return Ok(RecacheDecision::Unchanged);
+        }
+        // The modification time is dubious because we cannot track when file deletions
+        // occur and modifications times could be preserved from some sort of archive.
+        // It should work fine for changes to Git repos however, which are our main
+        // concern.
+        let old_modification = old_group.last_modified().unwrap_or_default();
+        // Be robust against our delta being negative.
+        let modification_delta = chrono::Utc::now() - old_modification;
+        let modification_delta = std::cmp::max(chrono::Duration::zero(), modification_delta);
+        if modification_delta > *min_recache_interval {
+            Ok(RecacheDecision::Changed)
+        } else {
+            Ok(RecacheDecision::WithinInterval {
+                outdated_by: modification_delta.to_std()?,
+                interval: min_recache_interval.to_std()?,
+            })
+        }
+    }
+
+    /// Saves each group in `groups` unless its entry was updated since it was
+    /// restored using the paired key
+    async fn save_groups(
+        &self,
+        groups: Vec<(&AgnosticPath, Option<&str>)>,
+        cross_platform_sharing: CrossPlatformSharing,
+    ) -> Result<(), Error> {
+        for (path, old_restore_key) in groups {
+            let identifier = self.build_group_identifier(path);
+            let entry = Self::group_identifier_to_cache_entry(self.cache_type, &identifier, cross_platform_sharing);
+            info!(
+                "Saving modified {} cache group {}",
+                self.cache_type.friendly_name(),
+                path
+            );
+            match entry.save_if_update(old_restore_key).await? {
+                SaveOutcome::Saved(_) => info!(
+                    "{} cache group {} saved successfully.",
                     self.cache_type.friendly_name(),
                     path
-                );
-                if entry.save_if_update(old_restore_key).await?.is_some() {
-                    info!(
-                        "{} cache group {} saved successfully.",
-                        self.cache_type.friendly_name(),
-                        path
-                    );
-                } else {
-                    info!(
-                        concat!(
-                            "It looks like the changed {} cache group {} already exists. ",
-                            "Not saving our version this time around because we can't be certain it's a useful update. "
-                        ),
-                        self.cache_type.friendly_name(),
-                        path
-                    );
-                }
+                ),
+                SaveOutcome::Superseded => info!(
+                    concat!(
+                        "It looks like the changed {} cache group {} already exists. ",
+                        "Not saving our version this time around because we can't be certain it's a useful update. "
+                    ),
+                    self.cache_type.friendly_name(),
+                    path
+                ),
+                // Already reported when saving
+                SaveOutcome::TooLarge => {}
+                SaveOutcome::Unavailable | SaveOutcome::NotSaved => info!(
+                    "{} cache group {} was not saved.",
+                    self.cache_type.friendly_name(),
+                    path
+                ),
             }
         }
         Ok(())
@@ -404,7 +660,7 @@ impl Cache {
     ) -> CacheEntry {
         use crate::cache_key_builder::{Attribute, CacheKeyBuilder};
 
-        let name = format!("{} (content)", cache_type.friendly_name());
+        let name = cache_type.entry_name(CONTENT_ENTRY_ROLE);
         let mut builder = CacheKeyBuilder::new(&name);
         builder.add_key_data(group_id);
         builder.set_attribute(Attribute::Path, group_id.path.to_string());
@@ -435,7 +691,7 @@ impl Cache {
         left: &BTreeMap<AgnosticPath, Fingerprint>,
         right: &mut BTreeMap<AgnosticPath, Fingerprint>,
         right_path: &Path,
-    ) -> Result<(), Error> {
+    ) -> Result<PruneStats, Error> {
         use itertools::{EitherOrBoth, Itertools as _};
         let from_iter = left.iter();
         let to_iter = right.iter();
@@ -447,27 +703,35 @@ impl Cache {
             })
             .collect();
 
+        let mut stats = PruneStats::default();
         for element_path in to_prune {
             let path = right_path.join(element_path);
             info!("Pruning unused cache element at {}", path);
             actions::io::rm_rf(&path).await?;
-            right.remove(element_path);
+            if let Some(fingerprint) = right.remove(element_path) {
+                stats.num_entries += 1;
+                stats.num_bytes += fingerprint.size();
+            }
         }
-        Ok(())
+        Ok(stats)
     }
 
-    pub async fn prune_unused(&mut self, old: &Cache) -> Result<(), Error> {
+    pub async fn prune_unused(&mut self, old: &Cache) -> Result<PruneStats, Error> {
         use itertools::{EitherOrBoth, Itertools as _};
         let root_path = Path::from(&self.root_path);
         let from_iter = old.root.iter();
         let to_iter = self.root.iter_mut();
         let merged = from_iter.merge_join_by(to_iter, |left, right| left.0.cmp(right.0));
+        let mut stats = PruneStats::default();
         for element in merged {
             match element {
                 EitherOrBoth::Left(_) | EitherOrBoth::Right(_) => {}
                 EitherOrBoth::Both(left, right) => {
                     let entry_path = root_path.join(right.0);
-                    Self::prune_unused_entries(&left.1.entries, &mut right.1.entries, &entry_path).await?;
+                    let group_stats =
+                        Self::prune_unused_entries(&left.1.entries, &mut right.1.entries, &entry_path).await?;
+                    stats.num_entries += group_stats.num_entries;
+                    stats.num_bytes += group_stats.num_bytes;
                 }
             }
         }
@@ -478,22 +742,63 @@ impl Cache {
             }
             keep
         });
-        Ok(())
+        Ok(stats)
     }
 
     pub fn get_root_path(&self) -> Path {
         Path::from(&self.root_path)
     }
+
+    fn size(&self) -> u64 {
+        self.root
+            .values()
+            .flat_map(|group| group.entries.values())
+            .map(Fingerprint::size)
+            .sum()
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn bytes_to_mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
 }
 
-fn find_cargo_home() -> Path {
-    Path::from(CARGO_HOME.as_str())
+/// Sets a location for Cargo home which takes precedence over the environment
+pub fn set_cargo_home_override(path: Option<String>) {
+    *CARGO_HOME_OVERRIDE.lock() = path;
+}
+
+pub fn find_cargo_home() -> Path {
+    // This is deliberately not memoized since an earlier part of this step may
+    // have changed CARGO_HOME
+    if let Some(path) = CARGO_HOME_OVERRIDE.lock().as_ref() {
+        return Path::from(path);
+    }
+    core::get_exported_variable(CARGO_HOME_VAR)
+        .or_else(|| node::process::get_env().get(CARGO_HOME_VAR).cloned())
+        .map_or_else(|| homedir().join(".cargo"), |path| Path::from(&path))
+}
+
+fn render_delta<S: std::fmt::Display>(delta: &[(S, DeltaAction)]) -> String {
+    if delta.len() > MAX_DELTA_LIST_LENGTH && !core::is_debug() {
+        render_delta_summary(delta, DELTA_SUMMARY_ITEMS_PER_ACTION)
+    } else {
+        render_delta_list(delta)
+    }
 }
 
 fn find_path(cache_type: CacheType) -> Path {
     find_cargo_home().join(cache_type.relative_path())
 }
 
+/// A sibling of `folder_path` so that moving content there and back is a
+/// rename on the same filesystem
+fn preserved_folder_path(folder_path: &Path) -> Path {
+    folder_path
+        .parent()
+        .join(&format!("{}.pre-restore", folder_path.file_name()))
+}
+
 fn depth_to_match(depth: usize) -> Result<PathMatch, Error> {
     use itertools::Itertools as _;
 
@@ -505,9 +810,9 @@ fn depth_to_match(depth: usize) -> Result<PathMatch, Error> {
     Ok(PathMatch::from_pattern(&pattern, &node::path::separator())?)
 }
 
-async fn find_additional_delete_paths(cache_type: CacheType) -> Result<Vec<Path>, Error> {
+async fn find_additional_delete_paths(cache_type: CacheType, cache_registry_src: bool) -> Result<Vec<Path>, Error> {
     let mut path_match_builder = PathMatchBuilder::new(&node::path::separator());
-    cache_type.add_additional_delete_paths(&mut path_match_builder)?;
+    cache_type.add_additional_delete_paths(&mut path_match_builder, cache_registry_src)?;
     let path_matcher = path_match_builder.build()?;
     let home_path = find_cargo_home();
     let result = if home_path.exists().await {
@@ -520,7 +825,8 @@ async fn find_additional_delete_paths(cache_type: CacheType) -> Result<Vec<Path>
 
 fn cached_folder_info_path(cache_type: CacheType) -> Result<Path, Error> {
     let file_name = format!("{}.postcard", cache_type.short_name());
-    Ok(get_action_cache_dir()?.join("cached-folder-info").join(&file_name))
+    let dir_name = format!("cached-folder-info-v{}", fingerprinting::FORMAT_VERSION);
+    Ok(get_action_cache_dir()?.join(&dir_name).join(&file_name))
 }
 
 fn dependency_files_dir() -> Result<Path, Error> {
@@ -539,6 +845,10 @@ enum CacheType {
 
     #[strum(serialize = "git-repos")]
     GitRepos,
+
+    // Only cached alongside crate files when `cache-registry-src` is set
+    #[strum(serialize = "crate-sources")]
+    CrateSources,
 }
 
 impl CacheType {
@@ -552,19 +862,29 @@ impl CacheType {
             CacheType::Indices => "registry indices",
             CacheType::Crates => "crate files",
             CacheType::GitRepos => "Git repositories",
+            CacheType::CrateSources => "crate sources",
         }
         .into()
     }
 
+    fn entry_name(self, role: &str) -> String {
+        format!("{} ({})", self.friendly_name(), role)
+    }
+
     fn relative_path(self) -> Path {
         match self {
             CacheType::Indices => Path::from("registry").join("index"),
             CacheType::Crates => Path::from("registry").join("cache"),
             CacheType::GitRepos => Path::from("git").join("db"),
+            CacheType::CrateSources => Path::from("registry").join("src"),
         }
     }
 
-    fn add_additional_delete_paths(self, match_builder: &mut PathMatchBuilder) -> Result<(), Error> {
+    fn add_additional_delete_paths(
+        self,
+        match_builder: &mut PathMatchBuilder,
+        cache_registry_src: bool,
+    ) -> Result<(), Error> {
         // These are paths we should delete at the same time as restoring the cache and
         // also before saving. This is primarily because we want to see what in
         // the cache is accessed, and leaving derived information about can
@@ -575,11 +895,16 @@ impl CacheType {
                 match_builder.add_pattern("registry/index/*/.cache")?;
             }
             CacheType::Crates => {
-                match_builder.add_pattern("registry/src")?;
+                // Unless it is cached in its own right, `registry/src` is derived from the
+                // crate files
+                if !cache_registry_src {
+                    match_builder.add_pattern("registry/src")?;
+                }
             }
             CacheType::GitRepos => {
                 match_builder.add_pattern("git/checkouts")?;
             }
+            CacheType::CrateSources => {}
         }
         Ok(())
     }
@@ -591,7 +916,7 @@ impl CacheType {
             CacheType::Indices => {
                 ignores.add(1, ".last-updated");
             }
-            CacheType::Crates | CacheType::GitRepos => {}
+            CacheType::Crates | CacheType::GitRepos | CacheType::CrateSources => {}
         }
         ignores
     }
@@ -604,8 +929,8 @@ impl CacheType {
     fn entry_depth(self) -> usize {
         match self {
             CacheType::Indices | CacheType::GitRepos => 1,
-            CacheType::Crates => {
-                // This means we can prune individual crate files within an index
+            CacheType::Crates | CacheType::CrateSources => {
+                // This means we can prune individual crates within an index
                 2
             }
         }
@@ -622,11 +947,56 @@ impl CacheType {
         match self {
             CacheType::Indices => input_manager::Input::MinRecacheIndices,
             CacheType::GitRepos => input_manager::Input::MinRecacheGitRepos,
-            CacheType::Crates => input_manager::Input::MinRecacheCrates,
+            CacheType::Crates | CacheType::CrateSources => input_manager::Input::MinRecacheCrates,
         }
     }
 }
 
+/// Summarises what will be cached so it can be reported before doing any work
+#[derive(Debug)]
+struct CachePlan {
+    min_recache_intervals: Vec<(CacheType, chrono::Duration)>,
+    cross_platform_sharing: CrossPlatformSharing,
+}
+
+impl CachePlan {
+    fn new(
+        input_manager: &input_manager::Manager,
+        cached_types: &[CacheType],
+        cross_platform_sharing: CrossPlatformSharing,
+    ) -> Result<CachePlan, Error> {
+        let min_recache_intervals = cached_types
+            .iter()
+            .map(|&cache_type| Ok((cache_type, get_min_recache_interval(input_manager, cache_type)?)))
+            .collect::<Result<_, Error>>()?;
+        Ok(CachePlan {
+            min_recache_intervals,
+            cross_platform_sharing,
+        })
+    }
+}
+
+impl std::fmt::Display for CachePlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use itertools::Itertools as _;
+
+        let types = self.min_recache_intervals.iter().map(|(cache_type, interval)| {
+            let interval = interval.to_std().unwrap_or_default();
+            format!(
+                "{} (min recache {})",
+                cache_type.friendly_name(),
+                humantime::format_duration(interval)
+            )
+        });
+        write!(
+            f,
+            "Will cache {} with cross-platform sharing {}",
+            types.format(", "),
+            self.cross_platform_sharing
+        )
+    }
+}
+
 fn get_cross_platform_sharing(input_manager: &input_manager::Manager) -> Result<CrossPlatformSharing, Error> {
     Ok(if let Some(value) = input_manager.get(Input::CrossPlatformSharing) {
         CrossPlatformSharing::from_str(value).map_err(|_| Error::ParseCrossPlatformSharing(value.to_string()))?
@@ -635,17 +1005,128 @@ fn get_cross_platform_sharing(input_manager: &input_manager::Manager) -> Result<
     })
 }
 
-fn get_types_to_cache(input_manager: &input_manager::Manager) -> Result<Vec<CacheType>, Error> {
-    let mut result = HashSet::new();
-    if let Some(types) = input_manager.get(Input::CacheOnly) {
-        let types = types.split_whitespace();
-        for cache_type in types {
-            let cache_type =
-                CacheType::from_str(cache_type).map_err(|_| Error::ParseCacheableItem(cache_type.to_string()))?;
-            result.insert(cache_type);
+fn get_allow_cargo_home_change(input_manager: &input_manager::Manager) -> Result<bool, Error> {
+    input_manager.get_bool(Input::AllowCargoHomeChange, false)
+}
+
+fn get_merge_restore(input_manager: &input_manager::Manager) -> Result<bool, Error> {
+    input_manager.get_bool(Input::MergeRestore, false)
+}
+
+fn get_verify_cache(input_manager: &input_manager::Manager) -> Result<bool, Error> {
+    input_manager.get_bool(Input::VerifyCache, false)
+}
+
+fn get_scope_run_attempt(input_manager: &input_manager::Manager) -> Result<bool, Error> {
+    input_manager.get_bool(Input::ScopeRunAttempt, false)
+}
+
+fn get_recache_on_lockfile_change(input_manager: &input_manager::Manager) -> Result<bool, Error> {
+    input_manager.get_bool(Input::RecacheOnLockfileChange, false)
+}
+
+/// The scope of a particular type of cached item. If `lockfile_scope` is
+/// provided, the scope of downloaded crates also depends on it so that they
+/// are not carried over when dependencies change.
+fn scope_for_type(cache_type: CacheType, scope_hash: &HashValue, lockfile_scope: Option<&HashValue>) -> HashValue {
+    match (cache_type, lockfile_scope) {
+        (CacheType::Crates | CacheType::CrateSources, Some(lockfile_scope)) => {
+            let mut hasher = Blake3Hasher::default();
+            scope_hash.hash(&mut hasher);
+            lockfile_scope.hash(&mut hasher);
+            hasher.hash_value()
         }
+        _ => scope_hash.clone(),
+    }
+}
+
+fn get_scope_files(input_manager: &input_manager::Manager) -> Vec<String> {
+    use crate::cargo_lock_hashing::CARGO_LOCK_FILE_NAME;
+
+    let names: Vec<String> = input_manager
+        .get(Input::ScopeFiles)
+        .map(|names| names.split_whitespace().map(String::from).collect())
+        .unwrap_or_default();
+    if names.is_empty() {
+        vec![CARGO_LOCK_FILE_NAME.into()]
+    } else {
+        names
+    }
+}
+
+fn get_scope_strategy(input_manager: &input_manager::Manager) -> Result<ScopeStrategy, Error> {
+    Ok(if let Some(value) = input_manager.get(Input::ScopeStrategy) {
+        ScopeStrategy::from_str(value)
+            .map_err(|_| Error::OptionParseError(Input::ScopeStrategy.to_string(), value.to_string()))?
+    } else {
+        ScopeStrategy::Lockfile
+    })
+}
+
+fn get_cache_registry_src(input_manager: &input_manager::Manager) -> Result<bool, Error> {
+    input_manager.get_bool(Input::CacheRegistrySrc, false)
+}
+
+fn parse_cache_types(
+    input_manager: &input_manager::Manager,
+    input: Input,
+) -> Result<Option<HashSet<CacheType>>, Error> {
+    input_manager
+        .get(input)
+        .map(|types| parse_cache_type_list(input, types))
+        .transpose()
+}
+
+/// Parses a list of cache types. If every item is prefixed with `!`, the
+/// result is all cacheable types except those listed.
+fn parse_cache_type_list(input: Input, types: &str) -> Result<HashSet<CacheType>, Error> {
+    let mut included = HashSet::new();
+    let mut excluded = HashSet::new();
+    for item in types.split_whitespace() {
+        let (name, set) = match item.strip_prefix('!') {
+            Some(name) => (name, &mut excluded),
+            None => (item, &mut included),
+        };
+        let cache_type = CacheType::from_str(name)
+            .ok()
+            .filter(|t| *t != CacheType::CrateSources)
+            .ok_or_else(|| Error::ParseCacheableItem(item.to_string()))?;
+        set.insert(cache_type);
+    }
+    if excluded.is_empty() {
+        Ok(included)
+    } else if included.is_empty() {
+        Ok(CacheType::iter()
+            .filter(|t| *t != CacheType::CrateSources && !excluded.contains(t))
+            .collect())
     } else {
-        result.extend(CacheType::iter());
+        Err(Error::MixedCacheableItems(input.to_string(), types.to_string()))
+    }
+}
+
+/// Determines the types selected by `cache-only`, further restricted by each
+/// of `restrictions`
+fn get_types_to_cache(
+    input_manager: &input_manager::Manager,
+    cache_registry_src: bool,
+    restrictions: &[Input],
+) -> Result<Vec<CacheType>, Error> {
+    let mut result = parse_cache_types(input_manager, Input::CacheOnly)?
+        .unwrap_or_else(|| CacheType::iter().filter(|t| *t != CacheType::CrateSources).collect());
+    for &input in restrictions {
+        if let Some(selected) = parse_cache_types(input_manager, input)? {
+            for cache_type in selected.difference(&result) {
+                warning!(
+                    "Ignoring {} in {} since it is not otherwise selected for caching",
+                    cache_type,
+                    input
+                );
+            }
+            result.retain(|t| selected.contains(t));
+        }
+    }
+    if cache_registry_src && result.contains(&CacheType::Crates) {
+        result.insert(CacheType::CrateSources);
     }
     Ok(result.into_iter().collect())
 }
@@ -654,7 +1135,11 @@ fn get_min_recache_interval(
     input_manager: &input_manager::Manager,
     cache_type: CacheType,
 ) -> Result<chrono::Duration, Error> {
-    let result = if let Some(duration) = input_manager.get(cache_type.min_recache_input()) {
+    // A per-type interval takes precedence over the global one
+    let duration = input_manager
+        .get(cache_type.min_recache_input())
+        .or_else(|| input_manager.get(Input::MinRecache));
+    let result = if let Some(duration) = duration {
         let duration = humantime::parse_duration(duration)?;
         chrono::Duration::from_std(duration)?
     } else {
@@ -681,7 +1166,7 @@ fn dependency_file_path(cache_type: CacheType, scope: &HashValue, job: &Job) ->
 
 fn build_cache_entry_dependencies(cache_type: CacheType, scope: &HashValue, job: &Job) -> Result<CacheEntry, Error> {
     use crate::cache_key_builder::{Attribute, CacheKeyBuilder};
-    let name = format!("{} (dependencies)", cache_type.friendly_name());
+    let name = cache_type.entry_name(DEPENDENCIES_ENTRY_ROLE);
     let mut key_builder = CacheKeyBuilder::new(&name);
     key_builder.add_key_data(scope);
     key_builder.set_key_attribute(Attribute::Workflow, job.get_workflow().to_string());
@@ -689,6 +1174,9 @@ fn build_cache_entry_dependencies(cache_type: CacheType, scope: &HashValue, job:
     if let Some(properties) = job.matrix_properties_as_string() {
         key_builder.set_key_attribute(Attribute::Matrix, properties);
     }
+    if let Some(run_id) = job.get_run_id() {
+        key_builder.set_attribute(Attribute::RunId, run_id.to_string());
+    }
     let mut cache_entry = key_builder.into_entry();
     cache_entry.root(node::os::homedir());
     let path = dependency_file_path(cache_type, scope, job)?;
@@ -696,9 +1184,69 @@ fn build_cache_entry_dependencies(cache_type: CacheType, scope: &HashValue, job:
     Ok(cache_entry)
 }
 
+fn scope_to_run_attempt(scope_hash: HashValue) -> Result<HashValue, Error> {
+    let job = Job::from_env()?;
+    Ok(if let Some(run_attempt) = job.get_run_attempt() {
+        info!("Cached items will be scoped to run attempt {}", run_attempt);
+        let mut hasher = Blake3Hasher::default();
+        scope_hash.hash(&mut hasher);
+        run_attempt.hash(&mut hasher);
+        hasher.hash_value()
+    } else {
+        warning!("Unable to determine the run attempt of this job, cached items will not be scoped to it");
+        scope_hash
+    })
+}
+
+/// Computes a hash of the project's dependencies, used to scope cached items
+/// when access times cannot be used for pruning
+async fn hash_project_dependencies(scope_strategy: ScopeStrategy, scope_files: &[String]) -> Result<HashValue, Error> {
+    use crate::cargo_lock_hashing::{hash_dependency_graph, hash_files_named};
+
+    let cwd = node::process::cwd();
+    let graph_hash = match scope_strategy {
+        ScopeStrategy::Lockfile => None,
+        ScopeStrategy::Metadata => match hash_dependency_graph(&cwd).await {
+            Ok(hash) => {
+                info!("Hashed {} resolved dependencies from cargo metadata", hash.num_files);
+                Some(hash)
+            }
+            Err(e) => {
+                warning!(
+                    "Unable to hash dependency graph using cargo metadata, falling back to hashing scope files: {}",
+                    e
+                );
+                None
+            }
+        },
+    };
+    let lock_hash = if let Some(graph_hash) = graph_hash {
+        graph_hash
+    } else {
+        let hash = hash_files_named(&cwd, scope_files)
+            .await
+            .context("while hashing scope files")?;
+        info!("Hashed {} files named {}", hash.num_files, scope_files.join(", "));
+        hash
+    };
+    Ok(HashValue::from_bytes(&lock_hash.bytes))
+}
+
 pub async fn restore_cargo_cache(input_manager: &input_manager::Manager) -> Result<(), Error> {
     use crate::access_times::supports_atime;
-    use crate::cargo_lock_hashing::hash_cargo_lock_files;
+    use crate::cargo_lock_hashing::ensure_cargo_lock_present;
+
+    let require_lockfile = input_manager.get_bool(Input::RequireLockfile, false)?;
+    if require_lockfile {
+        ensure_cargo_lock_present(&node::process::cwd()).await?;
+    }
+    // Fail now rather than after spending time downloading items we cannot write
+    let cargo_home = find_cargo_home();
+    if cargo_home.exists().await && !node::fs::access(&cargo_home, node::fs::W_OK).await {
+        return Err(Error::PathNotWritable(cargo_home.to_string()));
+    }
+    let scope_strategy = get_scope_strategy(input_manager)?;
+    let scope_files = get_scope_files(input_manager);
 
     info!("Checking to see if filesystem supports access times...");
     let atimes_supported = supports_atime().await?;
@@ -723,86 +1271,351 @@ pub async fn restore_cargo_cache(input_manager: &input_manager::Manager) -> Resu
         // doesn't play well with `save_state`.
         HashValue::from_bytes(&[42u8])
     } else {
-        let cwd = node::process::cwd();
-        let lock_hash = hash_cargo_lock_files(&cwd).await?;
-        HashValue::from_bytes(&lock_hash.bytes)
+        hash_project_dependencies(scope_strategy, &scope_files).await?
+    };
+    let scope_hash = if get_scope_run_attempt(input_manager)? {
+        scope_to_run_attempt(scope_hash)?
+    } else {
+        scope_hash
     };
     core::save_state(SCOPE_HASH_KEY, safe_encoding::encode(&scope_hash));
+    // Without access times, every item is already scoped to the dependencies
+    let lockfile_scope = if atimes_supported && get_recache_on_lockfile_change(input_manager)? {
+        info!("Cached crates will be scoped to the project's dependencies");
+        let lockfile_scope = hash_project_dependencies(scope_strategy, &scope_files).await?;
+        core::save_state(LOCKFILE_SCOPE_HASH_KEY, safe_encoding::encode(&lockfile_scope));
+        Some(lockfile_scope)
+    } else {
+        None
+    };
 
     let cross_platform_sharing = get_cross_platform_sharing(input_manager)?;
-    let cached_types = get_types_to_cache(input_manager)?;
+    // Mark as used to avoid spurious warnings (we only use this when we save)
+    let _ = get_allow_cargo_home_change(input_manager)?;
+    let verify = get_verify_cache(input_manager)?;
+    let merge = get_merge_restore(input_manager)?;
+    let cache_registry_src = get_cache_registry_src(input_manager)?;
+    let cached_types = get_types_to_cache(input_manager, cache_registry_src, &[Input::RestoreOnly])?;
+    // Validate this now and mark it as used to avoid spurious warnings (we only use
+    // it when we save)
+    let _ = parse_cache_types(input_manager, Input::SaveOnly)?;
+    // This also marks the min-recache inputs as used to avoid spurious warnings (we
+    // only use them when we save the entries)
+    info!(
+        "{}",
+        CachePlan::new(input_manager, &cached_types, cross_platform_sharing)?
+    );
+    // Only a restore of every item's dependency list counts as a hit
+    let mut cache_hit = !cached_types.is_empty();
     for cache_type in cached_types {
-        core::start_group(cache_type.friendly_name().to_string());
-        // Mark as used to avoid spurious warnings (we only use this when we save the
-        // entries)
-        let _ = get_min_recache_interval(input_manager, cache_type)?;
+        let _group = core::Group::new(cache_type.friendly_name().to_string());
 
         // Build the cache
-        let cache = Cache::restore_from_env(cache_type, &scope_hash, cross_platform_sharing).await?;
+        let (cache, dependencies_found) = Cache::restore_from_env(
+            cache_type,
+            &scope_for_type(cache_type, &scope_hash, lockfile_scope.as_ref()),
+            cross_platform_sharing,
+            verify,
+            cache_registry_src,
+            merge,
+        )
+        .await
+        .with_context(|| format!("while restoring {} cache", cache_type.friendly_name()))?;
+        cache_hit &= dependencies_found;
         let serialized_cache = postcard::to_stdvec(&cache)?;
         let cached_info_path = cached_folder_info_path(cache_type)?;
         {
             let parent = cached_info_path.parent();
             node::fs::create_dir_all(&parent).await?;
         }
-        node::fs::write_file(&cached_info_path, &serialized_cache).await?;
-        core::end_group();
+        node::fs::write_file(&cached_info_path, &serialized_cache)
+            .await
+            .context("while writing cached folder info")?;
     }
+    core::set_output(CACHE_HIT_OUTPUT, cache_hit.to_string());
     Ok(())
 }
 
-pub async fn save_cargo_cache(input_manager: &input_manager::Manager) -> Result<(), Error> {
-    let scope_hash = core::get_state(SCOPE_HASH_KEY).expect("Failed to find scope ID hash");
-    let scope_hash = safe_encoding::decode(&scope_hash).expect("Failed to decode scope ID hash");
-    let scope_hash = HashValue::from_bytes(&scope_hash);
+pub async fn reset_cargo_cache(input_manager: &input_manager::Manager) -> Result<(), Error> {
+    let cache_registry_src = get_cache_registry_src(input_manager)?;
+    let cached_types = get_types_to_cache(input_manager, cache_registry_src, &[])?;
+    for &cache_type in &cached_types {
+        let folder_path = find_path(cache_type);
+        info!("Deleting {} at {}", cache_type.friendly_name(), folder_path);
+        actions::io::rm_rf(&folder_path)
+            .await
+            .with_context(|| format!("while deleting {}", folder_path))?;
+        let cached_info_path = cached_folder_info_path(cache_type)?;
+        actions::io::rm_rf(&cached_info_path)
+            .await
+            .context("while deleting cached folder info")?;
+    }
+    // Dependency lists are named using the scope hash, so we cannot select those of
+    // particular cache types
+    let dependency_dir = dependency_files_dir()?;
+    actions::io::rm_rf(&dependency_dir)
+        .await
+        .context("while deleting dependency lists")?;
+    if let Some(token) = input_manager.get(Input::GithubToken) {
+        delete_remote_entries(&cached_types, token).await?;
+    } else {
+        warning!(concat!(
+            "Local cache state was reset but no github-token was supplied so entries stored in the cache service ",
+            "were not deleted. They can be deleted from the repository's Actions cache management page or with the ",
+            "GitHub CLI."
+        ));
+    }
+    Ok(())
+}
+
+async fn delete_remote_entries(cache_types: &[CacheType], token: &str) -> Result<(), Error> {
+    use crate::cache_key_builder::CacheKeyBuilder;
+
+    for cache_type in cache_types {
+        let mut num_deleted = 0;
+        for role in [CONTENT_ENTRY_ROLE, DEPENDENCIES_ENTRY_ROLE] {
+            let prefix = CacheKeyBuilder::name_prefix(&cache_type.entry_name(role));
+            let mut entry = CacheEntry::new(prefix.as_str());
+            entry.restore_key(prefix);
+            num_deleted += entry
+                .delete(token)
+                .await
+                .map_err(Error::Js)
+                .with_context(|| format!("while deleting cached {}", cache_type.friendly_name()))?;
+        }
+        info!(
+            "Deleted {} entries for {} from the cache service",
+            num_deleted,
+            cache_type.friendly_name()
+        );
+    }
+    Ok(())
+}
+
+/// Retrieves a scope hash saved under `key` when the cache was restored.
+/// Returns `None` if it is missing or cannot be decoded.
+fn get_saved_scope_hash(key: &str) -> Option<HashValue> {
+    let scope_hash = core::get_state(key)?;
+    match safe_encoding::decode(&scope_hash) {
+        Ok(scope_hash) => Some(HashValue::from_bytes(&scope_hash)),
+        Err(e) => {
+            warning!("Saved scope ID hash appears to be corrupted: {}", e);
+            None
+        }
+    }
+}
+
+fn get_saved_atimes_supported() -> Option<bool> {
+    let atimes_supported = core::get_state(ATIMES_SUPPORTED_KEY)?;
+    match serde_json::de::from_str(&atimes_supported) {
+        Ok(atimes_supported) => Some(atimes_supported),
+        Err(e) => {
+            warning!("Saved access times support flag appears to be corrupted: {}", e);
+            None
+        }
+    }
+}
+
+/// Reads what was restored for `cache_type`. If this cannot be decoded, the
+/// type is not saved rather than failing the whole save.
+async fn read_cached_folder_info(cache_type: CacheType) -> Result<Option<Cache>, Error> {
+    let cached_info_path = cached_folder_info_path(cache_type)?;
+    let cache_serialized = node::fs::read_file(&cached_info_path)
+        .await
+        .context("while reading cached folder info")?;
+    match postcard::from_bytes(&cache_serialized) {
+        Ok(cache) => Ok(Some(cache)),
+        Err(e) => {
+            warning!(
+                "Cached folder info for {} appears to be corrupted, so it will not be saved: {}",
+                cache_type.friendly_name(),
+                e
+            );
+            Ok(None)
+        }
+    }
+}
 
-    let atimes_supported = core::get_state(ATIMES_SUPPORTED_KEY).expect("Failed to find access times support flag");
-    let atimes_supported: bool = serde_json::de::from_str(&atimes_supported)?;
+pub async fn save_cargo_cache(input_manager: &input_manager::Manager) -> Result<(), Error> {
+    use crate::lock_file::LockFile;
+    use std::time::Duration;
+
+    let saved_state = get_saved_scope_hash(SCOPE_HASH_KEY).zip(get_saved_atimes_supported());
+    if let Some((scope_hash, atimes_supported)) = saved_state {
+        // Concurrent saves from the same runner would mostly find that another had
+        // already saved the same entries, so we save one at a time
+        let lock_path = get_action_cache_dir()?.join(SAVE_LOCK_FILE_NAME);
+        let lock = LockFile::acquire(
+            &lock_path,
+            Duration::from_secs(SAVE_LOCK_TIMEOUT_SECS),
+            Duration::from_secs(SAVE_LOCK_STALE_AFTER_SECS),
+        )
+        .await?;
+        if lock.is_none() {
+            warning!("Timed out waiting for lock {}. Saving regardless.", lock_path);
+        }
+        let lockfile_scope = get_saved_scope_hash(LOCKFILE_SCOPE_HASH_KEY);
+        let result =
+            save_cargo_cache_in_scope(input_manager, &scope_hash, lockfile_scope.as_ref(), atimes_supported).await;
+        if let Some(lock) = lock {
+            lock.release().await;
+        }
+        result
+    } else {
+        // Without the scope we cannot tell which entries were restored, so the next
+        // run will start from whatever it finds in the cache
+        warning!("Unable to find the state saved when the cache was restored, so nothing will be saved");
+        Ok(())
+    }
+}
 
+async fn save_cargo_cache_in_scope(
+    input_manager: &input_manager::Manager,
+    scope_hash: &HashValue,
+    lockfile_scope: Option<&HashValue>,
+    atimes_supported: bool,
+) -> Result<(), Error> {
     let cross_platform_sharing = get_cross_platform_sharing(input_manager)?;
-    let cached_types = get_types_to_cache(input_manager)?;
+    let allow_cargo_home_change = get_allow_cargo_home_change(input_manager)?;
+    let cache_registry_src = get_cache_registry_src(input_manager)?;
+    // Only restored types have the information needed to save them
+    let cached_types = get_types_to_cache(
+        input_manager,
+        cache_registry_src,
+        &[Input::RestoreOnly, Input::SaveOnly],
+    )?;
     for cache_type in cached_types {
-        core::start_group(cache_type.friendly_name().to_string());
+        let _group = core::Group::new(cache_type.friendly_name().to_string());
         // Delete items that should never make it into the cache
-        for delete_path in find_additional_delete_paths(cache_type).await? {
+        for delete_path in find_additional_delete_paths(cache_type, cache_registry_src).await? {
             if delete_path.exists().await {
                 info!("Pruning redundant cache element: {}", delete_path);
-                actions::io::rm_rf(&delete_path).await?;
+                actions::io::rm_rf(&delete_path)
+                    .await
+                    .with_context(|| format!("while pruning {}", delete_path))?;
             }
         }
 
         // Restore the old cache
-        let cache_old: Cache = {
-            let cached_info_path = cached_folder_info_path(cache_type)?;
-            let cache_serialized = node::fs::read_file(&cached_info_path).await?;
-            postcard::from_bytes(&cache_serialized)?
+        let Some(cache_old) = read_cached_folder_info(cache_type).await? else {
+            continue;
         };
 
         // Construct the new cache
-        let mut cache = Cache::new(cache_type).await?;
+        let mut cache = Cache::new(cache_type, cache_registry_src)
+            .await
+            .with_context(|| format!("while scanning {} cache", cache_type.friendly_name()))?;
 
         // Check the path to the cached items hasn't changed
-        if cache.get_root_path() != cache_old.get_root_path() {
-            use wasm_bindgen::JsError;
-            let error = JsError::new(&format!(
-                "Path to cache changed from {} to {}. Perhaps CARGO_HOME changed?",
-                cache_old.get_root_path(),
-                cache.get_root_path()
-            ));
-            return Err(Error::Js(error.into()));
+        let root_path_changed = cache.get_root_path() != cache_old.get_root_path();
+        if root_path_changed {
+            if allow_cargo_home_change {
+                notice!(
+                    concat!(
+                        "Path to cache changed from {} to {}. ",
+                        "Unused items will not be pruned since access times at the new location cannot be trusted, ",
+                        "so the saved {} cache may contain entries that are no longer needed."
+                    ),
+                    cache_old.get_root_path(),
+                    cache.get_root_path(),
+                    cache_type.friendly_name()
+                );
+            } else {
+                use wasm_bindgen::JsError;
+                let error = JsError::new(&format!(
+                    "Path to cache changed from {} to {}. Perhaps CARGO_HOME changed?",
+                    cache_old.get_root_path(),
+                    cache.get_root_path()
+                ));
+                return Err(Error::Js(error.into()));
+            }
         }
 
         // Prune unused items (if we have access time suppport)
-        if atimes_supported {
-            cache.prune_unused(&cache_old).await?;
+        if atimes_supported && !root_path_changed {
+            let stats = cache
+                .prune_unused(&cache_old)
+                .await
+                .with_context(|| format!("while pruning unused items from {} cache", cache_type.friendly_name()))?;
+            info!(
+                "Pruned {} unused {} cache entries, freeing approximately {:.1} MiB (restored {:.1} MiB, now {:.1} MiB).",
+                stats.num_entries,
+                cache_type.friendly_name(),
+                bytes_to_mib(stats.num_bytes),
+                bytes_to_mib(cache_old.size()),
+                bytes_to_mib(cache.size())
+            );
         }
 
         // Save groups to cache if they have changed
         let min_recache_interval = get_min_recache_interval(input_manager, cache_type)?;
         cache
-            .save_changes(&cache_old, &scope_hash, &min_recache_interval, cross_platform_sharing)
-            .await?;
-        core::end_group();
+            .save_changes(
+                &cache_old,
+                &scope_for_type(cache_type, scope_hash, lockfile_scope),
+                &min_recache_interval,
+                cross_platform_sharing,
+            )
+            .await
+            .with_context(|| format!("while saving {} cache", cache_type.friendly_name()))?;
+    }
+
+    let oversize_keys = actions::cache::oversize_skipped_keys();
+    if !oversize_keys.is_empty() {
+        warning!(
+            "{} cache entries were too large to save: {}. Consider caching fewer items using `cache-only`.",
+            oversize_keys.len(),
+            oversize_keys.join(", ")
+        );
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::{parse_cache_type_list, CacheType, RecacheDecision};
+    use crate::input_manager::Input;
+    use crate::Error;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn cache_types_can_be_excluded() -> Result<(), Error> {
+        use std::collections::HashSet;
+
+        let parse = |types| parse_cache_type_list(Input::CacheOnly, types);
+        assert_eq!(
+            parse("indices crates")?,
+            HashSet::from([CacheType::Indices, CacheType::Crates])
+        );
+        assert_eq!(
+            parse("!git-repos")?,
+            HashSet::from([CacheType::Indices, CacheType::Crates])
+        );
+        assert_eq!(parse("!git-repos !crates")?, HashSet::from([CacheType::Indices]));
+        assert!(matches!(
+            parse("indices !crates"),
+            Err(Error::MixedCacheableItems(_, _))
+        ));
+        assert!(matches!(parse("!crate-sources"), Err(Error::ParseCacheableItem(_))));
+        assert!(matches!(parse("!"), Err(Error::ParseCacheableItem(_))));
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    fn recache_decisions_are_described() {
+        use humantime::parse_duration;
+
+        let within = RecacheDecision::WithinInterval {
+            outdated_by: parse_duration("30m").expect("Failed to parse duration"),
+            interval: parse_duration("2h").expect("Failed to parse duration"),
+        };
+        assert_eq!(RecacheDecision::New.to_string(), "WILL SAVE (new)");
+        assert_eq!(
+            within.to_string(),
+            "SKIPPED (outdated by 30m, within 2h recache window)"
+        );
+        assert_eq!(RecacheDecision::Unchanged.to_string(), "SKIPPED (unchanged)");
+        assert!(RecacheDecision::Changed.should_save());
+        assert!(!within.should_save());
+    }
+}
@@ -1,14 +1,44 @@
 use crate::actions::cache::Entry as CacheEntry;
+use crate::actions::core;
 use crate::hasher::Blake3 as Blake3Hasher;
-use crate::{node, safe_encoding};
-use std::collections::BTreeMap;
+use crate::{debug, node, safe_encoding};
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, BTreeSet};
 
 const CACHE_ENTRY_VERSION: &str = "17";
 
+static TIMESTAMP_FORMAT: Mutex<TimestampFormat> = parking_lot::const_mutex(TimestampFormat::Rfc3339);
+
+#[derive(Clone, Copy, Debug, strum::EnumString)]
+pub enum TimestampFormat {
+    #[strum(serialize = "rfc3339")]
+    Rfc3339,
+
+    #[strum(serialize = "none")]
+    None,
+}
+
+impl TimestampFormat {
+    fn format(self, time: &DateTime<Utc>) -> Option<String> {
+        match self {
+            TimestampFormat::Rfc3339 => Some(time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+            TimestampFormat::None => None,
+        }
+    }
+}
+
+/// Sets how the timestamp attribute is rendered in all subsequently built keys
+pub fn set_timestamp_format(format: TimestampFormat) {
+    *TIMESTAMP_FORMAT.lock() = format;
+}
+
 pub struct CacheKeyBuilder {
     name: String,
     hasher: Blake3Hasher,
     attributes: BTreeMap<&'static str, (String, bool)>,
+    sensitive: BTreeSet<&'static str>,
+    key_data: Vec<String>,
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, strum::Display, strum::IntoStaticStr, Ord, PartialEq, PartialOrd)]
@@ -40,6 +70,12 @@ pub enum Attribute {
     #[strum(serialize = "path")]
     Path,
 
+    #[strum(serialize = "compiler_flags")]
+    CompilerFlags,
+
+    #[strum(serialize = "run_id")]
+    RunId,
+
     #[strum(serialize = "date")]
     Timestamp,
 
@@ -53,12 +89,26 @@ pub enum Attribute {
     EntriesHash,
 }
 
+impl Attribute {
+    fn is_sensitive(self) -> bool {
+        // Matrix properties, command-line arguments and compiler flags are
+        // user-supplied and could conceivably contain credentials, so we never
+        // log them
+        matches!(
+            self,
+            Attribute::Matrix | Attribute::ArgsTruncated | Attribute::CompilerFlags
+        )
+    }
+}
+
 impl CacheKeyBuilder {
     fn empty(name: &str) -> CacheKeyBuilder {
         let mut result = CacheKeyBuilder {
             name: name.into(),
             hasher: Blake3Hasher::default(),
             attributes: BTreeMap::new(),
+            sensitive: BTreeSet::new(),
+            key_data: Vec::new(),
         };
         result.add_key_data(CACHE_ENTRY_VERSION);
         result
@@ -69,24 +119,54 @@ impl CacheKeyBuilder {
 
         let mut result = Self::empty(name);
         result.set_key_attribute(Attribute::Platform, node::os::platform());
-        let date = chrono::Local::now();
-        result.set_attribute(Attribute::Timestamp, date.to_string());
+        // We use UTC so that keys do not depend on the runner's timezone
+        if let Some(date) = TIMESTAMP_FORMAT.lock().format(&Utc::now()) {
+            result.set_attribute(Attribute::Timestamp, date);
+        }
         let nonce = nonce::build(8);
         let nonce = safe_encoding::encode(nonce);
         result.set_attribute(Attribute::Nonce, nonce);
         result
     }
 
-    pub fn add_key_data<T: std::hash::Hash + ?Sized>(&mut self, data: &T) {
+    pub fn add_key_data<T: std::fmt::Debug + std::hash::Hash + ?Sized>(&mut self, data: &T) {
         data.hash(&mut self.hasher);
+        if core::is_debug() {
+            self.key_data.push(format!("{:?}", data));
+        }
     }
 
     pub fn set_key_attribute(&mut self, key: Attribute, value: String) {
-        self.attributes.insert(key.into(), (value, true));
+        self.insert_attribute(key, value, true);
     }
 
     pub fn set_attribute(&mut self, name: Attribute, value: String) {
-        self.attributes.insert(name.into(), (value, false));
+        self.insert_attribute(name, value, false);
+    }
+
+    fn insert_attribute(&mut self, attribute: Attribute, value: String, is_key: bool) {
+        let name = attribute.into();
+        if attribute.is_sensitive() {
+            self.sensitive.insert(name);
+        }
+        self.attributes.insert(name, (value, is_key));
+    }
+
+    fn log_key_inputs(&self, restore_key: &str) {
+        // This is purely diagnostic and must not influence the key itself
+        debug!("Inputs for cache key {}:", restore_key);
+        for data in &self.key_data {
+            debug!("  key data: {}", data);
+        }
+        for (name, (value, is_key)) in &self.attributes {
+            let value = if self.sensitive.contains(name) {
+                "<redacted>"
+            } else {
+                value.as_str()
+            };
+            let role = if *is_key { "key" } else { "informational" };
+            debug!("  attribute ({}): {}={}", role, name, value);
+        }
     }
 
     fn restore_key_to_save_key(restore_key: &str, attributes: &BTreeMap<&str, (String, bool)>) -> String {
@@ -120,11 +200,21 @@ impl CacheKeyBuilder {
             safe_encoding::encode(id)
         };
 
-        let restore_key = format!("Ferrous Actions: {} - id={}", name, id);
+        let restore_key = format!("{}id={}", Self::name_prefix(name), id);
         restore_key.replace(',', ";")
     }
 
+    /// The prefix shared by the keys of all entries built with `name`
+    pub fn name_prefix(name: &str) -> String {
+        let prefix = format!("Ferrous Actions: {} - ", name);
+        prefix.replace(',', ";")
+    }
+
     pub fn into_entry(self) -> CacheEntry {
+        if core::is_debug() {
+            let restore_key = Self::build_restore_key(&self.name, self.hasher.clone(), &self.attributes);
+            self.log_key_inputs(&restore_key);
+        }
         let restore_key = Self::build_restore_key(&self.name, self.hasher, &self.attributes);
         let save_key = Self::restore_key_to_save_key(&restore_key, &self.attributes);
         let mut result = CacheEntry::new(save_key.as_str());
@@ -135,3 +225,20 @@ impl CacheKeyBuilder {
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::TimestampFormat;
+    use chrono::TimeZone as _;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn timestamp_formatting() {
+        let time = chrono::Utc.with_ymd_and_hms(2023, 4, 5, 6, 7, 8).unwrap();
+        assert_eq!(
+            TimestampFormat::Rfc3339.format(&time).as_deref(),
+            Some("2023-04-05T06:07:08Z")
+        );
+        assert_eq!(TimestampFormat::None.format(&time), None);
+    }
+}
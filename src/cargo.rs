@@ -1,21 +1,22 @@
 use crate::action_paths::get_action_cache_dir;
 use crate::actions::exec::Command;
 use crate::actions::io;
+use crate::cargo_alias::Aliases;
 use crate::cargo_hooks::{
-    Annotation as AnnotationHook, Composite as CompositeHook, Hook as CargoHook, Install as CargoInstallHook,
+    Annotation as AnnotationHook, Artifacts as ArtifactsHook, Composite as CompositeHook, Hook as CargoHook,
+    Install as CargoInstallHook, TestBinaries as TestBinariesHook, Timings as TimingsHook,
 };
+use crate::cargo_lock_hashing::ensure_cargo_lock_present;
 use crate::input_manager::{self, Input};
 use crate::node::path::Path;
 use crate::node::process;
-use crate::{node, nonce, Error};
+use crate::{node, warning, Error};
 use std::borrow::Cow;
 
 async fn create_empty_dir() -> Result<Path, Error> {
-    let nonce = nonce::build(8);
-    let path = get_action_cache_dir()?
-        .join("empty-directories")
-        .join(&nonce.to_string());
-    node::fs::create_dir_all(&path).await?;
+    let parent = get_action_cache_dir()?.join("empty-directories");
+    node::fs::create_dir_all(&parent).await?;
+    let path = node::fs::mkdtemp(&parent.join("empty-")).await?;
     Ok(path)
 }
 
@@ -33,6 +34,58 @@ impl CargoHook for ChangeCwdHook {
 #[derive(Clone, Debug)]
 pub struct Cargo {
     path: Path,
+    global_args: Vec<String>,
+    toolchain_version: Option<(Option<String>, ToolchainVersion)>,
+}
+
+/// Flags passed to the compiler and rustdoc via `RUSTFLAGS` and
+/// `RUSTDOCFLAGS`
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct CompilerFlags {
+    rustflags: Option<String>,
+    rustdocflags: Option<String>,
+}
+
+impl CompilerFlags {
+    const RUSTDOCFLAGS_VAR: &'static str = "RUSTDOCFLAGS";
+    const RUSTFLAGS_VAR: &'static str = "RUSTFLAGS";
+
+    /// Uses the flags from the action inputs, falling back to those already
+    /// in the environment so that the effective flags are always known
+    pub fn from_inputs(input_manager: &input_manager::Manager) -> CompilerFlags {
+        let get = |input, var| {
+            input_manager
+                .get(input)
+                .map(String::from)
+                .or_else(|| process::get_env().remove(var))
+        };
+        CompilerFlags {
+            rustflags: get(Input::Rustflags, Self::RUSTFLAGS_VAR),
+            rustdocflags: get(Input::Rustdocflags, Self::RUSTDOCFLAGS_VAR),
+        }
+    }
+
+    pub fn apply(&self, command: &mut Command) {
+        if let Some(rustflags) = &self.rustflags {
+            command.env(Self::RUSTFLAGS_VAR, rustflags.as_str());
+        }
+        if let Some(rustdocflags) = &self.rustdocflags {
+            command.env(Self::RUSTDOCFLAGS_VAR, rustdocflags.as_str());
+        }
+    }
+
+    /// A human-readable summary for use in cache key attributes
+    pub fn describe(&self) -> String {
+        use itertools::Itertools as _;
+
+        [
+            (Self::RUSTFLAGS_VAR, &self.rustflags),
+            (Self::RUSTDOCFLAGS_VAR, &self.rustdocflags),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.as_ref().map(|v| format!("{}={}", name, v)))
+        .join(" ")
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -41,6 +94,10 @@ pub struct ToolchainVersion {
 }
 
 impl ToolchainVersion {
+    pub fn new<S: Into<String>>(long: S) -> ToolchainVersion {
+        ToolchainVersion { long: long.into() }
+    }
+
     pub fn short(&self) -> Cow<str> {
         self.long.lines().next().unwrap_or_default().trim().into()
     }
@@ -50,11 +107,44 @@ impl ToolchainVersion {
     }
 }
 
+pub async fn get_toolchain_version(toolchain: Option<&str>, cwd: Option<&Path>) -> Result<ToolchainVersion, Error> {
+    let rustc_path = io::which("rustc", true).await.map_err(Error::Js)?;
+    let mut command = Command::from(&rustc_path);
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+    query_toolchain_version(command, toolchain).await
+}
+
+/// Gets the version of a toolchain just installed to `bin_dir`, which need not
+/// be on the `PATH` of this process
+pub async fn get_installed_toolchain_version(
+    bin_dir: &Path,
+    toolchain: Option<&str>,
+) -> Result<ToolchainVersion, Error> {
+    let mut command = Command::from(&bin_dir.join("rustc"));
+    command.prepend_path(bin_dir);
+    query_toolchain_version(command, toolchain).await
+}
+
+async fn query_toolchain_version(mut command: Command, toolchain: Option<&str>) -> Result<ToolchainVersion, Error> {
+    if let Some(toolchain) = toolchain {
+        command.arg(format!("+{}", toolchain).as_str());
+    }
+    command.arg("-Vv");
+    let long = command.output().await?;
+    Ok(ToolchainVersion::new(long.trim()))
+}
+
 impl Cargo {
     pub async fn from_environment() -> Result<Cargo, Error> {
         io::which("cargo", true)
             .await
-            .map(|path| Cargo { path })
+            .map(|path| Cargo {
+                path,
+                global_args: Vec::new(),
+                toolchain_version: None,
+            })
             .map_err(Error::Js)
     }
 
@@ -63,10 +153,58 @@ impl Cargo {
         if !full_path.exists().await {
             return Err(Error::PathDoesNotExist(full_path.to_string()));
         }
-        let result = Cargo { path: full_path };
+        let result = Cargo {
+            path: full_path,
+            global_args: Vec::new(),
+            toolchain_version: None,
+        };
         Ok(result)
     }
 
+    /// Adds arguments to be passed before the subcommand, such as `-Z` flags
+    pub fn global_args<I, S>(&mut self, args: I) -> &mut Cargo
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.global_args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// The version of the toolchain Cargo will use, which is only determined
+    /// once for a given toolchain
+    pub async fn toolchain_version(&mut self, toolchain: Option<&str>) -> Result<ToolchainVersion, Error> {
+        match &self.toolchain_version {
+            Some((cached_toolchain, version)) if cached_toolchain.as_deref() == toolchain => Ok(version.clone()),
+            _ => {
+                let version = get_toolchain_version(toolchain, None).await?;
+                self.toolchain_version = Some((toolchain.map(String::from), version.clone()));
+                Ok(version)
+            }
+        }
+    }
+
+    /// Assembles the full argument list. The user's arguments are passed
+    /// through unchanged and in order after any options we add, so that
+    /// anything following `--` is unaffected.
+    fn assemble_args(
+        toolchain: Option<&str>,
+        global_args: &[String],
+        subcommand: &str,
+        options: Vec<String>,
+        args: Vec<String>,
+    ) -> Vec<String> {
+        let mut result = Vec::with_capacity(global_args.len() + options.len() + args.len() + 2);
+        if let Some(toolchain) = toolchain {
+            result.push(format!("+{}", toolchain));
+        }
+        result.extend(global_args.iter().cloned());
+        result.push(subcommand.into());
+        result.extend(options);
+        result.extend(args);
+        result
+    }
+
     pub async fn get_installed(&self) -> Result<Vec<String>, Error> {
         use parking_lot::Mutex;
         use std::sync::Arc;
@@ -87,44 +225,73 @@ impl Cargo {
                     installs_captured.lock().push(name.to_string());
                 }
             })
-            .exec()
-            .await
-            .map_err(Error::Js)?;
+            .exec_checked()
+            .await?;
         let installs = installs.lock().drain(..).collect();
         Ok(installs)
     }
 
     async fn get_hooks_for_subcommand(
-        &self,
+        &mut self,
         toolchain: Option<&str>,
         subcommand: &str,
         args: &[String],
+        flags: &CompilerFlags,
         input_manager: &input_manager::Manager,
-    ) -> Result<CompositeHook, Error> {
+    ) -> Result<CompositeHook<'static>, Error> {
         let mut hooks = CompositeHook::default();
         match subcommand {
             "build" | "check" | "clippy" => {
-                let enabled = if let Some(enabled) = input_manager.get(Input::Annotations) {
-                    enabled
-                        .parse::<bool>()
-                        .map_err(|_| Error::OptionParseError("annotations".into(), enabled.to_string()))?
-                } else {
-                    true
-                };
+                let enabled = input_manager.get_bool(Input::Annotations, true)?;
+                let user_message_format = AnnotationHook::user_message_format(args);
                 if enabled {
-                    hooks.push(AnnotationHook::new(subcommand));
+                    if let Some(format) = user_message_format {
+                        warning!(
+                            "Annotations will not be generated since a message format of {} was specified",
+                            format
+                        );
+                    } else {
+                        let echo = Self::annotations_echo_enabled(input_manager)?;
+                        hooks.push(AnnotationHook::new(subcommand, echo));
+                    }
                 }
+                if subcommand == "build" && Self::built_artifacts_enabled(input_manager)? {
+                    if let Some(format) = user_message_format {
+                        warning!(
+                            "Built artifacts will not be listed since a message format of {} was specified",
+                            format
+                        );
+                    } else {
+                        // The annotation hook already requests JSON output
+                        hooks.push(ArtifactsHook::new(!enabled));
+                    }
+                }
+                if Self::timings_enabled(input_manager)? {
+                    let version = self.toolchain_version(toolchain).await?;
+                    if TimingsHook::is_supported(&version) {
+                        hooks.push(TimingsHook::new(toolchain, args).await);
+                    } else {
+                        warning!(
+                            "Not generating a timing report since {} does not support --timings",
+                            version.short()
+                        );
+                    }
+                }
+            }
+            "test" if Self::cache_test_binaries_enabled(input_manager)? => {
+                let compiler_version = self.toolchain_version(toolchain).await?;
+                hooks.push(TestBinariesHook::new(toolchain, &compiler_version, args, flags).await?);
             }
             "install" => {
                 // Due to the presence of rust toolchain files, actions-rs decides to change
                 // directory before invoking cargo install cross. We do the same for all
                 // installs, not just cross.
                 let empty_dir = create_empty_dir().await?;
-                let compiler_version = self.get_toolchain_version(toolchain, Some(&empty_dir)).await?;
+                let compiler_version = get_toolchain_version(toolchain, Some(&empty_dir)).await?;
                 let empty_cwd_hook = ChangeCwdHook {
                     new_cwd: empty_dir.to_string(),
                 };
-                hooks.push(CargoInstallHook::new(&compiler_version, args).await?);
+                hooks.push(CargoInstallHook::new(&compiler_version, args, flags).await?);
                 hooks.push(empty_cwd_hook);
             }
             _ => {}
@@ -132,36 +299,32 @@ impl Cargo {
         Ok(hooks)
     }
 
-    async fn get_toolchain_version(
-        &self,
-        toolchain: Option<&str>,
-        cwd: Option<&Path>,
-    ) -> Result<ToolchainVersion, Error> {
-        use crate::actions::exec::Stdio;
-        use parking_lot::Mutex;
-        use std::sync::Arc;
+    fn annotations_echo_enabled(input_manager: &input_manager::Manager) -> Result<bool, Error> {
+        input_manager.get_bool(Input::AnnotationsEcho, false)
+    }
 
-        let rustc_path = io::which("rustc", true).await.map_err(Error::Js)?;
-        let mut command = Command::from(&rustc_path);
-        let output: Arc<Mutex<String>> = Arc::default();
-        let output_captured = output.clone();
-        if let Some(toolchain) = toolchain {
-            command.arg(format!("+{}", toolchain).as_str());
-        }
-        if let Some(cwd) = cwd {
-            command.current_dir(cwd);
-        }
-        command.arg("-Vv");
-        command
-            .outline(move |line| {
-                let mut out = output_captured.lock();
-                *out += line;
-                *out += "\n";
-            })
-            .stdout(Stdio::null());
-        command.exec().await?;
-        let long = output.lock().trim().to_string();
-        Ok(ToolchainVersion { long })
+    fn cache_test_binaries_enabled(input_manager: &input_manager::Manager) -> Result<bool, Error> {
+        input_manager.get_bool(Input::CacheTestBinaries, false)
+    }
+
+    fn timings_enabled(input_manager: &input_manager::Manager) -> Result<bool, Error> {
+        input_manager.get_bool(Input::CargoTimings, false)
+    }
+
+    fn built_artifacts_enabled(input_manager: &input_manager::Manager) -> Result<bool, Error> {
+        input_manager.get_bool(Input::BuiltArtifacts, false)
+    }
+
+    fn subcommand_supports_locked(subcommand: &str) -> bool {
+        // `cargo install` is excluded since it runs outside the workspace
+        matches!(
+            subcommand,
+            "bench" | "build" | "check" | "clippy" | "doc" | "fetch" | "run" | "test"
+        )
+    }
+
+    fn subcommand_supports_keep_going(subcommand: &str) -> bool {
+        matches!(subcommand, "build" | "check" | "clippy" | "doc")
     }
 
     pub async fn run<'a, I>(
@@ -175,20 +338,35 @@ impl Cargo {
         I: IntoIterator<Item = &'a str>,
     {
         let args: Vec<String> = args.into_iter().map(Into::into).collect();
-        let mut final_args = Vec::with_capacity(args.len());
-        if let Some(toolchain) = toolchain {
-            final_args.push(format!("+{}", toolchain));
-        }
+        let flags = CompilerFlags::from_inputs(input_manager);
+        // Hooks are selected using what an alias expands to, but Cargo is still
+        // invoked with the alias
+        let (resolved_subcommand, alias_args) = Aliases::load().await.expand(subcommand);
+        let hook_args: Vec<String> = alias_args.into_iter().chain(args.iter().cloned()).collect();
         let mut hooks = self
-            .get_hooks_for_subcommand(toolchain, subcommand, &args[..], input_manager)
+            .get_hooks_for_subcommand(toolchain, &resolved_subcommand, &hook_args, &flags, input_manager)
             .await?;
-        final_args.push(subcommand.into());
-        final_args.extend(hooks.additional_cargo_options().into_iter().map(Cow::into_owned));
-        final_args.extend(args);
+        let mut options: Vec<String> = hooks
+            .additional_cargo_options()
+            .into_iter()
+            .map(Cow::into_owned)
+            .collect();
+        let require_lockfile = input_manager.get_bool(Input::RequireLockfile, false)?;
+        if require_lockfile && Self::subcommand_supports_locked(&resolved_subcommand) {
+            ensure_cargo_lock_present(&process::cwd()).await?;
+            options.push("--locked".into());
+        }
+        let keep_going = input_manager.get_bool(Input::CargoKeepGoing, false)?;
+        if keep_going && Self::subcommand_supports_keep_going(&resolved_subcommand) {
+            options.push("--keep-going".into());
+        }
+        let final_args = Self::assemble_args(toolchain, &self.global_args, subcommand, options, args);
         let mut command = Command::from(&self.path);
         command.args(final_args);
+        flags.apply(&mut command);
         hooks.modify_command(&mut command);
-        if let Err(e) = command.exec().await.map_err(Error::Js) {
+        // A non-zero exit code is a failure too, not just an exception from exec
+        if let Err(e) = command.exec_checked().await {
             hooks.failed().await;
             Err(e)
         } else {
@@ -197,3 +375,53 @@ impl Cargo {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Cargo;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().copied().map(String::from).collect()
+    }
+
+    #[wasm_bindgen_test]
+    fn unstable_flags_and_config_pass_through() {
+        // Equivalent to `cargo -Z build-std build --config net.retry=3` with
+        // annotations
+        let args = Cargo::assemble_args(
+            Some("nightly"),
+            &strings(&["-Z", "build-std"]),
+            "build",
+            strings(&["--message-format=json"]),
+            strings(&["--config", "net.retry=3"]),
+        );
+        assert_eq!(
+            args,
+            strings(&[
+                "+nightly",
+                "-Z",
+                "build-std",
+                "build",
+                "--message-format=json",
+                "--config",
+                "net.retry=3"
+            ])
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn user_args_keep_their_order() {
+        let args = Cargo::assemble_args(
+            None,
+            &[],
+            "test",
+            strings(&["--locked"]),
+            strings(&["--config", "b=2", "-Z", "x", "--", "--nocapture"]),
+        );
+        assert_eq!(
+            args,
+            strings(&["test", "--locked", "--config", "b=2", "-Z", "x", "--", "--nocapture"])
+        );
+    }
+}
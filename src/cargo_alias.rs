@@ -0,0 +1,224 @@
+use crate::cache_cargo_home::find_cargo_home;
+use crate::node::path::Path;
+use crate::node::process;
+use crate::{debug, node, warning, Error};
+use std::collections::{BTreeSet, HashMap};
+use toml::Value;
+
+const CONFIG_DIR_NAME: &str = ".cargo";
+
+// Cargo prefers the name without an extension if both are present
+const CONFIG_FILE_NAMES: [&str; 2] = ["config", "config.toml"];
+
+const ALIAS_TABLE_NAME: &str = "alias";
+
+// Aliases Cargo defines itself. Unlike built-in commands, these can be
+// redefined.
+const DEFAULT_ALIASES: [(&str, &str); 6] = [
+    ("b", "build"),
+    ("c", "check"),
+    ("d", "doc"),
+    ("r", "run"),
+    ("rm", "remove"),
+    ("t", "test"),
+];
+
+// Cargo ignores user-defined aliases with these names
+const BUILTIN_COMMANDS: [&str; 35] = [
+    "add",
+    "bench",
+    "build",
+    "check",
+    "clean",
+    "config",
+    "doc",
+    "fetch",
+    "fix",
+    "generate-lockfile",
+    "git-checkout",
+    "help",
+    "info",
+    "init",
+    "install",
+    "locate-project",
+    "login",
+    "logout",
+    "metadata",
+    "new",
+    "owner",
+    "package",
+    "pkgid",
+    "publish",
+    "read-manifest",
+    "remove",
+    "report",
+    "run",
+    "rustc",
+    "rustdoc",
+    "search",
+    "test",
+    "tree",
+    "uninstall",
+    "update",
+];
+
+/// Aliases for Cargo subcommands, as defined in the `[alias]` tables of
+/// Cargo configuration files
+#[derive(Clone, Debug, Default)]
+pub struct Aliases {
+    aliases: HashMap<String, Vec<String>>,
+}
+
+impl Aliases {
+    /// Loads the aliases visible from the current directory. Configuration
+    /// files which cannot be parsed are skipped with a warning.
+    pub async fn load() -> Aliases {
+        let mut result = Aliases::default();
+        for dir in config_dirs() {
+            for name in CONFIG_FILE_NAMES {
+                let path = dir.join(name);
+                if path.exists().await {
+                    match read_config(&path).await {
+                        Ok(config) => result.add_from_config(&config),
+                        Err(e) => warning!("Unable to read Cargo aliases from {}: {}", path, e),
+                    }
+                    break;
+                }
+            }
+        }
+        result
+    }
+
+    /// Adds the aliases defined in a parsed configuration file. Aliases which
+    /// are already defined take precedence.
+    fn add_from_config(&mut self, config: &Value) {
+        let table = config.get(ALIAS_TABLE_NAME).and_then(Value::as_table);
+        for (name, value) in table.into_iter().flatten() {
+            let expansion = match value {
+                Value::String(expansion) => Some(expansion.split_whitespace().map(String::from).collect()),
+                Value::Array(expansion) => expansion.iter().map(|v| v.as_str().map(String::from)).collect(),
+                _ => None,
+            };
+            match expansion {
+                Some(expansion) => {
+                    self.aliases.entry(name.clone()).or_insert(expansion);
+                }
+                None => warning!(
+                    "Ignoring Cargo alias {} which is neither a string nor a list of strings",
+                    name
+                ),
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Vec<String>> {
+        if BUILTIN_COMMANDS.contains(&name) {
+            None
+        } else if let Some(expansion) = self.aliases.get(name) {
+            Some(expansion.clone())
+        } else {
+            DEFAULT_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map(|(_, command)| vec![command.to_string()])
+        }
+    }
+
+    /// Expands `subcommand` if it is an alias, returning the subcommand it
+    /// resolves to and the arguments the alias supplies before those given on
+    /// the command line. An alias which cannot be expanded resolves to itself.
+    pub fn expand(&self, subcommand: &str) -> (String, Vec<String>) {
+        let mut resolved = subcommand.to_string();
+        let mut args = Vec::new();
+        let mut seen = BTreeSet::new();
+        while let Some(mut expansion) = self.lookup(&resolved) {
+            if !seen.insert(resolved.clone()) || expansion.is_empty() {
+                debug!("Unable to expand Cargo alias {}", subcommand);
+                return (subcommand.to_string(), Vec::new());
+            }
+            // Arguments from an inner alias precede those of the alias that used it
+            let inner = expansion.remove(0);
+            args.splice(0..0, expansion);
+            resolved = inner;
+        }
+        if resolved != subcommand {
+            debug!("Cargo alias {} resolves to {} {}", subcommand, resolved, args.join(" "));
+        }
+        (resolved, args)
+    }
+}
+
+/// Directories which may contain Cargo configuration, in order of precedence
+fn config_dirs() -> Vec<Path> {
+    let mut result = Vec::new();
+    let mut dir = process::cwd();
+    loop {
+        result.push(dir.join(CONFIG_DIR_NAME));
+        let parent = dir.parent();
+        if parent == dir {
+            break;
+        }
+        dir = parent;
+    }
+    let cargo_home = find_cargo_home();
+    if !result.contains(&cargo_home) {
+        result.push(cargo_home);
+    }
+    result
+}
+
+async fn read_config(path: &Path) -> Result<Value, Error> {
+    use crate::error::{Context as _, Location};
+
+    let config = node::fs::read_file(path)
+        .await
+        .with_context(|| format!("while reading {}", path))?;
+    let config = String::from_utf8_lossy(&config);
+    let config = toml::from_str(&config).map_err(|e| Location::of_toml_error(path, &e).wrap(e))?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Aliases;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn aliases(configs: &[&str]) -> Aliases {
+        let mut result = Aliases::default();
+        for config in configs {
+            result.add_from_config(&toml::from_str(config).expect("Failed to parse config"));
+        }
+        result
+    }
+
+    #[wasm_bindgen_test]
+    fn aliases_are_expanded() {
+        let aliases = aliases(&[
+            r#"
+            [alias]
+            br = "build --release"
+            lint = ["clippy", "--all-targets"]
+            lintw = "lint --workspace"
+            build = "check"
+            loop = "loop"
+            bad = 1
+            "#,
+            r#"
+            [alias]
+            br = "check"
+            t = "nextest run"
+            "#,
+        ]);
+        let strings = |args: &[&str]| -> Vec<String> { args.iter().copied().map(String::from).collect() };
+        assert_eq!(aliases.expand("br"), ("build".into(), strings(&["--release"])));
+        assert_eq!(
+            aliases.expand("lintw"),
+            ("clippy".into(), strings(&["--all-targets", "--workspace"]))
+        );
+        assert_eq!(aliases.expand("b"), ("build".into(), Vec::new()));
+        assert_eq!(aliases.expand("t"), ("nextest".into(), strings(&["run"])));
+        assert_eq!(aliases.expand("build"), ("build".into(), Vec::new()));
+        assert_eq!(aliases.expand("loop"), ("loop".into(), Vec::new()));
+        assert_eq!(aliases.expand("bad"), ("bad".into(), Vec::new()));
+    }
+}
@@ -6,30 +6,65 @@ use async_trait::async_trait;
 use cargo_metadata::diagnostic::{DiagnosticLevel, DiagnosticSpan};
 use std::borrow::Cow;
 
+const MESSAGE_FORMAT_FLAG: &str = "--message-format";
+
 #[derive(Default)]
 pub struct Annotation {
     subcommand: String,
+    echo: bool,
 }
 
 impl Annotation {
-    pub fn new(subcommand: &str) -> Annotation {
+    /// If `echo` is true, the human-readable rendering of each diagnostic is
+    /// also written to the log, since Cargo's own output is suppressed
+    pub fn new(subcommand: &str, echo: bool) -> Annotation {
         Annotation {
             subcommand: subcommand.to_string(),
+            echo,
+        }
+    }
+
+    /// Removes the ANSI escape sequences Cargo uses to colour rendered
+    /// diagnostics
+    fn strip_ansi_escapes(text: &str) -> Cow<'_, str> {
+        use lazy_static::lazy_static;
+        use regex::Regex;
+
+        lazy_static! {
+            static ref ESCAPE: Regex = Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").expect("Regex compilation failed");
         }
+        ESCAPE.replace_all(text, "")
     }
 
-    fn process_json_record(cargo_subcommand: &str, line: &str) {
+    /// Returns the message format supplied in `args`, if any. Cargo rejects
+    /// combinations of JSON with other formats, so we cannot add our own in
+    /// this case.
+    pub fn user_message_format(args: &[String]) -> Option<&str> {
+        let mut iter = args.iter().map(String::as_str).take_while(|a| *a != "--");
+        while let Some(arg) = iter.next() {
+            if arg == MESSAGE_FORMAT_FLAG {
+                return iter.next();
+            } else if let Some(format) = arg
+                .strip_prefix(MESSAGE_FORMAT_FLAG)
+                .and_then(|rest| rest.strip_prefix('='))
+            {
+                return Some(format);
+            }
+        }
+        None
+    }
+
+    fn process_json_record(cargo_subcommand: &str, echo: bool, line: &[u8]) {
         use crate::actions::core::Annotation;
         use crate::node::path::Path;
         use cargo_metadata::Message;
 
         // Ignore blank lines
-        let line = line.trim();
-        if line.is_empty() {
+        if line.iter().all(u8::is_ascii_whitespace) {
             return;
         }
 
-        let metadata: Message = match serde_json::from_str(line) {
+        let metadata: Message = match serde_json::from_slice(line) {
             Ok(metadata) => metadata,
             Err(e) => {
                 warning!("Unable to cargo output line as JSON metadata record: {}", e);
@@ -39,8 +74,13 @@ impl Annotation {
         if let Message::CompilerMessage(compiler_message) = metadata {
             let diagnostic = &compiler_message.message;
             let level = Self::annotation_level(diagnostic.level);
+            if echo {
+                if let Some(rendered) = &diagnostic.rendered {
+                    crate::actions::core::info(rendered.trim_end());
+                }
+            }
             let mut annotation = if let Some(rendered) = &diagnostic.rendered {
-                let mut annotation = Annotation::from(rendered.as_str());
+                let mut annotation = Annotation::from(Self::strip_ansi_escapes(rendered).as_ref());
                 annotation.title(&format!("cargo-{}: {}", cargo_subcommand, diagnostic.message));
                 annotation
             } else {
@@ -79,15 +119,53 @@ impl Annotation {
 #[async_trait(?Send)]
 impl Hook for Annotation {
     fn additional_cargo_options(&self) -> Vec<Cow<str>> {
-        vec!["--message-format=json".into()]
+        let format = if self.echo {
+            // Colours are preserved for the echoed output
+            "json-diagnostic-rendered-ansi"
+        } else {
+            "json"
+        };
+        vec![format!("{}={}", MESSAGE_FORMAT_FLAG, format).into()]
     }
 
     fn modify_command(&self, command: &mut Command) {
         use crate::actions::exec::Stdio;
 
         let subcommand = self.subcommand.clone();
+        let echo = self.echo;
         command
-            .outline(move |line| Self::process_json_record(&subcommand, line))
+            .outline_bytes(move |line| Self::process_json_record(&subcommand, echo, line))
             .stdout(Stdio::null());
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Annotation;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn user_message_format(args: &[&str]) -> Option<String> {
+        let args: Vec<String> = args.iter().copied().map(String::from).collect();
+        Annotation::user_message_format(&args).map(String::from)
+    }
+
+    #[wasm_bindgen_test]
+    fn detects_user_message_format() {
+        assert_eq!(user_message_format(&["--release"]), None);
+        assert_eq!(
+            user_message_format(&["--message-format", "short"]).as_deref(),
+            Some("short")
+        );
+        assert_eq!(
+            user_message_format(&["--message-format=human"]).as_deref(),
+            Some("human")
+        );
+        assert_eq!(user_message_format(&["--", "--message-format=human"]), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn ansi_escapes_are_stripped() {
+        let rendered = "\x1b[0m\x1b[1m\x1b[33mwarning\x1b[0m\x1b[0m\x1b[1m: unused variable\x1b[0m";
+        assert_eq!(Annotation::strip_ansi_escapes(rendered), "warning: unused variable");
+    }
+}
@@ -0,0 +1,98 @@
+use super::Hook;
+use crate::actions::core;
+use crate::actions::exec::Command;
+use crate::{debug, info};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+const OUTPUT_NAME: &str = "built-artifacts";
+
+/// Collects the paths of artifacts produced by `cargo build` and publishes
+/// them as an output of the action
+#[derive(Default)]
+pub struct Artifacts {
+    paths: Arc<Mutex<Vec<String>>>,
+    add_message_format: bool,
+}
+
+impl Artifacts {
+    /// If `add_message_format` is false, another hook must already be
+    /// requesting JSON output from Cargo
+    pub fn new(add_message_format: bool) -> Artifacts {
+        Artifacts {
+            paths: Arc::default(),
+            add_message_format,
+        }
+    }
+
+    fn process_json_record(paths: &Mutex<Vec<String>>, line: &[u8]) {
+        use cargo_metadata::Message;
+
+        let artifact = match serde_json::from_slice(line) {
+            Ok(Message::CompilerArtifact(artifact)) => artifact,
+            Ok(_) => return,
+            Err(e) => {
+                debug!("Unable to parse cargo output line as JSON metadata record: {}", e);
+                return;
+            }
+        };
+        let mut paths = paths.lock();
+        for path in artifact.executable.iter().chain(artifact.filenames.iter()) {
+            let path = path.to_string();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Hook for Artifacts {
+    fn additional_cargo_options(&self) -> Vec<Cow<str>> {
+        if self.add_message_format {
+            // Diagnostics are still rendered for the log
+            vec!["--message-format=json-render-diagnostics".into()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn modify_command(&self, command: &mut Command) {
+        use crate::actions::exec::Stdio;
+
+        let paths = Arc::clone(&self.paths);
+        command.outline_bytes(move |line| Self::process_json_record(&paths, line));
+        if self.add_message_format {
+            command.stdout(Stdio::null());
+        }
+    }
+
+    async fn succeeded(&mut self) {
+        let paths = self.paths.lock();
+        info!("Cargo built {} artifacts", paths.len());
+        core::set_output(OUTPUT_NAME, paths.join("\n"));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Artifacts;
+    use parking_lot::Mutex;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn artifact_paths_are_collected() {
+        let paths = Mutex::default();
+        let artifact = r#"{"reason":"compiler-artifact","package_id":"foo 0.1.0 (path+file:///foo)",
+            "manifest_path":"/foo/Cargo.toml",
+            "target":{"kind":["bin"],"crate_types":["bin"],"name":"foo","src_path":"/foo/src/main.rs",
+            "edition":"2021","doc":true,"doctest":false,"test":true},
+            "profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},
+            "features":[],"filenames":["/foo/target/debug/foo"],"executable":"/foo/target/debug/foo","fresh":false}"#;
+        Artifacts::process_json_record(&paths, artifact.as_bytes());
+        Artifacts::process_json_record(&paths, br#"{"reason":"build-finished","success":true}"#);
+        assert_eq!(*paths.lock(), vec!["/foo/target/debug/foo"]);
+    }
+}
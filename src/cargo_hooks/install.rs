@@ -1,18 +1,199 @@
-use super::Hook;
+use super::{find_flag_value, Hook};
 use crate::action_paths::get_action_cache_dir;
-use crate::actions::cache::Entry as CacheEntry;
-use crate::cargo::ToolchainVersion;
+use crate::actions::cache::{Entry as CacheEntry, SaveOutcome};
+use crate::cache_cargo_home::find_cargo_home;
+use crate::cargo::{CompilerFlags, ToolchainVersion};
 use crate::delta::render_list as render_delta_list;
-use crate::fingerprinting::Fingerprint;
+use crate::fingerprinting::{self, Fingerprint};
 use crate::hasher::Blake3 as Blake3Hasher;
 use crate::node::path::Path;
-use crate::{actions, error, info, node, warning, Error};
+use crate::{actions, debug, dir_tree, error, info, node, warning, Error};
 use async_trait::async_trait;
 use rustup_toolchain_manifest::HashValue;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 
 const MAX_ARG_STRING_LENGTH: usize = 80;
 
+// How often the build artifacts of packages installed from a git branch are
+// discarded. Otherwise artifacts from every commit built would accumulate.
+const GIT_BRANCH_REBUILD_INTERVAL_SECS: u64 = 7 * 24 * 60 * 60;
+
+// Files of a `--path` install which determine its dependencies. Changes to
+// other files are rebuilt incrementally by Cargo so should not cause a build
+// from scratch. A fingerprint based on modification times would be useless
+// since checkouts give every file a new one.
+const PATH_SOURCE_KEY_FILES: &[&str] = &["Cargo.toml", "Cargo.lock"];
+
+// Flags taking no value whose position on the command line has no effect
+const ORDER_INSENSITIVE_FLAGS: &[&str] = &[
+    "--all-features",
+    "--bins",
+    "--debug",
+    "--examples",
+    "--force",
+    "--frozen",
+    "--locked",
+    "--no-default-features",
+    "--no-track",
+    "--offline",
+    "--quiet",
+    "--verbose",
+    "-f",
+    "-q",
+    "-v",
+];
+
+const FEATURES_FLAGS: &[&str] = &["--features", "-F"];
+
+// Prefixes of environment variables which configure how Cargo builds
+const BUILD_ENV_VAR_PREFIXES: &[&str] = &["CARGO_PROFILE_", "CARGO_BUILD_"];
+
+// Build configuration which does not affect what is built. The target
+// directory is always overridden by this hook.
+const IGNORED_BUILD_ENV_VARS: &[&str] = &["CARGO_BUILD_JOBS", "CARGO_BUILD_TARGET_DIR"];
+
+/// Rewrites `cargo install` arguments into a canonical form so that
+/// equivalent invocations hash identically. Feature lists are merged, sorted
+/// and deduplicated and flags known to be order-insensitive are sorted. All
+/// other arguments keep their relative order, as does anything following
+/// `--`.
+fn canonicalize_args(args: &[String]) -> Vec<String> {
+    use std::collections::BTreeSet;
+
+    let mut ordered = Vec::with_capacity(args.len());
+    let mut flags = Vec::new();
+    let mut features = BTreeSet::new();
+    let mut tail = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let arg = arg.as_str();
+        if arg == "--" {
+            // We don't know how anything after this is interpreted
+            tail.push(arg.to_string());
+            tail.extend(iter.by_ref().cloned());
+        } else if ORDER_INSENSITIVE_FLAGS.contains(&arg) {
+            flags.push(arg.to_string());
+        } else if FEATURES_FLAGS.contains(&arg) {
+            if let Some(value) = iter.next() {
+                features.extend(split_features(value));
+            } else {
+                ordered.push(arg.to_string());
+            }
+        } else if let Some(value) = features_flag_value(arg) {
+            features.extend(split_features(value));
+        } else {
+            ordered.push(arg.to_string());
+        }
+    }
+    flags.sort_unstable();
+    ordered.extend(flags);
+    if !features.is_empty() {
+        use itertools::Itertools as _;
+
+        ordered.push("--features".into());
+        ordered.push(features.into_iter().join(","));
+    }
+    ordered.extend(tail);
+    ordered
+}
+
+/// Extracts the value of a features flag with an attached value, i.e.
+/// `--features=a`, `-F=a` or `-Fa`
+fn features_flag_value(arg: &str) -> Option<&str> {
+    if let Some(value) = arg.strip_prefix("--features=") {
+        Some(value)
+    } else if let Some(value) = arg.strip_prefix("-F") {
+        Some(value.strip_prefix('=').unwrap_or(value))
+    } else {
+        None
+    }
+}
+
+fn split_features(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|f| !f.is_empty())
+        .map(String::from)
+}
+
+/// The environment variables which can change the build artifacts produced,
+/// such as profile settings, in a stable order
+fn build_env_vars(env: &HashMap<String, String>) -> BTreeMap<&str, &str> {
+    env.iter()
+        .filter(|(name, _)| BUILD_ENV_VAR_PREFIXES.iter().any(|prefix| name.starts_with(prefix)))
+        .filter(|(name, _)| !IGNORED_BUILD_ENV_VARS.contains(&name.as_str()))
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect()
+}
+
+/// Where the package being installed comes from, as far as it affects the
+/// cache key
+#[derive(Debug, PartialEq, Eq)]
+enum Source<'a> {
+    /// A registry, or a git repository pinned to a revision or tag. The
+    /// arguments identify these.
+    Pinned,
+    /// A git repository tracking a branch, which may move without the
+    /// arguments changing
+    GitBranch,
+    /// A local directory
+    Path(&'a str),
+}
+
+fn find_source(args: &[String]) -> Source<'_> {
+    if let Some(path) = find_flag_value(args, "--path") {
+        Source::Path(path)
+    } else if find_flag_value(args, "--git").is_some()
+        && find_flag_value(args, "--rev").is_none()
+        && find_flag_value(args, "--tag").is_none()
+    {
+        Source::GitBranch
+    } else {
+        Source::Pinned
+    }
+}
+
+/// Hashes the files of a `--path` install source which determine its
+/// dependencies
+async fn hash_path_source<H: std::hash::Hasher>(path: &str, hasher: &mut H) -> Result<(), Error> {
+    use crate::hasher::hash_file;
+    use std::hash::Hash as _;
+
+    // `cargo install` is run from elsewhere so relative paths are resolved here
+    let path = node::process::cwd().join(path);
+    for file_name in PATH_SOURCE_KEY_FILES {
+        let file_path = path.join(*file_name);
+        let content_hash = if file_path.exists().await {
+            Some(hash_file(&file_path).await?)
+        } else {
+            None
+        };
+        debug!(
+            "Install source file {} contributes to cache key (present: {})",
+            file_path,
+            content_hash.is_some()
+        );
+        content_hash.hash(hasher);
+    }
+    Ok(())
+}
+
+pub(super) fn truncate_for_key(value: &str) -> String {
+    let mut value = value.to_string();
+    if value.len() > MAX_ARG_STRING_LENGTH {
+        let ellipsis = "...";
+        let mut end = MAX_ARG_STRING_LENGTH - ellipsis.len();
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+        value += ellipsis;
+    }
+    value
+}
+
 fn get_package_build_dir(hash: &HashValue) -> Result<Path, Error> {
     // Don't use safe_encoding here because the platform filesystem
     // might not be case sensitive
@@ -22,93 +203,225 @@ fn get_package_build_dir(hash: &HashValue) -> Result<Path, Error> {
     Ok(dir)
 }
 
+fn get_restored_fingerprint_path(hash: &HashValue) -> Result<Path, Error> {
+    let path = get_action_cache_dir()?
+        .join(&format!(
+            "package-build-fingerprints-v{}",
+            fingerprinting::FORMAT_VERSION
+        ))
+        .join(&format!("{}.postcard", hash));
+    Ok(path)
+}
+
+/// Joins arguments into a single shell-quoted string after canonicalizing
+/// them
+pub(super) fn canonical_arg_string(args: &[String]) -> String {
+    let mut arg_string = String::new();
+    let mut first = true;
+    for arg in canonicalize_args(args) {
+        if first {
+            first = false;
+        } else {
+            arg_string += " ";
+        }
+        arg_string += &shlex::quote(&arg);
+    }
+    arg_string
+}
+
+pub(super) async fn fingerprint_build_dir(path: &Path) -> Result<Fingerprint, Error> {
+    use crate::fingerprinting::{default_volatile_ignores, fingerprint_path_with_ignores};
+
+    let ignores = default_volatile_ignores();
+    let fingerprint = fingerprint_path_with_ignores(path, &ignores).await?;
+    Ok(fingerprint)
+}
+
+/// Saves a build directory to the cache unless it was restored from
+/// `restore_key` with fingerprint `old_fingerprint` and has not changed since
+pub(super) async fn save_build_dir_if_changed(
+    cache_entry: &CacheEntry,
+    path: &Path,
+    old_fingerprint: Option<&Fingerprint>,
+    restore_key: Option<&str>,
+    description: &str,
+) {
+    let save = if let Some(old_fingerprint) = old_fingerprint {
+        match fingerprint_build_dir(path).await {
+            Ok(new_fingerprint) => {
+                let changed = new_fingerprint.content_hash() != old_fingerprint.content_hash();
+                if changed {
+                    info!(
+                        "{} cache changed fingerprint from {} to {}",
+                        description,
+                        old_fingerprint.content_hash(),
+                        new_fingerprint.content_hash()
+                    );
+                    let delta = new_fingerprint.changes_from(old_fingerprint);
+                    info!("{}", render_delta_list(&delta));
+                }
+                changed
+            }
+            Err(e) => {
+                error!("Could not fingerprint {} directory: {}", description.to_lowercase(), e);
+                false
+            }
+        }
+    } else {
+        true
+    };
+    if !save {
+        info!("{} unchanged, no need to save back to cache.", description);
+        return;
+    }
+    match cache_entry.save_if_update(restore_key).await.map_err(Error::Js) {
+        Err(e) => {
+            error!("Failed to save {} to cache: {}", description.to_lowercase(), e);
+        }
+        Ok(SaveOutcome::Saved(_)) => info!("Saved {} to cache.", description.to_lowercase()),
+        Ok(SaveOutcome::Superseded) => info!(
+            "Looks like a concurrent CI job updated the {}, not saving back to cache",
+            description.to_lowercase()
+        ),
+        // Already reported when saving
+        Ok(SaveOutcome::TooLarge) => {}
+        Ok(SaveOutcome::Unavailable | SaveOutcome::NotSaved) => {
+            info!("{} was not saved to cache.", description);
+        }
+    }
+}
+
+/// The fingerprint of a build directory immediately after it was restored
+/// from a particular cache entry. Since the build directory is always empty
+/// before restoring, restoring the same entry again later in the job yields
+/// identical contents, so this saves walking the restored tree each time the
+/// same package is installed.
+#[derive(Serialize, Deserialize)]
+struct RestoredFingerprint {
+    restore_key: String,
+    fingerprint: Fingerprint,
+}
+
+/// Empties a build directory before restoring into it. Anything left behind
+/// by an earlier failed run would not be part of the restored entry, and
+/// reusing a `RestoredFingerprint` relies on the directory being empty.
+async fn prepare_build_dir(path: &Path) -> Result<(), Error> {
+    actions::io::rm_rf(path).await?;
+    node::fs::create_dir_all(path).await?;
+    Ok(())
+}
+
+/// Fingerprints a build directory just restored from `restore_key`, reusing
+/// the fingerprint stored at `memo_path` if it was taken after restoring the
+/// same entry
+async fn fingerprint_restored_build_dir(
+    memo_path: &Path,
+    path: &Path,
+    restore_key: &str,
+) -> Result<Fingerprint, Error> {
+    if let Ok(data) = node::fs::read_file(memo_path).await {
+        match postcard::from_bytes::<RestoredFingerprint>(&data) {
+            Ok(memo) if memo.restore_key == restore_key => {
+                debug!("Reusing fingerprint of build artifacts restored earlier in this job");
+                return Ok(memo.fingerprint);
+            }
+            Ok(_) => {}
+            Err(e) => debug!("Unable to parse stored build artifact fingerprint: {}", e),
+        }
+    }
+    let fingerprint = fingerprint_build_dir(path).await?;
+    let memo = RestoredFingerprint {
+        restore_key: restore_key.to_string(),
+        fingerprint,
+    };
+    let data = postcard::to_stdvec(&memo)?;
+    node::fs::create_dir_all(&memo_path.parent()).await?;
+    node::fs::write_file(memo_path, &data).await?;
+    Ok(memo.fingerprint)
+}
+
 pub struct Install {
     hash: HashValue,
     build_dir: String,
     fingerprint: Option<Fingerprint>,
     arg_string: String,
+    flags_string: String,
     restore_key: Option<String>,
     toolchain_version_short: String,
 }
 
 impl Install {
-    pub async fn new<I, A>(toolchain_version: &ToolchainVersion, args: I) -> Result<Install, Error>
+    pub async fn new<I, A>(
+        toolchain_version: &ToolchainVersion,
+        args: I,
+        flags: &CompilerFlags,
+    ) -> Result<Install, Error>
     where
         I: IntoIterator<Item = A>,
         A: AsRef<str>,
     {
         use std::hash::Hash as _;
 
+        let args: Vec<String> = args.into_iter().map(|a| a.as_ref().to_string()).collect();
         let mut hasher = Blake3Hasher::default();
         toolchain_version.long().hash(&mut hasher);
-        let arg_string = {
-            let mut arg_string = String::new();
-            let mut first = true;
-            for arg in args {
-                let arg = arg.as_ref();
-                if first {
-                    first = false;
-                } else {
-                    arg_string += " ";
-                }
-                arg_string += &shlex::quote(arg);
-            }
-            arg_string
-        };
+        let arg_string = canonical_arg_string(&args);
         arg_string.hash(&mut hasher);
+        // Builds with different compiler flags must not share artifacts
+        flags.hash(&mut hasher);
+        let env = node::process::get_env();
+        let build_env_vars = build_env_vars(&env);
+        for name in build_env_vars.keys() {
+            debug!("Environment variable {} contributes to cache key", name);
+        }
+        build_env_vars.hash(&mut hasher);
+        match find_source(&args) {
+            Source::Pinned => {}
+            Source::GitBranch => {
+                use humantime::format_duration;
+                use std::time::Duration;
+
+                info!(
+                    "Package is installed from a git branch so its build artifacts will be rebuilt from scratch every {}",
+                    format_duration(Duration::from_secs(GIT_BRANCH_REBUILD_INTERVAL_SECS))
+                );
+                let now = u64::try_from(chrono::Utc::now().timestamp()).unwrap_or_default();
+                (now / GIT_BRANCH_REBUILD_INTERVAL_SECS).hash(&mut hasher);
+            }
+            Source::Path(path) => hash_path_source(path, &mut hasher).await?,
+        }
         let hash = hasher.hash_value();
         let build_dir = get_package_build_dir(&hash)?;
-        node::fs::create_dir_all(&build_dir).await?;
+        prepare_build_dir(&build_dir).await?;
         let mut result = Install {
             hash,
             build_dir: build_dir.to_string(),
             fingerprint: None,
             arg_string,
+            flags_string: flags.describe(),
             restore_key: None,
             toolchain_version_short: toolchain_version.short().to_string(),
         };
         let cache_entry = result.build_cache_entry();
         if let Some(key) = cache_entry.restore().await? {
             info!("Restored files from cache with key {}", key);
-            result.fingerprint = Some(Self::fingerprint_build_dir(&build_dir).await?);
+            let memo_path = get_restored_fingerprint_path(&result.hash)?;
+            result.fingerprint = Some(fingerprint_restored_build_dir(&memo_path, &build_dir, &key).await?);
             result.restore_key = Some(key);
         }
         Ok(result)
     }
 
-    async fn fingerprint_build_dir(path: &Path) -> Result<Fingerprint, Error> {
-        use crate::fingerprinting::{fingerprint_path_with_ignores, Ignores};
-
-        // It seems that between runs something causes the rustc fingerprint to change.
-        // It looks like this could simply be the file modification timestamp. This
-        // would also explain why it seemed to occur with Rustup but not the
-        // internal toolchain downloader.
-        //
-        // https://github.com/rust-lang/cargo/blob/70898e522116f6c23971e2a554b2dc85fd4c84cd/src/cargo/util/rustc.rs#L306
-
-        let mut ignores = Ignores::default();
-        ignores.add(1, ".rustc_info.json");
-
-        let fingerprint = fingerprint_path_with_ignores(path, &ignores).await?;
-        Ok(fingerprint)
-    }
-
     fn build_cache_entry(&self) -> CacheEntry {
         use crate::cache_key_builder::{Attribute, CacheKeyBuilder};
 
         let mut key_builder = CacheKeyBuilder::new("cargo install build artifacts");
         key_builder.add_key_data(&self.hash);
         key_builder.set_attribute(Attribute::ToolchainVersion, self.toolchain_version_short.clone());
-        let arg_string = {
-            let mut arg_string = self.arg_string.clone();
-            if arg_string.len() > MAX_ARG_STRING_LENGTH {
-                let ellipsis = "...";
-                arg_string.truncate(MAX_ARG_STRING_LENGTH - ellipsis.len());
-                arg_string += ellipsis;
-            }
-            arg_string
-        };
-        key_builder.set_attribute(Attribute::ArgsTruncated, arg_string);
+        key_builder.set_attribute(Attribute::ArgsTruncated, truncate_for_key(&self.arg_string));
+        if !self.flags_string.is_empty() {
+            key_builder.set_attribute(Attribute::CompilerFlags, truncate_for_key(&self.flags_string));
+        }
         let mut cache_entry = key_builder.into_entry();
         cache_entry.path(&Path::from(&self.build_dir));
         cache_entry
@@ -128,51 +441,24 @@ impl Hook for Install {
     }
 
     async fn succeeded(&mut self) {
-        let save = if let Some(old_fingerprint) = &self.fingerprint {
-            let path = Path::from(&self.build_dir);
-            match Self::fingerprint_build_dir(&path).await {
-                Ok(new_fingerprint) => {
-                    let changed = new_fingerprint.content_hash() != old_fingerprint.content_hash();
-                    if changed {
-                        info!(
-                            "Package artifact cache changed fingerprint from {} to {}",
-                            old_fingerprint.content_hash(),
-                            new_fingerprint.content_hash()
-                        );
-                        let delta = new_fingerprint.changes_from(old_fingerprint);
-                        info!("{}", render_delta_list(&delta));
-                    }
-                    changed
-                }
-                Err(e) => {
-                    error!("Could not fingerprint build artifact directory: {}", e);
-                    false
-                }
-            }
-        } else {
-            true
-        };
-        if save {
-            let cache_entry = self.build_cache_entry();
-            match cache_entry
-                .save_if_update(self.restore_key.as_deref())
-                .await
-                .map_err(Error::Js)
-            {
-                Err(e) => {
-                    error!("Failed to save package build artifacts to cache: {}", e);
-                }
-                Ok(r) => {
-                    if r.is_some() {
-                        info!("Saved package build artifacts to cache.");
-                    } else {
-                        info!("Looks like a concurrent CI job updated the artifacts, not saving back to cache");
-                    }
-                }
-            }
-        } else {
-            info!("Build artifacts unchanged, no need to save back to cache.");
+        // Binaries installed from restored build artifacts may have lost their
+        // executable bits
+        let cargo_bin = find_cargo_home().join("bin");
+        if let Err(e) = dir_tree::ensure_executable(&cargo_bin).await {
+            warning!(
+                "Failed to mark installed binaries at {} as executable: {}",
+                cargo_bin,
+                e
+            );
         }
+        save_build_dir_if_changed(
+            &self.build_cache_entry(),
+            &Path::from(&self.build_dir),
+            self.fingerprint.as_ref(),
+            self.restore_key.as_deref(),
+            "Package build artifacts",
+        )
+        .await;
         self.cleanup().await;
     }
 
@@ -180,3 +466,118 @@ impl Hook for Install {
         self.cleanup().await;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        build_env_vars, canonicalize_args, find_source, fingerprint_restored_build_dir, prepare_build_dir, Source,
+    };
+    use crate::{node, Error};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn canonicalize(args: &[&str]) -> Vec<String> {
+        let args: Vec<String> = args.iter().copied().map(String::from).collect();
+        canonicalize_args(&args)
+    }
+
+    #[wasm_bindgen_test]
+    fn feature_order_is_ignored() {
+        let expected = canonicalize(&["ripgrep", "--features", "a,b"]);
+        assert_eq!(expected, canonicalize(&["ripgrep", "--features", "b,a"]));
+        assert_eq!(expected, canonicalize(&["ripgrep", "--features=b a"]));
+        assert_eq!(expected, canonicalize(&["ripgrep", "-F", "b", "--features", "a,b"]));
+        assert_eq!(expected, canonicalize(&["--features", "b,a", "ripgrep"]));
+        assert_eq!(expected, canonicalize(&["ripgrep", "-F=b", "-Fa"]));
+        assert_eq!(expected, canonicalize(&["ripgrep", "-Fa,b"]));
+    }
+
+    #[wasm_bindgen_test]
+    fn flag_order_is_ignored() {
+        assert_eq!(
+            canonicalize(&["ripgrep", "--locked", "--no-default-features"]),
+            canonicalize(&["--no-default-features", "ripgrep", "--locked"])
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn other_args_keep_their_order() {
+        assert_ne!(
+            canonicalize(&["--version", "1.0", "ripgrep"]),
+            canonicalize(&["ripgrep", "--version", "1.0"])
+        );
+        assert_ne!(canonicalize(&["a", "b"]), canonicalize(&["b", "a"]));
+    }
+
+    #[wasm_bindgen_test]
+    fn args_after_separator_are_untouched() {
+        assert_eq!(
+            canonicalize(&["ripgrep", "--", "--locked", "--features", "b,a"]),
+            vec!["ripgrep", "--", "--locked", "--features", "b,a"]
+        );
+        assert_eq!(
+            canonicalize(&["--locked", "ripgrep", "-Fb", "--", "x", "--features", "c"]),
+            vec!["ripgrep", "--locked", "--features", "b", "--", "x", "--features", "c"]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn source_is_found_in_args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().copied().map(String::from).collect() };
+        let url = "https://example.com/a.git";
+        assert_eq!(find_source(&args(&["ripgrep"])), Source::Pinned);
+        assert_eq!(find_source(&args(&["--git", url])), Source::GitBranch);
+        assert_eq!(
+            find_source(&args(&["--git", url, "--branch", "dev"])),
+            Source::GitBranch
+        );
+        assert_eq!(find_source(&args(&["--git", url, "--rev", "abc"])), Source::Pinned);
+        assert_eq!(find_source(&args(&["--git", url, "--tag=v1"])), Source::Pinned);
+        assert_eq!(find_source(&args(&["--path", "tools/a"])), Source::Path("tools/a"));
+    }
+
+    #[wasm_bindgen_test]
+    fn build_env_vars_are_selected() {
+        let env = [
+            ("CARGO_PROFILE_RELEASE_LTO", "true"),
+            ("CARGO_BUILD_TARGET", "x86_64-unknown-linux-musl"),
+            ("CARGO_BUILD_JOBS", "4"),
+            ("CARGO_BUILD_TARGET_DIR", "/tmp/target"),
+            ("CARGO_HOME", "/home/user/.cargo"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let selected: Vec<_> = build_env_vars(&env).into_keys().collect();
+        assert_eq!(selected, ["CARGO_BUILD_TARGET", "CARGO_PROFILE_RELEASE_LTO"]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn restored_fingerprints_are_reused() -> Result<(), Error> {
+        let root = node::fs::mkdtemp(&node::os::temp_dir().join("ferrous-actions-restored-fingerprint-test-")).await?;
+        let build_dir = root.join("build");
+        let memo_path = root.join("fingerprint.postcard");
+        let leftover = build_dir.join("leftover");
+        let restored = build_dir.join("restored");
+
+        node::fs::create_dir_all(&build_dir).await?;
+        node::fs::write_file(&leftover, b"from a failed run").await?;
+        prepare_build_dir(&build_dir).await?;
+        assert!(!leftover.exists().await);
+        node::fs::write_file(&restored, b"restored").await?;
+        let first = fingerprint_restored_build_dir(&memo_path, &build_dir, "key-1").await?;
+
+        // The stored fingerprint is used for the same restore key, even though
+        // the contents now differ
+        prepare_build_dir(&build_dir).await?;
+        node::fs::write_file(&restored, b"modified after restoring").await?;
+        let reused = fingerprint_restored_build_dir(&memo_path, &build_dir, "key-1").await?;
+        assert_eq!(first.content_hash(), reused.content_hash());
+
+        // A different restore key means the directory is walked again
+        let walked = fingerprint_restored_build_dir(&memo_path, &build_dir, "key-2").await?;
+        assert_ne!(first.content_hash(), walked.content_hash());
+
+        crate::actions::io::rm_rf(&root).await?;
+        Ok(())
+    }
+}
@@ -1,7 +1,120 @@
 mod annotation;
+mod artifacts;
 mod hook;
 mod install;
+mod test_binaries;
+mod timings;
 
 pub(crate) use annotation::*;
+pub(crate) use artifacts::*;
 pub(crate) use hook::*;
 pub(crate) use install::*;
+pub(crate) use test_binaries::*;
+pub(crate) use timings::*;
+
+use crate::actions::exec::{Command, Stdio};
+use crate::actions::io;
+use crate::node::path::Path;
+use crate::node::process;
+use crate::{warning, Error};
+
+const MANIFEST_PATH_FLAG: &str = "--manifest-path";
+const TARGET_DIR_FLAG: &str = "--target-dir";
+const TARGET_DIR_VAR: &str = "CARGO_TARGET_DIR";
+
+/// Finds the value of the last occurrence of `flag` in `args`
+fn find_flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let mut result = None;
+    let mut iter = args.iter().map(String::as_str).take_while(|a| *a != "--");
+    while let Some(arg) = iter.next() {
+        if arg == flag {
+            result = iter.next();
+        } else if let Some(value) = arg.strip_prefix(flag).and_then(|rest| rest.strip_prefix('=')) {
+            result = Some(value);
+        }
+    }
+    result
+}
+
+/// The target directory Cargo will use given `args`. Unless it is given on the
+/// command line, this is the one reported by `cargo metadata`, which accounts
+/// for Cargo configuration and the location of the workspace root.
+async fn target_dir(toolchain: Option<&str>, args: &[String]) -> Path {
+    if let Some(target_dir) = find_flag_value(args, TARGET_DIR_FLAG) {
+        return process::cwd().join(target_dir);
+    }
+    match metadata_target_dir(toolchain, args).await {
+        Ok(target_dir) => target_dir,
+        Err(e) => {
+            warning!("Unable to find target directory using cargo metadata: {}", e);
+            let target_dir = process::get_env()
+                .remove(TARGET_DIR_VAR)
+                .unwrap_or_else(|| "target".into());
+            process::cwd().join(&target_dir)
+        }
+    }
+}
+
+async fn metadata_target_dir(toolchain: Option<&str>, args: &[String]) -> Result<Path, Error> {
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    let cargo_path = io::which("cargo", true).await.map_err(Error::Js)?;
+    let mut command = Command::from(&cargo_path);
+    if let Some(toolchain) = toolchain {
+        command.arg(format!("+{}", toolchain).as_str());
+    }
+    command.args(["metadata", "--format-version", "1", "--no-deps"]);
+    if let Some(manifest_path) = find_flag_value(args, MANIFEST_PATH_FLAG) {
+        command.args([MANIFEST_PATH_FLAG, manifest_path]);
+    }
+    let output: Arc<Mutex<String>> = Arc::default();
+    let output_captured = output.clone();
+    command
+        .outline(move |line| {
+            let mut out = output_captured.lock();
+            *out += line;
+            *out += "\n";
+        })
+        .stdout(Stdio::null())
+        .exec_checked()
+        .await?;
+    let target_dir = parse_target_dir(&output.lock())?;
+    Ok(target_dir)
+}
+
+/// Extracts the target directory from the output of `cargo metadata`
+fn parse_target_dir(metadata: &str) -> Result<Path, Error> {
+    let metadata: cargo_metadata::Metadata = serde_json::from_str(metadata)?;
+    Ok(Path::from(metadata.target_directory.as_str()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_target_dir, target_dir};
+    use crate::node::path::Path;
+    use crate::node::process;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn target_dir_is_parsed_from_metadata() {
+        let metadata = r#"{
+            "packages": [],
+            "workspace_members": [],
+            "resolve": null,
+            "target_directory": "/home/runner/work/project/out",
+            "workspace_root": "/home/runner/work/project",
+            "metadata": null,
+            "version": 1
+        }"#;
+        let target_dir = parse_target_dir(metadata).expect("Failed to parse metadata");
+        assert_eq!(target_dir, Path::from("/home/runner/work/project/out"));
+        assert!(parse_target_dir("{}").is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn target_dir_flag_takes_precedence() {
+        let args = vec!["--target-dir".to_string(), "out".to_string()];
+        assert_eq!(target_dir(None, &args).await, process::cwd().join("out"));
+    }
+}
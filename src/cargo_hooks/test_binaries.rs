@@ -0,0 +1,152 @@
+use super::install::{canonical_arg_string, fingerprint_build_dir, save_build_dir_if_changed, truncate_for_key};
+use super::{find_flag_value, target_dir, Hook};
+use crate::actions::cache::Entry as CacheEntry;
+use crate::cargo::{CompilerFlags, ToolchainVersion};
+use crate::fingerprinting::Fingerprint;
+use crate::hasher::Blake3 as Blake3Hasher;
+use crate::node::path::Path;
+use crate::node::process;
+use crate::{info, warning, Error};
+use async_trait::async_trait;
+use rustup_toolchain_manifest::HashValue;
+
+const PROFILE_FLAG: &str = "--profile";
+const TARGET_FLAG: &str = "--target";
+const BUILD_TARGET_VAR: &str = "CARGO_BUILD_TARGET";
+
+/// The name of the subdirectory of the target directory that Cargo writes
+/// the output of `profile` to
+fn profile_dir_name(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        _ => profile,
+    }
+}
+
+/// The path relative to the target directory that `cargo test` writes its
+/// build output to given `args`
+fn test_output_dir(args: &[String]) -> Path {
+    let release = args
+        .iter()
+        .take_while(|a| *a != "--")
+        .any(|a| a == "--release" || a == "-r");
+    let profile = find_flag_value(args, PROFILE_FLAG).unwrap_or(if release { "release" } else { "test" });
+    let profile_dir = Path::from(profile_dir_name(profile));
+    let target = find_flag_value(args, TARGET_FLAG)
+        .map(String::from)
+        .or_else(|| process::get_env().remove(BUILD_TARGET_VAR));
+    match target {
+        Some(target) => Path::from(&target).join(&profile_dir),
+        None => profile_dir,
+    }
+}
+
+/// Caches the output directory of the profile used by `cargo test` so that
+/// test binaries need not be compiled from scratch. This is mostly useful
+/// with `--no-run` in workflows which compile and run tests in separate
+/// steps.
+pub struct TestBinaries {
+    hash: HashValue,
+    output_dir: String,
+    fingerprint: Option<Fingerprint>,
+    arg_string: String,
+    flags_string: String,
+    restore_key: Option<String>,
+    toolchain_version_short: String,
+}
+
+impl TestBinaries {
+    pub async fn new(
+        toolchain: Option<&str>,
+        toolchain_version: &ToolchainVersion,
+        args: &[String],
+        flags: &CompilerFlags,
+    ) -> Result<TestBinaries, Error> {
+        use std::hash::Hash as _;
+
+        let mut hasher = Blake3Hasher::default();
+        toolchain_version.long().hash(&mut hasher);
+        let arg_string = canonical_arg_string(args);
+        arg_string.hash(&mut hasher);
+        flags.hash(&mut hasher);
+        let output_dir = target_dir(toolchain, args).await.join(test_output_dir(args));
+        let mut result = TestBinaries {
+            hash: hasher.hash_value(),
+            output_dir: output_dir.to_string(),
+            fingerprint: None,
+            arg_string,
+            flags_string: flags.describe(),
+            restore_key: None,
+            toolchain_version_short: toolchain_version.short().to_string(),
+        };
+        if let Err(e) = result.restore(&output_dir).await {
+            warning!("Unable to restore test binaries from cache: {}", e);
+        }
+        Ok(result)
+    }
+
+    /// Restores the output directory from the cache. Restored files are
+    /// merged with anything already there, such as the output of earlier
+    /// build steps.
+    async fn restore(&mut self, output_dir: &Path) -> Result<(), Error> {
+        if let Some(key) = self.build_cache_entry().restore().await? {
+            info!("Restored test binaries from cache with key {}", key);
+            self.fingerprint = Some(fingerprint_build_dir(output_dir).await?);
+            self.restore_key = Some(key);
+        }
+        Ok(())
+    }
+
+    fn build_cache_entry(&self) -> CacheEntry {
+        use crate::cache_key_builder::{Attribute, CacheKeyBuilder};
+
+        let mut key_builder = CacheKeyBuilder::new("cargo test build artifacts");
+        key_builder.add_key_data(&self.hash);
+        key_builder.set_attribute(Attribute::ToolchainVersion, self.toolchain_version_short.clone());
+        key_builder.set_attribute(Attribute::ArgsTruncated, truncate_for_key(&self.arg_string));
+        if !self.flags_string.is_empty() {
+            key_builder.set_attribute(Attribute::CompilerFlags, truncate_for_key(&self.flags_string));
+        }
+        let mut cache_entry = key_builder.into_entry();
+        cache_entry.path(Path::from(&self.output_dir));
+        cache_entry
+    }
+}
+
+#[async_trait(?Send)]
+impl Hook for TestBinaries {
+    async fn succeeded(&mut self) {
+        save_build_dir_if_changed(
+            &self.build_cache_entry(),
+            &Path::from(&self.output_dir),
+            self.fingerprint.as_ref(),
+            self.restore_key.as_deref(),
+            "Test build artifacts",
+        )
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_output_dir;
+    use crate::node::path::Path;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn output_dir_follows_profile_and_target() {
+        let output_dir = |args: &[&str]| {
+            let args: Vec<String> = args.iter().copied().map(String::from).collect();
+            test_output_dir(&args)
+        };
+        assert_eq!(output_dir(&["--release"]), Path::from("release"));
+        assert_eq!(output_dir(&["--profile", "bench"]), Path::from("release"));
+        assert_eq!(output_dir(&["--profile=ci"]), Path::from("ci"));
+        assert_eq!(
+            output_dir(&["--no-run", "--target", "wasm32-wasi"]),
+            Path::from("wasm32-wasi").join("debug")
+        );
+        assert_eq!(output_dir(&["--no-run", "--", "--release"]).file_name(), "debug");
+    }
+}
@@ -0,0 +1,109 @@
+use super::{target_dir, Hook};
+use crate::actions::core;
+use crate::cargo::ToolchainVersion;
+use crate::node::path::Path;
+use crate::{info, warning};
+use async_trait::async_trait;
+use std::borrow::Cow;
+
+const OUTPUT_NAME: &str = "timings-report";
+const TIMINGS_FLAG: &str = "--timings";
+
+// `--timings` was stabilised in Cargo 1.60
+const MIN_SUPPORTED_MINOR_VERSION: u64 = 60;
+
+/// The minor version of the toolchain, e.g. 70 for 1.70.0
+fn minor_version(version: &ToolchainVersion) -> Option<u64> {
+    version
+        .short()
+        .split_whitespace()
+        .nth(1)
+        .and_then(|v| v.split('.').nth(1))
+        .and_then(|v| v.parse().ok())
+}
+
+/// Requests a build timing report from Cargo and publishes its location as an
+/// output of the action
+pub struct Timings {
+    report_path: String,
+    add_flag: bool,
+}
+
+impl Timings {
+    pub async fn new(toolchain: Option<&str>, args: &[String]) -> Timings {
+        let report_path = target_dir(toolchain, args)
+            .await
+            .join("cargo-timings")
+            .join("cargo-timing.html")
+            .to_string();
+        // The user may already have requested a report in a particular format
+        let add_flag = !args
+            .iter()
+            .take_while(|a| *a != "--")
+            .any(|a| a == TIMINGS_FLAG || a.starts_with("--timings="));
+        Timings { report_path, add_flag }
+    }
+
+    /// Whether the toolchain's Cargo supports `--timings`. Unknown versions
+    /// are assumed to.
+    pub fn is_supported(version: &ToolchainVersion) -> bool {
+        !matches!(minor_version(version), Some(minor) if minor < MIN_SUPPORTED_MINOR_VERSION)
+    }
+
+    async fn publish(&self) {
+        if Path::from(&self.report_path).exists().await {
+            info!("Cargo timing report was written to {}", self.report_path);
+            core::set_output(OUTPUT_NAME, self.report_path.as_str());
+        } else {
+            warning!("Cargo timing report was not found at {}", self.report_path);
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Hook for Timings {
+    fn additional_cargo_options(&self) -> Vec<Cow<str>> {
+        if self.add_flag {
+            vec![TIMINGS_FLAG.into()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    async fn succeeded(&mut self) {
+        self.publish().await;
+    }
+
+    async fn failed(&mut self) {
+        // A report is still written for the units which were built
+        self.publish().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{minor_version, Timings};
+    use crate::cargo::ToolchainVersion;
+    use crate::cargo_hooks::find_flag_value;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn target_dir_is_found_in_args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().copied().map(String::from).collect() };
+        let find = |a: &[&str]| find_flag_value(&args(a), "--target-dir").map(String::from);
+        assert_eq!(find(&["--release", "--target-dir", "out"]).as_deref(), Some("out"));
+        assert_eq!(find(&["--target-dir=out"]).as_deref(), Some("out"));
+        assert_eq!(find(&["--", "--target-dir=out"]), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn old_toolchains_are_unsupported() {
+        let stable = ToolchainVersion::new("rustc 1.70.0 (90c541806 2023-05-31)\nbinary: rustc");
+        let nightly = ToolchainVersion::new("rustc 1.73.0-nightly (8131b9774 2023-08-02)");
+        let old = ToolchainVersion::new("rustc 1.59.0 (9d1b2106e 2022-02-23)");
+        assert_eq!(minor_version(&stable), Some(70));
+        assert!(Timings::is_supported(&stable));
+        assert!(Timings::is_supported(&nightly));
+        assert!(!Timings::is_supported(&old));
+    }
+}
@@ -1,16 +1,19 @@
+use crate::actions::exec::{Command, Stdio};
+use crate::actions::io;
 use crate::dir_tree::{self, Ignores};
+use crate::hasher::Blake3 as Blake3Hasher;
 use crate::node::path::Path;
-use crate::{node, Error};
+use crate::Error;
 use async_trait::async_trait;
 
 #[derive(Debug)]
-struct FindFilesVisitor {
-    name: String,
+struct FindFilesVisitor<'a> {
+    names: &'a [String],
     paths: Vec<Path>,
 }
 
 #[async_trait(?Send)]
-impl dir_tree::Visitor for FindFilesVisitor {
+impl dir_tree::Visitor for FindFilesVisitor<'_> {
     async fn enter_folder(&mut self, _: &Path) -> Result<(), Error> {
         Ok(())
     }
@@ -20,7 +23,7 @@ impl dir_tree::Visitor for FindFilesVisitor {
     }
 
     async fn visit_entry(&mut self, path: &Path, is_file: bool) -> Result<(), Error> {
-        if is_file && path.file_name() == self.name {
+        if is_file && self.names.contains(&path.file_name()) {
             self.paths.push(path.clone());
         }
         Ok(())
@@ -33,9 +36,12 @@ pub struct HashInfo {
     pub bytes: [u8; 32],
 }
 
-pub async fn hash_cargo_lock_files(path: &Path) -> Result<HashInfo, Error> {
+pub const CARGO_LOCK_FILE_NAME: &str = "Cargo.lock";
+
+/// Finds all files under `path` with any of the specified names
+async fn find_files(path: &Path, names: &[String]) -> Result<Vec<String>, Error> {
     let mut visitor = FindFilesVisitor {
-        name: "Cargo.lock".into(),
+        names,
         paths: Vec::new(),
     };
     let ignores = Ignores::default();
@@ -43,13 +49,77 @@ pub async fn hash_cargo_lock_files(path: &Path) -> Result<HashInfo, Error> {
     let mut paths: Vec<_> = visitor.paths.iter().map(Path::to_string).collect();
     // We want the paths in a deterministic order
     paths.sort();
-    let mut hasher = blake3::Hasher::new();
+    Ok(paths)
+}
+
+pub async fn ensure_cargo_lock_present(path: &Path) -> Result<(), Error> {
+    if find_files(path, &[CARGO_LOCK_FILE_NAME.into()]).await?.is_empty() {
+        Err(Error::MissingLockfile(path.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Hashes the contents of all files under `path` with any of the specified
+/// names
+pub async fn hash_files_named(path: &Path, names: &[String]) -> Result<HashInfo, Error> {
+    let paths = find_files(path, names).await?;
+    let mut hasher = Blake3Hasher::default();
     for path in &paths {
-        let file_content = node::fs::read_file(path.as_str()).await?;
-        hasher.update(&file_content);
+        hasher.update_from_file(&Path::from(path.as_str())).await?;
     }
     let result = HashInfo {
         num_files: paths.len(),
+        bytes: hasher.inner().finalize().into(),
+    };
+    Ok(result)
+}
+
+/// Hashes the set of resolved non-workspace packages as reported by `cargo
+/// metadata`. Unlike hashing the `Cargo.lock` files, this is unaffected by
+/// changes to formatting or comments.
+///
+/// Cargo is not permitted to update the lockfile or access the network, so
+/// this fails rather than hashing a different dependency graph to the one the
+/// lockfile records.
+pub async fn hash_dependency_graph(path: &Path) -> Result<HashInfo, Error> {
+    use parking_lot::Mutex;
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    let cargo_path = io::which("cargo", true).await.map_err(Error::Js)?;
+    let output: Arc<Mutex<String>> = Arc::default();
+    let output_captured = output.clone();
+    Command::from(&cargo_path)
+        .args(["metadata", "--format-version", "1", "--locked", "--offline"])
+        .current_dir(path)
+        .outline(move |line| {
+            let mut out = output_captured.lock();
+            *out += line;
+            *out += "\n";
+        })
+        .stdout(Stdio::null())
+        .exec_checked()
+        .await?;
+    let metadata: cargo_metadata::Metadata = serde_json::from_str(&output.lock())?;
+
+    // Workspace members have no source. Their own versions should not affect
+    // what we cache.
+    let packages: BTreeSet<String> = metadata
+        .packages
+        .iter()
+        .filter_map(|package| {
+            let source = package.source.as_ref()?;
+            Some(format!("{} {} {}", package.name, package.version, source))
+        })
+        .collect();
+    let mut hasher = blake3::Hasher::new();
+    for package in &packages {
+        hasher.update(package.as_bytes());
+        hasher.update(b"\n");
+    }
+    let result = HashInfo {
+        num_files: packages.len(),
         bytes: hasher.finalize().into(),
     };
     Ok(result)
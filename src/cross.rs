@@ -1,6 +1,52 @@
+use crate::actions::exec::Command;
 use crate::actions::io;
+use crate::input_manager::{self, Input};
 use crate::node::path::Path;
-use crate::{debug, info, input_manager, Cargo, Error};
+use crate::{debug, info, node, warning, Cargo, Error};
+use cargo_metadata::semver::{Version, VersionReq};
+
+/// The environment variable `cross` reads to override the container image for
+/// `target`
+fn image_env_var(target: &str) -> String {
+    format!("CROSS_TARGET_{}_IMAGE", target.to_uppercase().replace(['-', '.'], "_"))
+}
+
+/// Finds the value of the last `--target` argument
+fn find_target(args: &[String]) -> Option<&str> {
+    let mut result = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--target" {
+            result = iter.next().map(String::as_str);
+        } else if let Some(target) = arg.strip_prefix("--target=") {
+            result = Some(target);
+        }
+    }
+    result
+}
+
+/// Parses `cross-version` the way `cargo install --version` does, where a
+/// full version without an operator must match exactly
+fn parse_version_req(requested: &str) -> Result<VersionReq, Error> {
+    let requirement = if Version::parse(requested).is_ok() {
+        VersionReq::parse(&format!("={}", requested))
+    } else {
+        VersionReq::parse(requested)
+    };
+    requirement.map_err(|_| Error::ParseCrossVersion(requested.to_string()))
+}
+
+/// Whether a version reported by `cross --version` satisfies `requested`.
+/// Builds from git follow the version with the commit hash and date.
+fn version_matches(reported: &str, requested: &VersionReq) -> bool {
+    reported
+        .split_whitespace()
+        .next()
+        .and_then(|version| Version::parse(version).ok())
+        .is_some_and(|version| requested.matches(&version))
+}
 
 #[derive(Clone, Debug)]
 pub struct Cross {
@@ -16,24 +62,124 @@ impl Cross {
     }
 
     pub async fn get_or_install(input_manager: &input_manager::Manager) -> Result<Cross, Error> {
-        match Self::get().await {
-            Ok(cross) => Ok(cross),
+        let requested_version = input_manager.get(Input::CrossVersion);
+        let requirement = requested_version.map(parse_version_req).transpose()?;
+        let cross = match Self::get().await {
+            Ok(cross) => match requested_version.zip(requirement) {
+                Some((requested, requirement)) if !version_matches(&cross.version().await?, &requirement) => {
+                    info!(
+                        "Installed cross does not satisfy version {}. Installing it now...",
+                        requested
+                    );
+                    Self::install(requested_version, input_manager).await?
+                }
+                _ => cross,
+            },
             Err(e) => {
                 info!("Unable to find cross. Installing it now...");
                 debug!("Attempting to locate cross returned this error: {}", e);
-                Self::install(input_manager).await
+                Self::install(requested_version, input_manager).await?
             }
-        }
+        };
+        info!("Using cross version {}", cross.version().await?);
+        Ok(cross)
     }
 
-    async fn install(input_manager: &input_manager::Manager) -> Result<Cross, Error> {
+    async fn install(version: Option<&str>, input_manager: &input_manager::Manager) -> Result<Cross, Error> {
         let mut cargo = Cargo::from_environment().await?;
-        let args = ["cross"];
+        let mut args = vec!["cross"];
+        // Since the version is part of the arguments, it also forms part of the key
+        // used to cache the build artifacts
+        if let Some(version) = version {
+            args.extend(["--version", version]);
+        }
         cargo.run(None, "install", args, input_manager).await?;
         Self::get().await
     }
 
+    /// Returns the version reported by `cross --version`
+    pub async fn version(&self) -> Result<String, Error> {
+        use crate::actions::exec::Stdio;
+        use parking_lot::Mutex;
+        use std::sync::Arc;
+
+        let first_line: Arc<Mutex<Option<String>>> = Arc::default();
+        {
+            let first_line = Arc::clone(&first_line);
+            Command::from(&self.path)
+                .arg("--version")
+                .outline(move |line| {
+                    first_line.lock().get_or_insert_with(|| line.to_string());
+                })
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .exec_checked()
+                .await?;
+        }
+        let first_line = first_line.lock().take().unwrap_or_default();
+        let version = first_line.trim();
+        let version = version.strip_prefix("cross ").unwrap_or(version);
+        Ok(version.to_string())
+    }
+
+    /// Directs `cross` to use `image` for the target named in `args`
+    pub fn set_image(image: &str, args: &[String]) {
+        if let Some(target) = find_target(args) {
+            let name = image_env_var(target);
+            info!("Setting {} to {}", name, image);
+            node::process::set_var(&name, image);
+        } else {
+            warning!(
+                "Ignoring cross image {} since no target was specified with --target",
+                image
+            );
+        }
+    }
+
     pub fn get_path(&self) -> Path {
         self.path.clone()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{find_target, image_env_var, parse_version_req, version_matches};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn image_env_var_is_derived_from_target() {
+        assert_eq!(
+            image_env_var("aarch64-unknown-linux-gnu"),
+            "CROSS_TARGET_AARCH64_UNKNOWN_LINUX_GNU_IMAGE"
+        );
+        assert_eq!(
+            image_env_var("thumbv7em-none-eabi"),
+            "CROSS_TARGET_THUMBV7EM_NONE_EABI_IMAGE"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn target_is_found_in_args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().copied().map(String::from).collect() };
+        assert_eq!(find_target(&args(&["--release", "--target", "a"])), Some("a"));
+        assert_eq!(find_target(&args(&["--target=b", "--release"])), Some("b"));
+        assert_eq!(find_target(&args(&["--release", "--", "--target=c"])), None);
+        assert_eq!(find_target(&args(&["--target"])), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn reported_versions_are_matched_against_requests() {
+        let matches = |reported, requested| {
+            let requested = parse_version_req(requested).expect("Failed to parse version requirement");
+            version_matches(reported, &requested)
+        };
+        assert!(matches("0.2.5", "0.2.5"));
+        assert!(!matches("0.2.5", "0.2.4"));
+        assert!(matches("0.2.5", "0.2"));
+        assert!(matches("0.2.5 (19be834 2023-02-06)", "0.2"));
+        assert!(!matches("0.2.5 (19be834 2023-02-06)", "0.3"));
+        assert!(matches("0.2.5", "~0.2.1"));
+        assert!(!matches("not a version", "0.2"));
+        assert!(parse_version_req("latest").is_err());
+    }
+}
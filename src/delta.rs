@@ -1,4 +1,4 @@
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, strum::Display)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, strum::Display, strum::EnumIter)]
 pub enum Action {
     Added,
     Removed,
@@ -16,3 +16,49 @@ where
     }
     result
 }
+
+/// Renders counts of each type of change, listing at most `max_per_action`
+/// items for each
+pub fn render_summary<S>(items: &[(S, Action)], max_per_action: usize) -> String
+where
+    S: std::fmt::Display,
+{
+    use std::fmt::Write as _;
+    use strum::IntoEnumIterator as _;
+
+    let mut result = String::new();
+    for action in Action::iter() {
+        let count = items.iter().filter(|(_, a)| *a == action).count();
+        if count == 0 {
+            continue;
+        }
+        writeln!(&mut result, "{}: {}", action, count).expect("Unable to write to string");
+        for (path, _) in items.iter().filter(|(_, a)| *a == action).take(max_per_action) {
+            writeln!(&mut result, "  {}", path).expect("Unable to write to string");
+        }
+        if count > max_per_action {
+            writeln!(&mut result, "  ... and {} more", count - max_per_action).expect("Unable to write to string");
+        }
+    }
+    writeln!(&mut result, "Total: {} change(s)", items.len()).expect("Unable to write to string");
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render_summary, Action};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn summary_elides_excess_items() {
+        let items = [
+            ("a", Action::Added),
+            ("b", Action::Added),
+            ("c", Action::Added),
+            ("d", Action::Changed),
+        ];
+        let summary = render_summary(&items, 2);
+        let expected = "Added: 3\n  a\n  b\n  ... and 1 more\nChanged: 1\n  d\nTotal: 4 change(s)\n";
+        assert_eq!(summary, expected);
+    }
+}
@@ -141,3 +141,114 @@ pub async fn match_relative_paths(path: &Path, matcher: &PathMatch, output_relat
     apply_visitor(path, &ignores, &mut visitor).await?;
     Ok(visitor.matching_paths)
 }
+
+#[derive(Debug)]
+struct ChmodVisitor {
+    file_mode: u16,
+    dir_mode: u16,
+}
+
+#[async_trait(?Send)]
+impl Visitor for ChmodVisitor {
+    async fn enter_folder(&mut self, path: &Path) -> Result<(), Error> {
+        fs::chmod(path, self.dir_mode).await?;
+        Ok(())
+    }
+
+    async fn visit_entry(&mut self, path: &Path, is_file: bool) -> Result<(), Error> {
+        if is_file {
+            // chmod follows symlinks, so we leave them (and whatever they point to) alone
+            let metadata = fs::symlink_metadata(path).await?;
+            if !metadata.file_type().is_symlink() {
+                fs::chmod(path, self.file_mode).await?;
+            }
+        } else {
+            panic!("Expected to descend into all directories");
+        }
+        Ok(())
+    }
+
+    async fn exit_folder(&mut self, _: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct SizeVisitor {
+    num_bytes: u64,
+}
+
+#[async_trait(?Send)]
+impl Visitor for SizeVisitor {
+    async fn enter_folder(&mut self, _: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn visit_entry(&mut self, path: &Path, _is_file: bool) -> Result<(), Error> {
+        self.num_bytes += fs::symlink_metadata(path).await?.len();
+        Ok(())
+    }
+
+    async fn exit_folder(&mut self, _: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The total size in bytes of the files under `path`, or of `path` itself if
+/// it is a file. Symlinks are not followed.
+pub async fn total_file_size(path: &Path) -> Result<u64, Error> {
+    let mut visitor = SizeVisitor::default();
+    let ignores = Ignores::default();
+    apply_visitor(path, &ignores, &mut visitor).await?;
+    Ok(visitor.num_bytes)
+}
+
+/// Moves the contents of `from` into `to`, replacing any files which exist
+/// in both, then removes `from`
+#[async_recursion(?Send)]
+pub async fn overlay_and_move_dir(from: &Path, to: &Path) -> Result<(), Error> {
+    fs::create_dir_all(to).await?;
+    {
+        let dir = fs::read_dir(from).await?;
+        for entry in dir {
+            let from = entry.path();
+            let to = to.join(&entry.file_name());
+            let file_type = entry.file_type();
+            if file_type.is_dir() {
+                overlay_and_move_dir(&from, &to).await?;
+            } else {
+                fs::rename(&from, &to).await?;
+            }
+        }
+    }
+    fs::remove_dir(from).await?;
+    Ok(())
+}
+
+/// Recursively sets the mode of all files and folders under `path`, skipping
+/// symlinks
+pub async fn chmod_tree(path: &Path, file_mode: u16, dir_mode: u16) -> Result<(), Error> {
+    let mut visitor = ChmodVisitor { file_mode, dir_mode };
+    let ignores = Ignores::default();
+    apply_visitor(path, &ignores, &mut visitor).await?;
+    Ok(())
+}
+
+/// Marks everything in a folder of binaries as executable, if the folder
+/// exists. Executable bits can be lost when files are restored from a cache.
+pub async fn ensure_executable(path: &Path) -> Result<(), Error> {
+    const EXECUTABLE_MODE: u16 = 0o755;
+
+    if path.exists().await {
+        chmod_tree(path, EXECUTABLE_MODE, EXECUTABLE_MODE).await?;
+        // This can still fail if the file system is mounted without permission to
+        // execute files
+        for entry in fs::read_dir(path).await? {
+            let entry_path = entry.path();
+            if entry.file_type().is_file() && !fs::access(&entry_path, fs::X_OK).await {
+                crate::warning!("{} is not executable despite having its mode set", entry_path);
+            }
+        }
+    }
+    Ok(())
+}
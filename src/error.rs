@@ -1,10 +1,12 @@
+use crate::actions::core::Annotation;
+use crate::node::path::Path;
 use crate::package_manifest;
 use thiserror::Error;
 use wasm_bindgen::JsValue;
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("{0:?}")]
+    #[error("{}", describe_js_value(.0))]
     Js(JsValue),
 
     #[error("Unable to parse option `{0}`, which was supplied as `{1}`")]
@@ -25,6 +27,15 @@ pub enum Error {
     #[error("Manifest error: {0}")]
     ManifestError(#[from] rustup_toolchain_manifest::Error),
 
+    #[error("No manifest exists at {0}. If this is a dated toolchain, no release may have been made on that date.")]
+    ManifestNotFound(String),
+
+    #[error("Unknown component `{0}`. Valid components are: {1}")]
+    UnknownComponent(String, String),
+
+    #[error("Unknown target `{0}`. Valid targets include: {1}")]
+    UnknownTarget(String, String),
+
     #[error("Unsupported platform: {0}")]
     UnsupportedPlatform(String),
 
@@ -37,9 +48,21 @@ pub enum Error {
     #[error("Unable to parse item to cache: {0}")]
     ParseCacheableItem(String),
 
+    #[error("Items to cache in {0} must either all be excluded with '!' or none of them: {1}")]
+    MixedCacheableItems(String, String),
+
     #[error("Unable to parse cross-plaform-sharing: {0}")]
     ParseCrossPlatformSharing(String),
 
+    #[error("Unable to parse log-level: {0}")]
+    ParseLogLevel(String),
+
+    #[error("Unable to parse cache-backend: {0}")]
+    ParseCacheBackend(String),
+
+    #[error("Unable to parse cross-version: {0}")]
+    ParseCrossVersion(String),
+
     #[error("Unable to parse duration: {0}")]
     DurationParse(#[from] humantime::DurationError),
 
@@ -49,17 +72,165 @@ pub enum Error {
     #[error("Path does not exist: {0}")]
     PathDoesNotExist(String),
 
+    #[error("Path is not writable: {0}")]
+    PathNotWritable(String),
+
+    #[error("Path {path} is not inside {root}")]
+    PathNotInside { path: String, root: String },
+
     #[error("Error during path match construction: {0}")]
     PathMatchPatternError(simple_path_match::Error),
 
     #[error("Required input was not supplied: {0}")]
     MissingInput(String),
 
+    #[error("Required environment variables are not set: {names}. {cause}")]
+    MissingEnvironment { names: String, cause: String },
+
+    #[error("No rust-version is declared by the package or workspace at {0}")]
+    MissingRustVersion(String),
+
+    #[error("Unable to parse rust-version: {0}")]
+    RustVersionParse(String),
+
     #[error("Toolchain install backend does not support {0} functionality")]
     ToolchainInstallFunctionality(String),
 
+    #[error("TOML deserialization error: {0}")]
+    TomlParse(#[from] toml::de::Error),
+
     #[error("Postcard serialization/deserialization error: {0}")]
     SerdePostcard(#[from] postcard::Error),
+
+    #[error("Command `{command}` failed with exit code {code}")]
+    CommandFailed { command: String, code: i32 },
+
+    #[error("No Cargo.lock file was found under {0}. Is it excluded from version control?")]
+    MissingLockfile(String),
+
+    #[error("{0}: {1}")]
+    Context(String, Box<Error>),
+
+    #[error("{0}: {1}")]
+    Located(Location, Box<Error>),
+}
+
+impl Error {
+    /// The location of the innermost error which has one
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Error::Context(_, inner) => inner.location(),
+            Error::Located(location, inner) => inner.location().or(Some(location)),
+            _ => None,
+        }
+    }
+
+    /// Builds an error annotation pointing at the location of the error, if
+    /// known
+    pub fn to_annotation(&self) -> Option<Annotation> {
+        self.location().map(|location| {
+            let mut annotation = Annotation::from(self.to_string());
+            annotation.file(&location.path);
+            if let Some(line) = location.line {
+                annotation.start_line(line);
+            }
+            if let Some(column) = location.column {
+                annotation.start_column(column);
+            }
+            annotation
+        })
+    }
+}
+
+/// A position within a file. Lines and columns start from 1.
+#[derive(Clone, Debug)]
+pub struct Location {
+    pub path: Path,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Location {
+    pub fn new(path: &Path) -> Location {
+        Location {
+            path: path.clone(),
+            line: None,
+            column: None,
+        }
+    }
+
+    pub fn line(mut self, line: Option<usize>) -> Location {
+        self.line = line;
+        self
+    }
+
+    pub fn column(mut self, column: Option<usize>) -> Location {
+        self.column = column;
+        self
+    }
+
+    /// The location of a TOML parse error in the file at `path`
+    pub fn of_toml_error(path: &Path, error: &toml::de::Error) -> Location {
+        let line_col = error.line_col();
+        Location::new(path)
+            .line(line_col.map(|(line, _)| line + 1))
+            .column(line_col.map(|(_, column)| column + 1))
+    }
+
+    /// Attaches this location to `error`
+    pub fn wrap<E: Into<Error>>(self, error: E) -> Error {
+        Error::Located(self, Box::new(error.into()))
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders a value thrown by JavaScript for humans. For `Error` objects this
+/// is the name and message without the stack, which is only included in the
+/// `Debug` form.
+fn describe_js_value(value: &JsValue) -> String {
+    use wasm_bindgen::JsCast as _;
+
+    if let Some(error) = value.dyn_ref::<js_sys::Error>() {
+        String::from(error.to_string())
+    } else if let Some(string) = value.as_string() {
+        string
+    } else if let Some(message) = js_sys::Reflect::get(value, &"message".into())
+        .ok()
+        .and_then(|m| m.as_string())
+    {
+        message
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+/// Adds a description of the operation that was being performed to an error
+pub trait Context<T> {
+    fn context<S: Into<String>>(self, context: S) -> Result<T, Error>;
+
+    fn with_context<S: Into<String>, F: FnOnce() -> S>(self, context: F) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context<S: Into<String>>(self, context: S) -> Result<T, Error> {
+        self.map_err(|e| Error::Context(context.into(), Box::new(e.into())))
+    }
+
+    fn with_context<S: Into<String>, F: FnOnce() -> S>(self, context: F) -> Result<T, Error> {
+        self.map_err(|e| Error::Context(context().into(), Box::new(e.into())))
+    }
 }
 
 impl From<JsValue> for Error {
@@ -73,3 +244,28 @@ impl From<simple_path_match::Error> for Error {
         Error::PathMatchPatternError(value)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Error;
+    use wasm_bindgen::{JsError, JsValue};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn js_errors_display_their_message() {
+        let error = Error::Js(JsError::new("something broke").into());
+        assert_eq!(error.to_string(), "Error: something broke");
+
+        let error = Error::Js(js_sys::TypeError::new("not a function").into());
+        assert_eq!(error.to_string(), "TypeError: not a function");
+    }
+
+    #[wasm_bindgen_test]
+    fn other_js_values_are_displayed() {
+        assert_eq!(Error::Js(JsValue::from_str("plain")).to_string(), "plain");
+        let object = js_sys::Object::new();
+        js_sys::Reflect::set(&object, &"message".into(), &"from object".into()).expect("Unable to set property");
+        assert_eq!(Error::Js(object.into()).to_string(), "from object");
+        assert_eq!(Error::Js(JsValue::from_f64(3.0)).to_string(), "JsValue(3)");
+    }
+}
@@ -1,19 +1,81 @@
 use crate::delta::Action as DeltaAction;
 pub use crate::dir_tree::Ignores;
+use crate::hasher::Blake3 as Blake3Hasher;
 use crate::node::fs;
 use crate::node::path::{self, Path};
-use crate::{dir_tree, Error};
+use crate::{dir_tree, info, node, warning, Error};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use itertools::{Either, EitherOrBoth};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::collections::hash_map::DefaultHasher;
-use std::collections::{btree_map, BTreeMap, VecDeque};
+use std::collections::{btree_map, BTreeMap, HashMap, VecDeque};
 use std::hash::{Hash, Hasher};
 
 const ROOT_NAME: &str = ".";
 
+/// Incremented whenever the way content hashes are computed changes. Stored
+/// fingerprints should be kept in locations which include this, so that
+/// fingerprints computed by earlier versions are recomputed rather than
+/// compared against incompatible hashes.
+pub const FORMAT_VERSION: u32 = 2;
+
+// How many files to visit between checks of whether to report progress
+const PROGRESS_CHECK_INTERVAL: usize = 1000;
+
+// Minimum number of seconds between progress reports
+const PROGRESS_REPORT_PERIOD_SECS: i64 = 5;
+
+static HASH_XATTRS: Mutex<bool> = parking_lot::const_mutex(false);
+
+static IGNORE_VOLATILE: Mutex<bool> = parking_lot::const_mutex(true);
+
+static IGNORE_OWNERSHIP: Mutex<bool> = parking_lot::const_mutex(true);
+
+/// Sets whether subsequently computed fingerprints include extended
+/// attributes. These are only read on Linux and only if `getfattr` is
+/// available.
+pub fn set_hash_xattrs(enabled: bool) {
+    *HASH_XATTRS.lock() = enabled;
+}
+
+/// Sets whether files known to change without their build outputs changing
+/// are ignored by [`default_volatile_ignores`]
+pub fn set_ignore_volatile(enabled: bool) {
+    *IGNORE_VOLATILE.lock() = enabled;
+}
+
+/// Sets whether file ownership (user and group IDs) is ignored when
+/// fingerprinting and comparing files. The user running builds can differ
+/// between runner images, which would otherwise cause every restored file to
+/// appear changed.
+pub fn set_ignore_ownership(enabled: bool) {
+    *IGNORE_OWNERSHIP.lock() = enabled;
+}
+
+/// Files within a Cargo target directory which are rewritten by Cargo even
+/// when nothing was rebuilt. Fingerprinting them would cause build artifacts
+/// to always appear modified. Empty if disabled via [`set_ignore_volatile`].
+pub fn default_volatile_ignores() -> Ignores {
+    let mut ignores = Ignores::default();
+    if *IGNORE_VOLATILE.lock() {
+        // Cargo's cache of rustc's version information. This is invalidated by
+        // (among other things) the modification time of the rustc binary, which
+        // differs between toolchain installations.
+        //
+        // https://github.com/rust-lang/cargo/blob/70898e522116f6c23971e2a554b2dc85fd4c84cd/src/cargo/util/rustc.rs#L306
+        ignores.add(1, ".rustc_info.json");
+
+        // The lock Cargo takes on a profile's output directory, which is
+        // rewritten by every build. This is at depth 3 when building for an
+        // explicit target.
+        ignores.add(2, ".cargo-lock");
+        ignores.add(3, ".cargo-lock");
+    }
+    ignores
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
 struct Metadata {
     uid: u64,
@@ -22,6 +84,7 @@ struct Metadata {
     mode: u64,
     modified: DateTime<Utc>,
     accessed: DateTime<Utc>,
+    xattrs_hash: Option<u64>,
 }
 
 impl From<&fs::Metadata> for Metadata {
@@ -33,6 +96,7 @@ impl From<&fs::Metadata> for Metadata {
             mode: stats.mode(),
             modified: stats.modified(),
             accessed: stats.accessed(),
+            xattrs_hash: None,
         }
     }
 }
@@ -40,19 +104,23 @@ impl From<&fs::Metadata> for Metadata {
 impl Metadata {
     fn hash_noteworthy<H: Hasher>(&self, hasher: &mut H) {
         // Noteworthy basically means anything that would need an rsync
-        self.uid.hash(hasher);
-        self.gid.hash(hasher);
+        if !*IGNORE_OWNERSHIP.lock() {
+            self.uid.hash(hasher);
+            self.gid.hash(hasher);
+        }
         self.len.hash(hasher);
         self.mode.hash(hasher);
         self.modified.hash(hasher);
+        self.xattrs_hash.hash(hasher);
     }
 
     fn equal_noteworthy(&self, other: &Metadata) -> bool {
-        self.uid == other.uid
-            && self.gid == other.gid
+        let ownership_equal = *IGNORE_OWNERSHIP.lock() || (self.uid == other.uid && self.gid == other.gid);
+        ownership_equal
             && self.len == other.len
             && self.mode == other.mode
             && self.modified == other.modified
+            && self.xattrs_hash == other.xattrs_hash
     }
 }
 
@@ -115,7 +183,9 @@ impl Fingerprint {
     }
 
     fn compute_entry_hash(entry: &Entry) -> u64 {
-        let mut hasher = DefaultHasher::default();
+        // The standard library's default hasher may change between Rust
+        // releases, which would make every stored fingerprint appear modified
+        let mut hasher = Blake3Hasher::default();
         match entry {
             Entry::File(metadata) => {
                 metadata.hash_noteworthy(&mut hasher);
@@ -139,6 +209,13 @@ impl Fingerprint {
         self.accessed
     }
 
+    /// The total length of all files covered by the fingerprint
+    pub fn size(&self) -> u64 {
+        self.sorted_file_paths_and_metadata()
+            .map(|(_, metadata)| metadata.len)
+            .sum()
+    }
+
     fn sorted_file_paths_and_metadata(&self) -> FlatteningIterator<'_> {
         let root_content = match &self.root {
             Entry::File(metadata) => Either::Right(*metadata),
@@ -178,9 +255,30 @@ struct BuildFingerprintVisitor {
     stack: VecDeque<Entry>,
     modified: Option<DateTime<Utc>>,
     accessed: Option<DateTime<Utc>>,
+    xattr_hashes: HashMap<String, u64>,
+    files_visited: usize,
+    next_progress_check: usize,
+    last_report: DateTime<Utc>,
 }
 
 impl BuildFingerprintVisitor {
+    fn report_progress(&mut self, path: &Path) {
+        self.files_visited += 1;
+        if self.files_visited < self.next_progress_check {
+            return;
+        }
+        self.next_progress_check += PROGRESS_CHECK_INTERVAL;
+        // Small trees are fingerprinted before the first report is due
+        let now = Utc::now();
+        if now - self.last_report >= chrono::Duration::seconds(PROGRESS_REPORT_PERIOD_SECS) {
+            info!(
+                "Fingerprinted {} files so far (currently at {})",
+                self.files_visited, path
+            );
+            self.last_report = now;
+        }
+    }
+
     fn push_file(&mut self, file_name: String, metadata: Metadata) {
         let to_insert = Entry::File(metadata);
         match self.stack.back_mut() {
@@ -222,7 +320,8 @@ impl dir_tree::Visitor for BuildFingerprintVisitor {
     async fn visit_entry(&mut self, path: &Path, is_file: bool) -> Result<(), Error> {
         if is_file {
             let stats = fs::symlink_metadata(path).await?;
-            let metadata = Metadata::from(&stats);
+            let mut metadata = Metadata::from(&stats);
+            metadata.xattrs_hash = self.xattr_hashes.get(&path.to_string()).copied();
             self.modified = Some(match self.modified {
                 None => metadata.modified,
                 Some(latest) => std::cmp::max(latest, metadata.modified),
@@ -233,6 +332,7 @@ impl dir_tree::Visitor for BuildFingerprintVisitor {
             });
             let file_name = path.file_name();
             self.push_file(file_name, metadata);
+            self.report_progress(path);
         } else {
             panic!("Expected to descend into all directories");
         }
@@ -240,11 +340,74 @@ impl dir_tree::Visitor for BuildFingerprintVisitor {
     }
 }
 
+/// Parses the output of `getfattr --dump`, returning a hash of the extended
+/// attributes of each listed file
+fn parse_getfattr_output<'a, I: IntoIterator<Item = &'a str>>(lines: I) -> HashMap<String, u64> {
+    fn finish(result: &mut HashMap<String, u64>, current: Option<(&str, Vec<&str>)>) {
+        if let Some((file, mut attributes)) = current {
+            attributes.sort_unstable();
+            let mut hasher = Blake3Hasher::default();
+            attributes.hash(&mut hasher);
+            result.insert(file.to_string(), hasher.finish());
+        }
+    }
+
+    let mut result = HashMap::new();
+    let mut current = None;
+    for line in lines {
+        if let Some(file) = line.strip_prefix("# file: ") {
+            finish(&mut result, current.take());
+            current = Some((file, Vec::new()));
+        } else if !line.is_empty() {
+            if let Some((_, attributes)) = current.as_mut() {
+                attributes.push(line);
+            }
+        }
+    }
+    finish(&mut result, current);
+    result
+}
+
+async fn read_xattr_hashes(path: &Path) -> HashMap<String, u64> {
+    use crate::actions::exec::{Command, Stdio};
+    use crate::actions::io;
+    use std::sync::Arc;
+
+    if !*HASH_XATTRS.lock() || node::os::platform() != "linux" {
+        return HashMap::new();
+    }
+    let lines: Arc<Mutex<Vec<String>>> = Arc::default();
+    let result = match io::which("getfattr", true).await.map_err(Error::Js) {
+        Ok(getfattr) => {
+            let lines = lines.clone();
+            Command::from(&getfattr)
+                .args(["--recursive", "--physical", "--no-dereference", "--absolute-names"])
+                .args(["--dump", "--match=-", "--encoding=hex"])
+                .arg(path)
+                .outline(move |line| lines.lock().push(line.to_string()))
+                .stdout(Stdio::null())
+                .exec_checked()
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        warning!("Unable to read extended attributes under {}: {}", path, e);
+        return HashMap::new();
+    }
+    let lines = lines.lock();
+    parse_getfattr_output(lines.iter().map(String::as_str))
+}
+
 pub async fn fingerprint_path_with_ignores(path: &Path, ignores: &Ignores) -> Result<Fingerprint, Error> {
     let mut visitor = BuildFingerprintVisitor {
         stack: VecDeque::new(),
         modified: None,
         accessed: None,
+        xattr_hashes: read_xattr_hashes(path).await,
+        files_visited: 0,
+        next_progress_check: PROGRESS_CHECK_INTERVAL,
+        last_report: Utc::now(),
     };
     dir_tree::apply_visitor(path, ignores, &mut visitor).await?;
     assert_eq!(visitor.stack.len(), 1, "Tree data stack should only have single entry");
@@ -261,3 +424,29 @@ pub async fn fingerprint_path_with_ignores(path: &Path, ignores: &Ignores) -> Re
     };
     Ok(result)
 }
+
+#[cfg(test)]
+mod test {
+    use super::parse_getfattr_output;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn getfattr_output_parsing() {
+        let output = [
+            "# file: /a",
+            "user.x=0x01",
+            "security.capability=0x02",
+            "",
+            "# file: /b",
+            "security.capability=0x02",
+            "user.x=0x01",
+            "",
+            "# file: /c",
+            "user.x=0x03",
+        ];
+        let hashes = parse_getfattr_output(output);
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(hashes["/a"], hashes["/b"]);
+        assert_ne!(hashes["/a"], hashes["/c"]);
+    }
+}
@@ -1,6 +1,10 @@
+use crate::node::path::Path;
+use crate::{node, Error};
 use rustup_toolchain_manifest::HashValue;
 
-#[derive(Debug, Default)]
+const FILE_READ_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Clone, Debug, Default)]
 pub struct Blake3 {
     inner: blake3::Hasher,
 }
@@ -27,4 +31,30 @@ impl Blake3 {
         let hash = self.inner.finalize();
         HashValue::from_bytes(&hash.as_bytes()[..])
     }
+
+    /// Feeds the contents of the file at `path` to the hasher without reading
+    /// it all into memory
+    pub async fn update_from_file(&mut self, path: &Path) -> Result<(), Error> {
+        let mut file = node::fs::open(path).await?;
+        let mut chunk = vec![0u8; FILE_READ_CHUNK_SIZE];
+        let result = loop {
+            match file.read(&mut chunk).await {
+                Ok(0) => break Ok(()),
+                Ok(bytes_read) => {
+                    self.inner.update(&chunk[..bytes_read]);
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        file.close().await?;
+        result?;
+        Ok(())
+    }
+}
+
+/// Computes the Blake3 hash of the contents of the file at `path`
+pub async fn hash_file(path: &Path) -> Result<HashValue, Error> {
+    let mut hasher = Blake3::default();
+    hasher.update_from_file(path).await?;
+    Ok(hasher.hash_value())
 }
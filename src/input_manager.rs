@@ -1,32 +1,121 @@
 use crate::actions::core;
-use crate::Error;
+use crate::error::{Context as _, Location};
+use crate::node::path::Path;
+use crate::{node, warning, Error};
 use parking_lot::Mutex;
 use std::collections::{HashMap, HashSet};
 use strum::{EnumIter, IntoEnumIterator as _, IntoStaticStr};
 
 #[derive(IntoStaticStr, Clone, Copy, Debug, EnumIter, Eq, Hash, PartialEq, strum::Display)]
 pub enum Input {
+    #[strum(serialize = "allow-cargo-home-change")]
+    AllowCargoHomeChange,
+
+    #[strum(serialize = "allow-unknown-targets")]
+    AllowUnknownTargets,
+
     #[strum(serialize = "annotations")]
     Annotations,
 
+    #[strum(serialize = "annotations-echo")]
+    AnnotationsEcho,
+
     #[strum(serialize = "args")]
     Args,
 
+    #[strum(serialize = "auto-install-components")]
+    AutoInstallComponents,
+
+    #[strum(serialize = "built-artifacts")]
+    BuiltArtifacts,
+
+    #[strum(serialize = "cache-backend")]
+    CacheBackend,
+
+    #[strum(serialize = "cache-download-concurrency")]
+    CacheDownloadConcurrency,
+
+    #[strum(serialize = "cache-download-timeout")]
+    CacheDownloadTimeout,
+
     #[strum(serialize = "cache-only")]
     CacheOnly,
 
+    #[strum(serialize = "cache-registry-src")]
+    CacheRegistrySrc,
+
+    #[strum(serialize = "cache-required")]
+    CacheRequired,
+
+    #[strum(serialize = "cache-segment-timeout")]
+    CacheSegmentTimeout,
+
+    #[strum(serialize = "cache-test-binaries")]
+    CacheTestBinaries,
+
+    #[strum(serialize = "cache-upload-chunk-size")]
+    CacheUploadChunkSize,
+
+    #[strum(serialize = "cache-upload-concurrency")]
+    CacheUploadConcurrency,
+
+    #[strum(serialize = "cargo-home")]
+    CargoHome,
+
+    #[strum(serialize = "cargo-keep-going")]
+    CargoKeepGoing,
+
+    #[strum(serialize = "cargo-timings")]
+    CargoTimings,
+
     #[strum(serialize = "command")]
     Command,
 
     #[strum(serialize = "components")]
     Components,
 
+    #[strum(serialize = "config-file")]
+    ConfigFile,
+
+    #[strum(serialize = "cross-image")]
+    CrossImage,
+
     #[strum(serialize = "cross-platform-sharing")]
     CrossPlatformSharing,
 
+    #[strum(serialize = "cross-version")]
+    CrossVersion,
+
     #[strum(serialize = "default")]
     Default,
 
+    #[strum(serialize = "download-concurrency")]
+    DownloadConcurrency,
+
+    #[strum(serialize = "fingerprint-ignore-volatile")]
+    FingerprintIgnoreVolatile,
+
+    #[strum(serialize = "fingerprint-xattrs")]
+    FingerprintXattrs,
+
+    #[strum(serialize = "github-token")]
+    GithubToken,
+
+    #[strum(serialize = "ignore-ownership")]
+    IgnoreOwnership,
+
+    #[strum(serialize = "log-level")]
+    LogLevel,
+
+    #[strum(serialize = "manifest")]
+    Manifest,
+
+    #[strum(serialize = "min-recache")]
+    MinRecache,
+
+    #[strum(serialize = "merge-restore")]
+    MergeRestore,
+
     #[strum(serialize = "min-recache-crates")]
     MinRecacheCrates,
 
@@ -42,15 +131,51 @@ pub enum Input {
     #[strum(serialize = "profile")]
     Profile,
 
+    #[strum(serialize = "recache-on-lockfile-change")]
+    RecacheOnLockfileChange,
+
+    #[strum(serialize = "require-lockfile")]
+    RequireLockfile,
+
+    #[strum(serialize = "restore-only")]
+    RestoreOnly,
+
+    #[strum(serialize = "rustdocflags")]
+    Rustdocflags,
+
+    #[strum(serialize = "rustflags")]
+    Rustflags,
+
+    #[strum(serialize = "rustup-update")]
+    RustupUpdate,
+
+    #[strum(serialize = "save-only")]
+    SaveOnly,
+
+    #[strum(serialize = "scope-files")]
+    ScopeFiles,
+
+    #[strum(serialize = "scope-run-attempt")]
+    ScopeRunAttempt,
+
+    #[strum(serialize = "scope-strategy")]
+    ScopeStrategy,
+
     // We name this target instead of targets since actions-rs only has target
     #[strum(serialize = "target")]
     Targets,
 
+    #[strum(serialize = "timestamp-format")]
+    TimestampFormat,
+
     #[strum(serialize = "toolchain")]
     Toolchain,
 
     #[strum(serialize = "use-cross")]
     UseCross,
+
+    #[strum(serialize = "verify-cache")]
+    VerifyCache,
 }
 
 #[derive(Debug)]
@@ -68,10 +193,55 @@ impl Manager {
                 inputs.insert(input, value);
             }
         }
-        Ok(Manager {
+        let result = Manager {
             inputs,
             accessed: Mutex::default(),
-        })
+        };
+        result.merge_config_file()
+    }
+
+    /// Adds inputs from the TOML file named by the `config-file` input, if
+    /// any. Inputs supplied directly take precedence.
+    fn merge_config_file(mut self) -> Result<Manager, Error> {
+        let path = if let Some(path) = self.get(Input::ConfigFile) {
+            path.to_string()
+        } else {
+            return Ok(self);
+        };
+        let contents = node::fs::read_file_sync(path.as_str()).with_context(|| format!("while reading {}", path))?;
+        let contents = String::from_utf8_lossy(&contents);
+        let table: toml::value::Table =
+            toml::from_str(&contents).map_err(|e| Location::of_toml_error(&Path::from(path.as_str()), &e).wrap(e))?;
+        for (key, value) in table {
+            let input = Input::iter().find(|i| <&str>::from(*i) == key && *i != Input::ConfigFile);
+            match (input, Self::config_value_to_string(&value)) {
+                (None, _) => warning!("Ignoring unknown input {} in {}", key, path),
+                (Some(_), None) => warning!("Ignoring input {} in {} since it has an unsupported type", key, path),
+                (Some(input), Some(value)) => {
+                    self.inputs.entry(input).or_insert(value);
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    fn config_value_to_string(value: &toml::Value) -> Option<String> {
+        use itertools::Itertools as _;
+        use toml::Value;
+
+        match value {
+            Value::String(value) => Some(value.clone()),
+            Value::Integer(value) => Some(value.to_string()),
+            Value::Float(value) => Some(value.to_string()),
+            Value::Boolean(value) => Some(value.to_string()),
+            // Lists (e.g. of components or targets) are whitespace separated
+            Value::Array(values) => values
+                .iter()
+                .map(Self::config_value_to_string)
+                .collect::<Option<Vec<_>>>()
+                .map(|v| v.into_iter().join(" ")),
+            Value::Datetime(_) | Value::Table(_) => None,
+        }
     }
 
     pub fn get(&self, input: Input) -> Option<&str> {
@@ -86,6 +256,16 @@ impl Manager {
         })
     }
 
+    /// Parses a boolean input, returning `default` if it was not supplied
+    pub fn get_bool(&self, input: Input, default: bool) -> Result<bool, Error> {
+        match self.get(input) {
+            Some(value) => value
+                .parse::<bool>()
+                .map_err(|_| Error::OptionParseError(input.to_string(), value.to_string())),
+            None => Ok(default),
+        }
+    }
+
     pub fn unused(&self) -> HashSet<Input> {
         let available: HashSet<_> = self.inputs.keys().copied().collect();
         &available - &self.accessed.lock()
@@ -1,17 +1,32 @@
 use crate::actions::core;
-use crate::Error;
+use crate::{node, Error};
 use serde::de::DeserializeOwned;
 use std::collections::BTreeMap;
 
 const JOB_INPUT: &str = "internal-use-github-job";
 const MATRIX_INPUT: &str = "internal-use-matrix";
+const RUN_ATTEMPT_ENV_VAR: &str = "GITHUB_RUN_ATTEMPT";
+const RUN_ID_ENV_VAR: &str = "GITHUB_RUN_ID";
 const WORKFLOW_INPUT: &str = "internal-use-github-workflow";
 
-#[derive(Clone, Debug, Hash)]
+#[derive(Clone, Debug)]
 pub struct Job {
     workflow: String,
     job_id: String,
     matrix_properties: Option<BTreeMap<String, String>>,
+    run_id: Option<String>,
+    run_attempt: Option<String>,
+}
+
+impl std::hash::Hash for Job {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        // The run ID and attempt are deliberately excluded since they differ
+        // between every run and would prevent anything keyed on the job from
+        // ever being reused
+        self.workflow.hash(state);
+        self.job_id.hash(state);
+        self.matrix_properties.hash(state);
+    }
 }
 
 impl Job {
@@ -27,10 +42,15 @@ impl Job {
         let workflow = Self::get_json_input(WORKFLOW_INPUT)?;
         let job_id = Self::get_json_input(JOB_INPUT)?;
         let matrix_properties = Self::get_json_input(MATRIX_INPUT)?;
+        let env = node::process::get_env();
+        let run_id = env.get(RUN_ID_ENV_VAR).cloned();
+        let run_attempt = env.get(RUN_ATTEMPT_ENV_VAR).cloned();
         let result = Job {
             workflow,
             job_id,
             matrix_properties,
+            run_id,
+            run_attempt,
         };
         Ok(result)
     }
@@ -43,6 +63,14 @@ impl Job {
         &self.job_id
     }
 
+    pub fn get_run_id(&self) -> Option<&str> {
+        self.run_id.as_deref()
+    }
+
+    pub fn get_run_attempt(&self) -> Option<&str> {
+        self.run_attempt.as_deref()
+    }
+
     pub fn matrix_properties_as_string(&self) -> Option<String> {
         // Note: This function does not attempt to guarantee that this string is
         // deterministic. At the time of writing it is though, regardless of whether
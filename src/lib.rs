@@ -13,6 +13,7 @@ mod agnostic_path;
 mod cache_cargo_home;
 mod cache_key_builder;
 mod cargo;
+mod cargo_alias;
 mod cargo_hooks;
 mod cargo_lock_hashing;
 mod cross;
@@ -23,6 +24,8 @@ mod fingerprinting;
 mod hasher;
 mod input_manager;
 mod job;
+mod lock_file;
+mod msrv;
 mod node;
 mod nonce;
 mod noop_stream;
@@ -48,6 +51,11 @@ pub async fn start() -> Result<(), JsValue> {
     utils::set_panic_hook();
 
     if let Err(e) = run::run().await {
+        if let Some(annotation) = e.to_annotation() {
+            annotation.error();
+        }
+        // The debug form includes details such as JavaScript stack traces
+        debug!("Action failed with error: {:?}", e);
         core::set_failed(e.to_string());
     }
     Ok(())
@@ -0,0 +1,91 @@
+use crate::node::path::Path;
+use crate::system::sleep::sleep;
+use crate::{info, node, nonce, warning, Error};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A lock held by creating a file which records when it was acquired, along
+/// with a nonce which makes its contents unique. Locks whose holder appears to
+/// have died are broken.
+#[derive(Debug)]
+pub struct LockFile {
+    path: Path,
+}
+
+impl LockFile {
+    /// Attempts to acquire the lock at `path`, waiting for up to `timeout`.
+    /// Existing locks older than `stale_after` are assumed to be abandoned.
+    /// Returns `None` if the lock could not be acquired in time.
+    pub async fn acquire(path: &Path, timeout: Duration, stale_after: Duration) -> Result<Option<LockFile>, Error> {
+        node::fs::create_dir_all(&path.parent()).await?;
+        let start = Utc::now();
+        let mut reported = false;
+        loop {
+            let now = Utc::now();
+            let contents = format!("{} {}", now.to_rfc3339(), nonce::build(8));
+            if node::fs::create_new_file(path, contents.as_bytes()).await? {
+                return Ok(Some(LockFile { path: path.clone() }));
+            }
+            if let Some(existing) = Self::read_contents(path).await {
+                if let Some(acquired) = Self::acquired_at(&existing) {
+                    if now.signed_duration_since(acquired).to_std().unwrap_or_default() > stale_after {
+                        warning!("Breaking lock {} which was acquired at {}", path, acquired);
+                        Self::break_stale(path, &existing).await;
+                        continue;
+                    }
+                }
+            }
+            if now.signed_duration_since(start).to_std().unwrap_or_default() > timeout {
+                return Ok(None);
+            }
+            if !reported {
+                info!("Waiting for lock {} held by another invocation of this action", path);
+                reported = true;
+            }
+            sleep(&RETRY_INTERVAL).await;
+        }
+    }
+
+    /// Removes the lock at `path` if it still has the contents it was found to
+    /// be stale with. Another waiter may have broken the same lock and then
+    /// acquired it, so the lock is first renamed to a unique name and checked
+    /// before being deleted.
+    async fn break_stale(path: &Path, stale_contents: &str) {
+        let set_aside = path
+            .parent()
+            .join(&format!("{}.{}.stale", path.file_name(), nonce::build(8)));
+        // If this fails, the lock was most likely released or broken in the meantime
+        if node::fs::rename(path, &set_aside).await.is_err() {
+            return;
+        }
+        let moved = Self::read_contents(&set_aside).await;
+        if let Some(contents) = moved.filter(|c| c != stale_contents) {
+            // We moved a lock which was acquired after the stale one was broken
+            let reinstated = node::fs::create_new_file(path, contents.as_bytes()).await;
+            if !matches!(reinstated, Ok(true)) {
+                warning!("Unable to reinstate lock {} after breaking it in error", path);
+            }
+        }
+        let _ = node::fs::remove_file(&set_aside).await;
+    }
+
+    async fn read_contents(path: &Path) -> Option<String> {
+        let contents = node::fs::read_file(path).await.ok()?;
+        String::from_utf8(contents).ok()
+    }
+
+    fn acquired_at(contents: &str) -> Option<DateTime<Utc>> {
+        let timestamp = contents.split_whitespace().next()?;
+        DateTime::parse_from_rfc3339(timestamp)
+            .ok()
+            .map(|t| t.with_timezone(&Utc))
+    }
+
+    pub async fn release(self) {
+        if let Err(e) = node::fs::remove_file(&self.path).await {
+            warning!("Failed to release lock {}: {}", self.path, Error::from(e));
+        }
+    }
+}
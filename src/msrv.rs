@@ -0,0 +1,124 @@
+use crate::node::path::Path;
+use crate::{node, warning, Error};
+use toml::Value;
+
+/// The toolchain name which requests the minimum supported Rust version of
+/// the project
+pub const MSRV_TOOLCHAIN_NAME: &str = "msrv";
+
+const MANIFEST_NAME: &str = "Cargo.toml";
+
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|component| component.parse().ok()).collect()
+}
+
+/// Returns the `rust-version` declared by a package or inheritable by the
+/// members of a workspace
+fn declared_rust_version(manifest: &Value) -> Option<&str> {
+    let package_version = manifest.get("package").and_then(|p| p.get("rust-version"));
+    let workspace_version = manifest
+        .get("workspace")
+        .and_then(|w| w.get("package"))
+        .and_then(|p| p.get("rust-version"));
+    // A member inheriting from the workspace has a table here instead
+    package_version
+        .and_then(Value::as_str)
+        .or_else(|| workspace_version.and_then(Value::as_str))
+}
+
+fn max_rust_version<'a, I: IntoIterator<Item = &'a str>>(versions: I) -> Result<Option<&'a str>, Error> {
+    let mut result: Option<(Vec<u64>, &str)> = None;
+    for version in versions {
+        let parsed = parse_version(version).ok_or_else(|| Error::RustVersionParse(version.to_string()))?;
+        let is_higher = match &result {
+            None => true,
+            Some((max, _)) => parsed > *max,
+        };
+        if is_higher {
+            result = Some((parsed, version));
+        }
+    }
+    Ok(result.map(|(_, version)| version))
+}
+
+async fn read_manifest(path: &Path) -> Result<Value, Error> {
+    use crate::error::{Context as _, Location};
+
+    let manifest = node::fs::read_file(path)
+        .await
+        .with_context(|| format!("while reading {}", path))?;
+    let manifest = String::from_utf8_lossy(&manifest);
+    let manifest = toml::from_str(&manifest).map_err(|e| Location::of_toml_error(path, &e).wrap(e))?;
+    Ok(manifest)
+}
+
+/// Finds the manifests of workspace members. Only literal paths and those
+/// ending in `/*` are understood.
+async fn member_manifest_paths(root: &Path, manifest: &Value) -> Result<Vec<Path>, Error> {
+    let members = manifest
+        .get("workspace")
+        .and_then(|w| w.get("members"))
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let mut result = Vec::new();
+    for member in members.iter().filter_map(Value::as_str) {
+        if let Some(parent) = member.strip_suffix("/*") {
+            let parent = root.join(parent);
+            if parent.exists().await {
+                for entry in node::fs::read_dir(&parent).await? {
+                    if entry.file_type().is_dir() {
+                        result.push(entry.path().join(MANIFEST_NAME));
+                    }
+                }
+            }
+        } else if member.contains(['*', '?', '[']) {
+            warning!("Unable to expand workspace member pattern {} when finding MSRV", member);
+        } else {
+            result.push(root.join(member).join(MANIFEST_NAME));
+        }
+    }
+    Ok(result)
+}
+
+/// Finds the highest `rust-version` declared by the package or workspace
+/// rooted at `root`
+pub async fn find_msrv(root: &Path) -> Result<String, Error> {
+    let root_manifest_path = root.join(MANIFEST_NAME);
+    let root_manifest = read_manifest(&root_manifest_path).await?;
+    let member_paths = member_manifest_paths(root, &root_manifest).await?;
+    let mut manifests = vec![root_manifest];
+    for path in member_paths {
+        if path.exists().await {
+            manifests.push(read_manifest(&path).await?);
+        }
+    }
+    let version = max_rust_version(manifests.iter().filter_map(declared_rust_version))?;
+    version
+        .map(String::from)
+        .ok_or_else(|| Error::MissingRustVersion(root_manifest_path.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declared_rust_version, max_rust_version};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn rust_version_is_found() {
+        let package: toml::Value = toml::from_str("[package]\nrust-version = \"1.60\"").unwrap();
+        assert_eq!(declared_rust_version(&package), Some("1.60"));
+        let workspace: toml::Value = toml::from_str("[workspace.package]\nrust-version = \"1.65.0\"").unwrap();
+        assert_eq!(declared_rust_version(&workspace), Some("1.65.0"));
+        let inherited: toml::Value = toml::from_str("[package]\nrust-version.workspace = true").unwrap();
+        assert_eq!(declared_rust_version(&inherited), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn highest_rust_version_is_selected() {
+        let max = max_rust_version(["1.60", "1.9", "1.60.1", "1.58.0"]).unwrap();
+        assert_eq!(max, Some("1.60.1"));
+        assert_eq!(max_rust_version([]).unwrap(), None);
+        assert!(max_rust_version(["1.x"]).is_err());
+    }
+}
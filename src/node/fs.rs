@@ -4,6 +4,20 @@ use js_sys::{BigInt, JsString, Object, Uint8Array};
 use std::collections::VecDeque;
 use wasm_bindgen::{JsCast, JsError, JsValue};
 
+/// Checks only that a path is visible, for use with [`access`]
+pub const F_OK: u32 = 0;
+
+/// Checks that a path is readable, for use with [`access`]
+#[allow(dead_code)]
+pub const R_OK: u32 = 4;
+
+/// Checks that a path is writable, for use with [`access`]
+pub const W_OK: u32 = 2;
+
+/// Checks that a path is executable, for use with [`access`]. On Windows
+/// this behaves like [`F_OK`].
+pub const X_OK: u32 = 1;
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct FileType {
     inner: FileTypeEnum,
@@ -110,6 +124,13 @@ impl Iterator for ReadDir {
     }
 }
 
+/// Whether the current process can access `path` in the way described by
+/// `mode`, a combination of [`F_OK`], [`R_OK`], [`W_OK`] and [`X_OK`]
+pub async fn access<P: Into<JsString>>(path: P, mode: u32) -> bool {
+    let path = path.into();
+    ffi::access(&path, Some(mode)).await.is_ok()
+}
+
 pub async fn chmod<P: Into<JsString>>(path: P, mode: u16) -> Result<(), JsValue> {
     let path: JsString = path.into();
     ffi::chmod(&path, mode).await.map(|_| ())
@@ -127,12 +148,87 @@ pub async fn read_file<P: Into<JsString>>(path: P) -> Result<Vec<u8>, JsValue> {
     Ok(result)
 }
 
+/// Like `read_file()` but blocks until the file has been read
+pub fn read_file_sync<P: Into<JsString>>(path: P) -> Result<Vec<u8>, JsValue> {
+    let path: JsString = path.into();
+    let buffer = ffi::read_file_sync(&path)?;
+    let buffer = buffer
+        .dyn_ref::<Uint8Array>()
+        .ok_or_else(|| JsError::new("readFileSync didn't return an array"))?;
+    Ok(buffer.to_vec())
+}
+
+/// A file opened for reading, allowing its contents to be read incrementally
+#[derive(Debug)]
+pub struct File {
+    handle: ffi::FileHandle,
+    buffer: Option<Uint8Array>,
+}
+
+impl File {
+    /// Reads up to `buf.len()` bytes into `buf`, returning the number of bytes
+    /// read. A return value of zero indicates the end of the file.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, JsValue> {
+        let length = u32::try_from(buf.len()).unwrap_or(u32::MAX);
+        let buffer = match self.buffer.take() {
+            Some(buffer) if buffer.length() >= length => buffer,
+            _ => Uint8Array::new_with_length(length),
+        };
+        let result = self.handle.read(&buffer, 0, length, JsValue::NULL).await?;
+        let bytes_read = js_sys::Reflect::get(&result, &"bytesRead".into())?
+            .as_f64()
+            .ok_or_else(|| JsError::new("read didn't return a number of bytes"))?;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let bytes_read = bytes_read as u32;
+        buffer.subarray(0, bytes_read).copy_to(&mut buf[..bytes_read as usize]);
+        self.buffer = Some(buffer);
+        Ok(bytes_read as usize)
+    }
+
+    pub async fn close(self) -> Result<(), JsValue> {
+        self.handle.close().await?;
+        Ok(())
+    }
+}
+
+/// Opens the file at `path` for reading
+pub async fn open<P: Into<JsString>>(path: P) -> Result<File, JsValue> {
+    let path: JsString = path.into();
+    let handle = ffi::open(&path, &"r".into()).await?;
+    Ok(File {
+        handle: handle.unchecked_into(),
+        buffer: None,
+    })
+}
+
 pub async fn write_file<P: Into<JsString>>(path: P, data: &[u8]) -> Result<(), JsValue> {
     let path: JsString = path.into();
     ffi::write_file(&path, data).await?;
     Ok(())
 }
 
+/// Creates a file containing `data` only if nothing exists at `path`.
+/// Returns whether the file was created.
+pub async fn create_new_file<P: Into<JsString>>(path: P, data: &[u8]) -> Result<bool, JsValue> {
+    let path: JsString = path.into();
+    let options = js_sys::Map::new();
+    options.set(&"flag".into(), &"wx".into());
+    let options = Object::from_entries(&options).expect("Failed to convert options map to object");
+    match ffi::write_file_with_options(&path, data, &options).await {
+        Ok(_) => Ok(true),
+        Err(e) => {
+            let code = js_sys::Reflect::get(&e, &"code".into())
+                .ok()
+                .and_then(|c| c.as_string());
+            if code.as_deref() == Some("EEXIST") {
+                Ok(false)
+            } else {
+                Err(e)
+            }
+        }
+    }
+}
+
 pub async fn read_dir<P: Into<JsString>>(path: P) -> Result<ReadDir, JsValue> {
     let path: JsString = path.into();
     let options = js_sys::Map::new();
@@ -160,6 +256,17 @@ pub async fn create_dir_all<P: Into<JsString>>(path: P) -> Result<(), JsValue> {
     Ok(())
 }
 
+/// Atomically creates a new, uniquely named directory whose path is `prefix`
+/// followed by random characters, returning its path
+pub async fn mkdtemp<P: Into<JsString>>(prefix: P) -> Result<Path, JsValue> {
+    let prefix: JsString = prefix.into();
+    let path = ffi::mkdtemp(&prefix).await?;
+    let path: JsString = path
+        .dyn_into()
+        .map_err(|_| JsError::new("mkdtemp didn't return a string"))?;
+    Ok(Path::from(path))
+}
+
 pub async fn create_dir<P: Into<JsString>>(path: P) -> Result<(), JsValue> {
     let path: JsString = path.into();
     ffi::mkdir(&path, None).await?;
@@ -270,6 +377,20 @@ fn timestamp_to_seconds(timestamp: &DateTime<Utc>) -> f64 {
     whole + fractional
 }
 
+/// Sets the access and modification times of `path`, following it if it is a
+/// symbolic link
+pub async fn utimes<P: Into<JsString>>(path: P, a_time: &DateTime<Utc>, m_time: &DateTime<Utc>) -> Result<(), JsValue> {
+    use js_sys::Number;
+
+    let path = path.into();
+    let a_time: Number = timestamp_to_seconds(a_time).into();
+    let m_time: Number = timestamp_to_seconds(m_time).into();
+    ffi::utimes(&path, a_time.as_ref(), m_time.as_ref()).await?;
+    Ok(())
+}
+
+/// Sets the access and modification times of `path`, which is not followed
+/// if it is a symbolic link
 pub async fn lutimes<P: Into<JsString>>(
     path: P,
     a_time: &DateTime<Utc>,
@@ -285,7 +406,7 @@ pub async fn lutimes<P: Into<JsString>>(
 }
 
 pub mod ffi {
-    use js_sys::{BigInt, JsString, Object};
+    use js_sys::{BigInt, JsString, Object, Uint8Array};
     use wasm_bindgen::prelude::*;
     use wasm_bindgen::JsValue;
 
@@ -349,6 +470,9 @@ pub mod ffi {
 
         #[wasm_bindgen(method, getter)]
         pub fn mode(this: &Stats) -> BigInt;
+
+        #[wasm_bindgen(catch, js_name = "readFileSync")]
+        pub fn read_file_sync(path: &JsString) -> Result<JsValue, JsValue>;
     }
 
     #[wasm_bindgen(module = "fs/promises")]
@@ -359,15 +483,28 @@ pub mod ffi {
         #[wasm_bindgen(catch, js_name = "readFile")]
         pub async fn read_file(path: &JsString) -> Result<JsValue, JsValue>;
 
+        #[wasm_bindgen(catch)]
+        pub async fn open(path: &JsString, flags: &JsString) -> Result<JsValue, JsValue>;
+
         #[wasm_bindgen(catch, js_name = "writeFile")]
         pub async fn write_file(path: &JsString, data: &[u8]) -> Result<JsValue, JsValue>;
 
+        #[wasm_bindgen(catch, js_name = "writeFile")]
+        pub async fn write_file_with_options(
+            path: &JsString,
+            data: &[u8],
+            options: &Object,
+        ) -> Result<JsValue, JsValue>;
+
         #[wasm_bindgen(catch, js_name = "readdir")]
         pub async fn read_dir(path: &JsString, options: Option<Object>) -> Result<JsValue, JsValue>;
 
         #[wasm_bindgen(catch)]
         pub async fn mkdir(path: &JsString, options: Option<Object>) -> Result<JsValue, JsValue>;
 
+        #[wasm_bindgen(catch)]
+        pub async fn mkdtemp(prefix: &JsString) -> Result<JsValue, JsValue>;
+
         #[wasm_bindgen(catch)]
         pub async fn rename(old: &JsString, new: &JsString) -> Result<JsValue, JsValue>;
 
@@ -385,6 +522,29 @@ pub mod ffi {
 
         #[wasm_bindgen(catch)]
         pub async fn unlink(path: &JsString) -> Result<JsValue, JsValue>;
+
+        #[wasm_bindgen(catch)]
+        pub async fn utimes(path: &JsString, atime: &JsValue, mtime: &JsValue) -> Result<JsValue, JsValue>;
+    }
+
+    // `FileHandle` is not exported by `fs/promises` so we can only access it
+    // structurally
+    #[wasm_bindgen]
+    extern "C" {
+        #[derive(Debug)]
+        pub type FileHandle;
+
+        #[wasm_bindgen(method, structural, catch)]
+        pub async fn read(
+            this: &FileHandle,
+            buffer: &Uint8Array,
+            offset: u32,
+            length: u32,
+            position: JsValue,
+        ) -> Result<JsValue, JsValue>;
+
+        #[wasm_bindgen(method, structural, catch)]
+        pub async fn close(this: &FileHandle) -> Result<JsValue, JsValue>;
     }
 }
 
@@ -444,6 +604,69 @@ mod test {
         Ok(())
     }
 
+    #[wasm_bindgen_test]
+    async fn mkdtemp_creates_distinct_dirs() -> Result<(), JsValue> {
+        let prefix = node::os::temp_dir().join("ferrous-actions-mkdtemp-test-");
+        let first = node::fs::mkdtemp(&prefix).await?;
+        let second = node::fs::mkdtemp(&prefix).await?;
+        assert_ne!(first, second);
+        for dir in [first, second] {
+            assert!(dir.exists().await);
+            node::fs::remove_dir(&dir).await?;
+        }
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn create_new_file_does_not_overwrite() -> Result<(), JsValue> {
+        let path = temp_path();
+        assert!(node::fs::create_new_file(&path, b"first").await?);
+        assert!(!node::fs::create_new_file(&path, b"second").await?);
+        assert_eq!(node::fs::read_file(&path).await?, b"first");
+        node::fs::remove_file(&path).await?;
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn access_checks_mode() -> Result<(), JsValue> {
+        use node::fs::{access, F_OK, R_OK, W_OK, X_OK};
+
+        let path = temp_path();
+        assert!(!access(&path, F_OK).await);
+        node::fs::write_file(&path, b"contents").await?;
+        node::fs::chmod(&path, 0o644).await?;
+        assert!(access(&path, F_OK).await);
+        assert!(access(&path, R_OK | W_OK).await);
+        if node::os::platform() != "win32" {
+            assert!(!access(&path, X_OK).await);
+        }
+        node::fs::chmod(&path, 0o755).await?;
+        assert!(access(&path, X_OK).await);
+        node::fs::remove_file(&path).await?;
+        Ok(())
+    }
+
+    #[wasm_bindgen_test]
+    async fn read_file_incrementally() -> Result<(), JsValue> {
+        let path = temp_path();
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        node::fs::write_file(&path, &data).await?;
+        let mut file = node::fs::open(&path).await?;
+        let mut read_data = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let bytes_read = file.read(&mut chunk).await?;
+            if bytes_read == 0 {
+                break;
+            }
+            read_data.extend_from_slice(&chunk[..bytes_read]);
+        }
+        file.close().await?;
+        assert_eq!(data, read_data);
+        node::fs::remove_file(&path).await?;
+        Ok(())
+    }
+
     #[wasm_bindgen_test]
     async fn create_remove_dir() -> Result<(), JsValue> {
         let first = temp_path();
@@ -2,6 +2,7 @@ use super::path;
 use js_sys::JsString;
 use lazy_static::lazy_static;
 use std::borrow::Cow;
+use wasm_bindgen::JsValue;
 
 lazy_static! {
     static ref EOL: String = {
@@ -38,8 +39,39 @@ pub fn temp_dir() -> path::Path {
     path::Path::from(ffi::tmpdir())
 }
 
+/// Identity of the user running the current process. On Windows, the user
+/// and group IDs are -1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserInfo {
+    pub uid: i64,
+    pub gid: i64,
+    pub username: String,
+}
+
+/// Fails if the user has no username or home directory, as can happen when
+/// running as an arbitrary UID inside a container
+#[allow(clippy::cast_possible_truncation)]
+pub fn user_info() -> Result<UserInfo, JsValue> {
+    let info = ffi::user_info()?;
+    Ok(UserInfo {
+        uid: info.uid() as i64,
+        gid: info.gid() as i64,
+        username: info.username().into(),
+    })
+}
+
+pub fn cpu_count() -> usize {
+    ffi::cpus().length() as usize
+}
+
+pub fn total_memory() -> u64 {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let bytes = ffi::totalmem() as u64;
+    bytes
+}
+
 pub mod ffi {
-    use js_sys::{JsString, Object};
+    use js_sys::{Array, JsString, Object};
     use wasm_bindgen::prelude::*;
 
     #[wasm_bindgen(module = "os")]
@@ -48,10 +80,30 @@ pub mod ffi {
         pub static EOL: Object;
 
         pub fn arch() -> JsString;
+        pub fn cpus() -> Array;
         pub fn homedir() -> JsString;
         pub fn machine() -> JsString;
         pub fn platform() -> JsString;
         pub fn tmpdir() -> JsString;
+        pub fn totalmem() -> f64;
+
+        #[wasm_bindgen(js_name = "userInfo", catch)]
+        pub fn user_info() -> Result<UserInfo, JsValue>;
+    }
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[derive(Debug)]
+        pub type UserInfo;
+
+        #[wasm_bindgen(method, getter)]
+        pub fn uid(this: &UserInfo) -> f64;
+
+        #[wasm_bindgen(method, getter)]
+        pub fn gid(this: &UserInfo) -> f64;
+
+        #[wasm_bindgen(method, getter)]
+        pub fn username(this: &UserInfo) -> JsString;
     }
 }
 
@@ -64,6 +116,11 @@ mod test {
         super::arch();
     }
 
+    #[wasm_bindgen_test]
+    fn invoke_cpu_count() {
+        assert!(super::cpu_count() > 0);
+    }
+
     #[wasm_bindgen_test]
     fn invoke_homedir() {
         super::homedir();
@@ -83,4 +140,15 @@ mod test {
     fn invoke_temp_dir() {
         super::temp_dir();
     }
+
+    #[wasm_bindgen_test]
+    fn invoke_user_info() {
+        let info = super::user_info().expect("Unable to get user info");
+        assert!(!info.username.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn invoke_total_memory() {
+        assert!(super::total_memory() > 0);
+    }
 }
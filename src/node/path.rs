@@ -36,14 +36,39 @@ impl std::fmt::Display for Path {
     }
 }
 
+lazy_static! {
+    static ref CASE_INSENSITIVE: bool = is_case_insensitive_platform(&super::os::platform());
+}
+
+lazy_static! {
+    static ref IS_WINDOWS: bool = super::os::platform() == "win32";
+}
+
+/// Whether the default filesystems of `platform` (as named by Node) are case
+/// insensitive. Network and other less common filesystems may differ, as may
+/// case-sensitive volumes on macOS.
+fn is_case_insensitive_platform(platform: &str) -> bool {
+    matches!(platform, "darwin" | "win32")
+}
+
+fn paths_equal(lhs: &JsString, rhs: &JsString, case_insensitive: bool) -> bool {
+    // This handles trailing slash issues. On Windows, relative() also ignores case.
+    let relative = if case_insensitive {
+        ffi::relative(&lhs.to_lower_case(), &rhs.to_lower_case())
+    } else {
+        ffi::relative(lhs, rhs)
+    };
+    relative.length() == 0
+}
+
 impl PartialEq for Path {
+    /// Compares paths case-insensitively on platforms whose filesystems are
+    /// usually case insensitive
     fn eq(&self, rhs: &Path) -> bool {
         // relative() resolves paths according to the CWD so we should only
         // use it if they will both be resolved the same way
         if self.is_absolute() == rhs.is_absolute() {
-            // This should handle both case-sensitivity and trailing slash issues
-            let relative = ffi::relative(&self.inner, &rhs.inner);
-            relative.length() == 0
+            paths_equal(&self.inner, &rhs.inner, *CASE_INSENSITIVE)
         } else {
             false
         }
@@ -75,13 +100,71 @@ impl Path {
         ffi::is_absolute(&self.inner)
     }
 
+    /// On Windows, converts an absolute path to its `\\?\`-prefixed form
+    /// which is not subject to the 260 character limit on path lengths. Other
+    /// paths are returned unchanged.
+    #[must_use]
+    pub fn to_namespaced(&self) -> Path {
+        if *IS_WINDOWS && self.is_absolute() {
+            // Not normalized since that could remove the prefix
+            let inner = ffi::to_namespaced_path(&self.inner);
+            Path { inner }
+        } else {
+            self.clone()
+        }
+    }
+
+    /// The names making up the path, split on the platform separator. Root
+    /// and trailing separators and `.` do not produce components. On Windows,
+    /// a drive letter is a component.
+    pub fn components(&self) -> impl Iterator<Item = String> {
+        let path = self.to_string();
+        let components: Vec<String> = path
+            .split(separator().as_ref())
+            .filter(|c| !c.is_empty() && *c != ".")
+            .map(String::from)
+            .collect();
+        components.into_iter()
+    }
+
     pub fn file_name(&self) -> String {
         let result = ffi::basename(&self.inner, None);
         result.into()
     }
 
+    /// Replaces the extension of the final component with `extension`, which
+    /// may be given with or without a leading `.`. Only the last extension is
+    /// replaced, so `foo.tar.gz` becomes `foo.tar.zst` rather than `foo.zst`.
+    /// An empty `extension` removes the existing one. Names such as `.bashrc`
+    /// are treated as having no extension. Returns false and leaves the path
+    /// unchanged if there is no final component to modify.
+    pub fn set_extension(&mut self, extension: &str) -> bool {
+        let file_name = self.file_name();
+        if file_name.is_empty() || file_name == "." || file_name == ".." {
+            return false;
+        }
+        let current: String = ffi::extname(&self.inner).into();
+        let stem = &file_name[..file_name.len() - current.len()];
+        let extension = extension.strip_prefix('.').unwrap_or(extension);
+        let file_name = if extension.is_empty() {
+            stem.to_string()
+        } else {
+            format!("{}.{}", stem, extension)
+        };
+        *self = self.parent().join(file_name.as_str());
+        true
+    }
+
+    /// Returns the path with its extension replaced as by `set_extension`
+    #[must_use]
+    pub fn with_extension(&self, extension: &str) -> Path {
+        let mut result = self.clone();
+        result.set_extension(extension);
+        result
+    }
+
     pub async fn exists(&self) -> bool {
-        super::fs::ffi::access(&self.inner, None).await.is_ok()
+        super::fs::access(self, super::fs::F_OK).await
     }
 
     #[must_use]
@@ -100,6 +183,14 @@ impl Path {
             relative.into()
         }
     }
+
+    /// Whether this path is `root` or is located beneath it. Relative paths
+    /// are resolved against the current directory.
+    pub fn is_inside<P: Into<Path>>(&self, root: P) -> bool {
+        let relative = self.relative_to(root);
+        // On Windows, a path on a different drive has no relative form
+        !relative.is_absolute() && relative.components().next().as_deref() != Some("..")
+    }
 }
 
 impl std::fmt::Debug for Path {
@@ -186,16 +277,41 @@ pub mod ffi {
         pub fn relative(from: &JsString, to: &JsString) -> JsString;
         #[wasm_bindgen]
         pub fn basename(path: &JsString, suffix: Option<JsString>) -> JsString;
+        #[wasm_bindgen]
+        pub fn extname(path: &JsString) -> JsString;
+        #[wasm_bindgen(js_name = "toNamespacedPath")]
+        pub fn to_namespaced_path(path: &JsString) -> JsString;
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Path;
+    use super::{is_case_insensitive_platform, paths_equal, Path};
     use crate::node;
     use wasm_bindgen::JsValue;
     use wasm_bindgen_test::wasm_bindgen_test;
 
+    #[wasm_bindgen_test]
+    fn case_sensitivity_depends_on_platform() {
+        let upper = Path::from("Foo");
+        let lower = Path::from("foo");
+        let case_insensitive = is_case_insensitive_platform(&node::os::platform());
+        assert_eq!(upper == lower, case_insensitive);
+        assert!(is_case_insensitive_platform("darwin"));
+        assert!(is_case_insensitive_platform("win32"));
+        assert!(!is_case_insensitive_platform("linux"));
+    }
+
+    #[wasm_bindgen_test]
+    fn case_insensitive_comparison() {
+        let upper = js_sys::JsString::from("a/Foo");
+        let lower = js_sys::JsString::from("a/foo/");
+        assert!(paths_equal(&upper, &lower, true));
+        if node::os::platform() != "win32" {
+            assert!(!paths_equal(&upper, &lower, false));
+        }
+    }
+
     #[wasm_bindgen_test]
     fn check_absolute() {
         let cwd = node::process::cwd();
@@ -249,6 +365,21 @@ mod test {
         );
     }
 
+    #[wasm_bindgen_test]
+    fn check_extension() {
+        let parent = Path::from("parent");
+        let path = parent.join("archive.tar.gz");
+        assert_eq!(path.with_extension("zst"), parent.join("archive.tar.zst"));
+        assert_eq!(path.with_extension(".zst"), parent.join("archive.tar.zst"));
+        assert_eq!(path.with_extension(""), parent.join("archive.tar"));
+        assert_eq!(parent.join("archive").with_extension("tar"), parent.join("archive.tar"));
+        assert_eq!(parent.join(".bashrc").with_extension("bak"), parent.join(".bashrc.bak"));
+
+        let mut root = Path::from(super::separator().as_ref());
+        assert!(!root.set_extension("tar"));
+        assert_eq!(root, Path::from(super::separator().as_ref()));
+    }
+
     #[wasm_bindgen_test]
     fn check_push() {
         let parent_name = "a";
@@ -309,6 +440,44 @@ mod test {
         Ok(())
     }
 
+    #[wasm_bindgen_test]
+    fn check_is_inside() {
+        let sep = super::separator();
+        let root = Path::from(&format!("a{}b", sep));
+        assert!(root.is_inside(&root));
+        assert!(root.join("c").is_inside(&root));
+        assert!(root.join("..c").is_inside(&root));
+        assert!(!root.join("..").is_inside(&root));
+        assert!(!root.join(&format!("..{}c", sep)).is_inside(&root));
+        assert!(!Path::from("a").is_inside(&root));
+    }
+
+    #[wasm_bindgen_test]
+    fn check_namespaced() {
+        let cwd = node::process::cwd();
+        let relative = Path::from("a");
+        assert_eq!(relative.to_namespaced().to_string(), "a");
+        if node::os::platform() == "win32" {
+            assert!(cwd.to_namespaced().to_string().starts_with("\\\\?\\"));
+        } else {
+            assert_eq!(cwd.to_namespaced().to_string(), cwd.to_string());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn check_components() {
+        let sep = super::separator();
+        let components = |path: &str| Path::from(path).components().collect::<Vec<_>>();
+        assert_eq!(components(&format!("a{}b", sep)), ["a", "b"]);
+        assert_eq!(components(&format!("a{}b{}", sep, sep)), ["a", "b"]);
+        assert_eq!(components(&format!("..{}a", sep)), ["..", "a"]);
+        assert!(components(".").is_empty());
+        let cwd = node::process::cwd();
+        let absolute = cwd.join("a");
+        assert_eq!(absolute.components().count(), cwd.components().count() + 1);
+        assert_eq!(absolute.components().last().as_deref(), Some("a"));
+    }
+
     #[wasm_bindgen_test]
     fn check_equality() {
         use itertools::Itertools as _;
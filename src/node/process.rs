@@ -6,6 +6,19 @@ pub fn cwd() -> path::Path {
     path::Path::from(ffi::cwd())
 }
 
+/// The command line arguments the process was launched with
+pub fn argv() -> Vec<String> {
+    ffi::ARGV
+        .iter()
+        .map(|arg| String::from(js_sys::JsString::from(arg)))
+        .collect()
+}
+
+/// The absolute path of the executable that started the process
+pub fn exec_path() -> Path {
+    Path::from(ffi::EXEC_PATH.clone())
+}
+
 pub fn get_env() -> HashMap<String, String> {
     use js_sys::JsString;
     use wasm_bindgen::JsCast as _;
@@ -48,7 +61,7 @@ pub fn chdir<P: Into<Path>>(path: P) -> Result<(), JsValue> {
 }
 
 pub mod ffi {
-    use js_sys::{JsString, Object};
+    use js_sys::{Array, JsString, Object};
     use wasm_bindgen::prelude::*;
 
     #[wasm_bindgen(module = "process")]
@@ -56,6 +69,12 @@ pub mod ffi {
         #[wasm_bindgen(js_name = "env")]
         pub static ENV: Object;
 
+        #[wasm_bindgen(js_name = "argv")]
+        pub static ARGV: Array;
+
+        #[wasm_bindgen(js_name = "execPath")]
+        pub static EXEC_PATH: JsString;
+
         pub fn cwd() -> JsString;
 
         #[wasm_bindgen(catch)]
@@ -72,6 +91,16 @@ mod test {
         super::get_env();
     }
 
+    #[wasm_bindgen_test]
+    fn invoke_argv() {
+        assert!(!super::argv().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn invoke_exec_path() {
+        assert!(super::exec_path().exists().await);
+    }
+
     #[wasm_bindgen_test]
     async fn invoke_cwd() {
         let cwd = super::cwd();
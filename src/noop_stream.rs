@@ -1,25 +1,37 @@
 use crate::node::stream;
 use js_sys::{Function, Object};
+use parking_lot::Mutex;
+use std::sync::Arc;
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsValue;
 
+type WriteClosure = Closure<dyn FnMut(JsValue, JsValue, JsValue)>;
+
+fn build_writable(write: &WriteClosure) -> stream::ffi::Writable {
+    let options = js_sys::Map::new();
+    options.set(&"write".into(), write.as_ref());
+    let options = Object::from_entries(&options).expect("Failed to convert options map to object");
+    stream::ffi::Writable::new(Some(options))
+}
+
+fn signal_written(callback: JsValue) {
+    let callback: Function = callback.into();
+    let this = JsValue::null();
+    let error = JsValue::null();
+    callback.call1(&this, &error).expect("Error calling write callback");
+}
+
 pub struct Sink {
-    _write: Closure<dyn FnMut(JsValue, JsValue, JsValue)>,
+    _write: WriteClosure,
     writable: stream::ffi::Writable,
 }
 
 impl Default for Sink {
     fn default() -> Sink {
         let write = Closure::new(|_chunk: JsValue, _encoding: JsValue, callback: JsValue| {
-            let callback: Function = callback.into();
-            let this = JsValue::null();
-            let error = JsValue::null();
-            callback.call1(&this, &error).expect("Error calling write callback");
+            signal_written(callback);
         });
-        let options = js_sys::Map::new();
-        options.set(&"write".into(), write.as_ref());
-        let options = Object::from_entries(&options).expect("Failed to convert options map to object");
-        let writable = stream::ffi::Writable::new(Some(options));
+        let writable = build_writable(&write);
         Sink {
             _write: write,
             writable,
@@ -32,3 +44,50 @@ impl AsRef<JsValue> for Sink {
         self.writable.as_ref()
     }
 }
+
+/// Like `Sink` but retains everything written to it. Useful when the raw
+/// output of a command is needed rather than individual lines.
+pub struct CapturingSink {
+    _write: WriteClosure,
+    writable: stream::ffi::Writable,
+    buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Default for CapturingSink {
+    fn default() -> CapturingSink {
+        let buffer: Arc<Mutex<Vec<u8>>> = Arc::default();
+        let write = {
+            let buffer = buffer.clone();
+            Closure::new(move |chunk: JsValue, _encoding: JsValue, callback: JsValue| {
+                // Chunks are only strings if the stream was written to with a string and
+                // `decodeStrings` is false, which it is not by default
+                if let Some(chunk) = chunk.as_string() {
+                    buffer.lock().extend_from_slice(chunk.as_bytes());
+                } else {
+                    let chunk = js_sys::Uint8Array::new(&chunk);
+                    buffer.lock().extend(chunk.to_vec());
+                }
+                signal_written(callback);
+            })
+        };
+        let writable = build_writable(&write);
+        CapturingSink {
+            _write: write,
+            writable,
+            buffer,
+        }
+    }
+}
+
+impl CapturingSink {
+    /// Returns a copy of everything written so far
+    pub fn contents(&self) -> Vec<u8> {
+        self.buffer.lock().clone()
+    }
+}
+
+impl AsRef<JsValue> for CapturingSink {
+    fn as_ref(&self) -> &JsValue {
+        self.writable.as_ref()
+    }
+}
@@ -8,26 +8,32 @@ pub enum EntryType {
     Directory,
 }
 
-impl FromStr for EntryType {
-    type Err = ParseError;
-
-    fn from_str(string: &str) -> Result<EntryType, ParseError> {
-        let result = match string {
-            "file" => EntryType::File,
-            "dir" => EntryType::Directory,
-            _ => return Err(ParseError::UnknownEntryType(string.to_string())),
-        };
-        Ok(result)
+impl EntryType {
+    fn from_name(name: &str) -> Option<EntryType> {
+        match name {
+            "file" => Some(EntryType::File),
+            "dir" => Some(EntryType::Directory),
+            _ => None,
+        }
     }
 }
 
 #[derive(Debug, Clone, Error)]
 pub enum ParseError {
-    #[error("Unknown entry type: {0}")]
-    UnknownEntryType(String),
+    #[error("Unknown entry type on line {0}: {1}")]
+    UnknownEntryType(usize, String),
 
-    #[error("Malformed line: {0}")]
-    MalformedLine(String),
+    #[error("Malformed line {0}: {1}")]
+    MalformedLine(usize, String),
+}
+
+impl ParseError {
+    /// The line the error occurred on, starting from 1
+    pub fn line(&self) -> usize {
+        match self {
+            ParseError::UnknownEntryType(line, _) | ParseError::MalformedLine(line, _) => *line,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -46,15 +52,33 @@ impl FromStr for PackageManifest {
 
     fn from_str(string: &str) -> Result<PackageManifest, ParseError> {
         let mut entries = Vec::new();
-        for line in string.lines() {
+        for (index, line) in string.lines().enumerate() {
+            let line_number = index + 1;
             let split: Vec<_> = line.splitn(2, ':').collect();
             if split.len() != 2 {
-                return Err(ParseError::MalformedLine(line.to_string()));
+                return Err(ParseError::MalformedLine(line_number, line.to_string()));
             }
-            let entry_type = EntryType::from_str(split[0])?;
+            let entry_type = EntryType::from_name(split[0])
+                .ok_or_else(|| ParseError::UnknownEntryType(line_number, split[0].to_string()))?;
             let path = Path::from(split[1]);
             entries.push((entry_type, path));
         }
         Ok(PackageManifest { entries })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{PackageManifest, ParseError};
+    use std::str::FromStr as _;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn errors_report_their_line() {
+        let malformed = PackageManifest::from_str("file:bin/rustc\nfile bin/cargo").unwrap_err();
+        assert!(matches!(malformed, ParseError::MalformedLine(2, _)));
+        let unknown = PackageManifest::from_str("dir:lib\nlink:bin/rustc").unwrap_err();
+        assert!(matches!(unknown, ParseError::UnknownEntryType(2, _)));
+        assert_eq!(unknown.line(), 2);
+    }
+}
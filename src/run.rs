@@ -1,13 +1,124 @@
-use crate::cache_cargo_home::{restore_cargo_cache, save_cargo_cache};
+use crate::actions::cache::{self, BackendKind as CacheBackendKind, TransferOptions as CacheTransferOptions};
+use crate::actions::{core, tool_cache};
+use crate::cache_cargo_home::{reset_cargo_cache, restore_cargo_cache, save_cargo_cache, set_cargo_home_override};
+use crate::cache_key_builder::{self, TimestampFormat};
+use crate::cargo::ToolchainVersion;
 use crate::cross::Cross;
 use crate::input_manager::{Input, Manager as InputManager};
-use crate::rustup::{self, ToolchainConfig};
-use crate::{node, toolchain, warning, Cargo, Error};
+use crate::rustup::{self, ToolchainConfig, UpdatePolicy};
+use crate::{debug, fingerprinting, info, msrv, node, toolchain, warning, Cargo, Error};
+use std::collections::HashMap;
+
+// Set by the JavaScript entry points in `phase-wrappers`
+const PHASE_ENV_VAR: &str = "GITHUB_RUST_ACTION_PHASE";
+
+// Set by the runner. The toolkit needs these to download and cache tools.
+const RUNNER_ENV_VARS: &[&str] = &["RUNNER_TEMP", "RUNNER_TOOL_CACHE"];
+
+// Set by `act` when it runs a workflow locally
+const ACT_ENV_VAR: &str = "ACT";
+
+// Components providing Cargo subcommands which are not part of every profile
+const SUBCOMMAND_COMPONENTS: &[(&str, &str)] = &[("clippy", "clippy"), ("fmt", "rustfmt"), ("miri", "miri")];
+
+// Cargo options which may precede the subcommand and take a separate value
+const CARGO_OPTIONS_WITH_VALUES: &[&str] = &["-C", "--config", "-Z"];
+
+/// Makes sure the component providing `subcommand` is installed, if it has
+/// one and the toolchain is managed by Rustup
+async fn ensure_subcommand_component(
+    input_manager: &InputManager,
+    subcommand: &str,
+    toolchain: Option<&str>,
+) -> Result<(), Error> {
+    let auto_install = input_manager.get_bool(Input::AutoInstallComponents, true)?;
+    let component = SUBCOMMAND_COMPONENTS
+        .iter()
+        .find(|(s, _)| *s == subcommand)
+        .map(|(_, c)| *c);
+    if let (true, Some(component)) = (auto_install, component) {
+        match rustup::Rustup::get().await {
+            Ok(rustup) => {
+                info!("Ensuring the {} component is installed", component);
+                if let Err(e) = rustup.install_component(component, toolchain).await {
+                    warning!("Unable to install the {} component: {}", component, e);
+                }
+            }
+            Err(e) => debug!(
+                "Not installing {} component since rustup could not be found: {}",
+                component, e
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Whether the tokens following `cargo` look like options followed by a
+/// subcommand, rather than a subcommand followed by its arguments
+fn is_cargo_command(global_args: &[&str], subcommand: &str) -> bool {
+    let mut args = global_args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            return false;
+        }
+        // If the value is missing, the apparent subcommand is actually the value
+        if CARGO_OPTIONS_WITH_VALUES.contains(arg) && args.next().is_none() {
+            return false;
+        }
+    }
+    !subcommand.starts_with('-')
+}
+
+/// Checks that the environment variables the action cannot do without are
+/// set, so that their absence is reported up front rather than as whatever
+/// fails first because of it
+fn preflight(environment: &HashMap<String, String>) -> Result<(), Error> {
+    use itertools::Itertools as _;
+
+    let missing_runner_vars: Vec<&str> = RUNNER_ENV_VARS
+        .iter()
+        .copied()
+        .filter(|name| !environment.contains_key(*name))
+        .collect();
+    let missing_phase = !environment.contains_key(PHASE_ENV_VAR);
+    if missing_runner_vars.is_empty() && !missing_phase {
+        return Ok(());
+    }
+    let cause = if !missing_runner_vars.is_empty() && environment.contains_key(ACT_ENV_VAR) {
+        "The action appears to be running under act, which may need to be configured to set them."
+    } else if !missing_runner_vars.is_empty() {
+        "The action is probably not running on a GitHub Actions runner."
+    } else {
+        "The action must be started via the entry points named in action.yml rather than by loading its module directly."
+    };
+    let names = missing_phase
+        .then_some(PHASE_ENV_VAR)
+        .into_iter()
+        .chain(missing_runner_vars)
+        .join(", ");
+    Err(Error::MissingEnvironment {
+        names,
+        cause: cause.into(),
+    })
+}
+
+fn set_toolchain_version_outputs(version: &ToolchainVersion) {
+    core::set_output("rustc-version", version.short().as_ref());
+    core::set_output("rustc-version-verbose", version.long().as_ref());
+}
+
+async fn get_toolchain_config(input_manager: &InputManager) -> Result<ToolchainConfig, Error> {
+    use std::str::FromStr as _;
 
-fn get_toolchain_config(input_manager: &InputManager) -> Result<ToolchainConfig, Error> {
     let mut toolchain_config = ToolchainConfig::default();
     if let Some(toolchain) = input_manager.get(Input::Toolchain) {
-        toolchain_config.name = toolchain.into();
+        toolchain_config.name = if toolchain == msrv::MSRV_TOOLCHAIN_NAME {
+            let version = msrv::find_msrv(&node::process::cwd()).await?;
+            info!("Minimum supported Rust version is {}", version);
+            version
+        } else {
+            toolchain.into()
+        };
     }
     if let Some(profile) = input_manager.get(Input::Profile) {
         toolchain_config.profile = profile.into();
@@ -18,72 +129,152 @@ fn get_toolchain_config(input_manager: &InputManager) -> Result<ToolchainConfig,
     if let Some(targets) = input_manager.get(Input::Targets) {
         toolchain_config.targets = targets.split_whitespace().map(String::from).collect();
     }
-    if let Some(set_default) = input_manager.get(Input::Default) {
-        let set_default = set_default
-            .parse::<bool>()
-            .map_err(|_| Error::OptionParseError(Input::Default.to_string(), set_default.to_string()))?;
-        toolchain_config.set_default = set_default;
-    }
-    if let Some(set_override) = input_manager.get(Input::Override) {
-        let set_override = set_override
-            .parse::<bool>()
-            .map_err(|_| Error::OptionParseError(Input::Override.to_string(), set_override.to_string()))?;
-        toolchain_config.set_override = set_override;
+    toolchain_config.set_default = input_manager.get_bool(Input::Default, toolchain_config.set_default)?;
+    toolchain_config.set_override = input_manager.get_bool(Input::Override, toolchain_config.set_override)?;
+    toolchain_config.allow_unknown_targets =
+        input_manager.get_bool(Input::AllowUnknownTargets, toolchain_config.allow_unknown_targets)?;
+    if let Some(update) = input_manager.get(Input::RustupUpdate) {
+        toolchain_config.update = UpdatePolicy::from_str(update)
+            .map_err(|_| Error::OptionParseError(Input::RustupUpdate.to_string(), update.to_string()))?;
     }
     Ok(toolchain_config)
 }
 
-pub async fn run() -> Result<(), Error> {
-    use wasm_bindgen::JsError;
+fn get_download_concurrency(input_manager: &InputManager) -> Result<usize, Error> {
+    Ok(match input_manager.get(Input::DownloadConcurrency) {
+        None => toolchain::DEFAULT_CONCURRENT_PACKAGE_INSTALLS,
+        Some("auto") => {
+            let concurrency = toolchain::auto_package_install_concurrency();
+            info!("Will download up to {} packages concurrently", concurrency);
+            concurrency
+        }
+        Some(value) => value
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| Error::OptionParseError(Input::DownloadConcurrency.to_string(), value.to_string()))?,
+    })
+}
+
+fn get_positive_integer(input_manager: &InputManager, input: Input) -> Result<Option<u32>, Error> {
+    input_manager
+        .get(input)
+        .map(|value| {
+            value
+                .parse::<u32>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| Error::OptionParseError(input.to_string(), value.to_string()))
+        })
+        .transpose()
+}
+
+fn get_duration(input_manager: &InputManager, input: Input) -> Result<Option<std::time::Duration>, Error> {
+    input_manager
+        .get(input)
+        .map(|value| humantime::parse_duration(value).map_err(Into::into))
+        .transpose()
+}
+
+fn configure_logging(input_manager: &InputManager) -> Result<(), Error> {
+    use std::str::FromStr as _;
+
+    if let Some(level) = input_manager.get(Input::LogLevel) {
+        let level = core::LogLevel::from_str(level).map_err(|_| Error::ParseLogLevel(level.to_string()))?;
+        core::set_log_level(level);
+    }
+    Ok(())
+}
+
+fn configure_caching(input_manager: &InputManager) -> Result<(), Error> {
+    use std::str::FromStr as _;
+
+    if let Some(backend) = input_manager.get(Input::CacheBackend) {
+        let backend = CacheBackendKind::from_str(backend).map_err(|_| Error::ParseCacheBackend(backend.to_string()))?;
+        cache::set_default_backend(backend);
+    }
+    cache::set_cache_required(input_manager.get_bool(Input::CacheRequired, false)?);
+    let transfer_options = CacheTransferOptions {
+        upload_chunk_size: get_positive_integer(input_manager, Input::CacheUploadChunkSize)?,
+        upload_concurrency: get_positive_integer(input_manager, Input::CacheUploadConcurrency)?,
+        download_concurrency: get_positive_integer(input_manager, Input::CacheDownloadConcurrency)?,
+        timeout: get_duration(input_manager, Input::CacheDownloadTimeout)?,
+        segment_timeout: get_duration(input_manager, Input::CacheSegmentTimeout)?,
+    };
+    cache::set_default_transfer_options(transfer_options);
+    set_cargo_home_override(input_manager.get(Input::CargoHome).map(String::from));
+    fingerprinting::set_hash_xattrs(input_manager.get_bool(Input::FingerprintXattrs, false)?);
+    fingerprinting::set_ignore_volatile(input_manager.get_bool(Input::FingerprintIgnoreVolatile, true)?);
+    fingerprinting::set_ignore_ownership(input_manager.get_bool(Input::IgnoreOwnership, true)?);
+    if let Ok(user) = node::os::user_info() {
+        debug!("Running as user {} (uid {}, gid {})", user.username, user.uid, user.gid);
+    }
+    if let Some(format) = input_manager.get(Input::TimestampFormat) {
+        let format = TimestampFormat::from_str(format)
+            .map_err(|_| Error::OptionParseError(Input::TimestampFormat.to_string(), format.to_string()))?;
+        cache_key_builder::set_timestamp_format(format);
+    }
+    Ok(())
+}
 
+pub async fn run() -> Result<(), Error> {
+    debug!(
+        "Invoked as {:?} using {}",
+        node::process::argv(),
+        node::process::exec_path()
+    );
     let environment = node::process::get_env();
-    if let Some(phase) = environment.get("GITHUB_RUST_ACTION_PHASE") {
-        match phase.as_str() {
-            "main" => main().await,
-            "post" => post().await,
-            _ => {
-                warning!("Unexpectedly invoked with phase {}. Doing nothing.", phase);
-                Ok(())
-            }
+    preflight(&environment)?;
+    let phase = environment.get(PHASE_ENV_VAR).map_or("", String::as_str);
+    match phase {
+        "main" => main().await,
+        "post" => post().await,
+        _ => {
+            warning!("Unexpectedly invoked with phase {}. Doing nothing.", phase);
+            Ok(())
         }
-    } else {
-        Err(Error::Js(
-            JsError::new("Action was invoked in an unexpected way. Could not determine phase.").into(),
-        ))
     }
 }
 
 pub async fn main() -> Result<(), Error> {
     let input_manager = InputManager::build()?;
+    configure_logging(&input_manager)?;
+    configure_caching(&input_manager)?;
+    tool_cache::set_github_token(input_manager.get(Input::GithubToken).map(String::from));
     let command = input_manager.get_required(Input::Command)?;
     let split: Vec<&str> = command.split_whitespace().collect();
     match split[..] {
         ["install-rustup"] => {
-            let toolchain_config = get_toolchain_config(&input_manager)?;
-            rustup::install(&toolchain_config).await?;
+            let toolchain_config = get_toolchain_config(&input_manager).await?;
+            if let Some(version) = rustup::install(&toolchain_config).await? {
+                set_toolchain_version_outputs(&version);
+            }
         }
         ["install-toolchain"] => {
-            let toolchain_config = get_toolchain_config(&input_manager)?;
-            toolchain::install(&toolchain_config).await?;
+            let toolchain_config = get_toolchain_config(&input_manager).await?;
+            let concurrency = get_download_concurrency(&input_manager)?;
+            let manifest = input_manager.get(Input::Manifest);
+            if let Some(version) = toolchain::install(&toolchain_config, concurrency, manifest).await? {
+                set_toolchain_version_outputs(&version);
+            }
         }
-        ["cargo", cargo_subcommand] => {
-            let use_cross = if let Some(use_cross) = input_manager.get(Input::UseCross) {
-                use_cross
-                    .parse::<bool>()
-                    .map_err(|_| Error::OptionParseError("use-cross".into(), use_cross.to_string()))?
-            } else {
-                false
-            };
+        ["cargo", ref global_args @ .., cargo_subcommand] if is_cargo_command(global_args, cargo_subcommand) => {
+            let use_cross = input_manager.get_bool(Input::UseCross, false)?;
+            let cargo_args = input_manager.get(Input::Args).unwrap_or_default();
+            let cargo_args =
+                shlex::split(cargo_args).ok_or_else(|| Error::ArgumentsParseError(cargo_args.to_string()))?;
             let mut cargo = if use_cross {
                 let cross = Cross::get_or_install(&input_manager).await?;
+                if let Some(image) = input_manager.get(Input::CrossImage) {
+                    Cross::set_image(image, &cargo_args);
+                }
                 Cargo::from_path(&cross.get_path()).await?
             } else {
                 Cargo::from_environment().await?
             };
-            let cargo_args = input_manager.get(Input::Args).unwrap_or_default();
-            let cargo_args =
-                shlex::split(cargo_args).ok_or_else(|| Error::ArgumentsParseError(cargo_args.to_string()))?;
+            cargo.global_args(global_args.iter().copied());
             let toolchain = input_manager.get(Input::Toolchain);
+            ensure_subcommand_component(&input_manager, cargo_subcommand, toolchain).await?;
             cargo
                 .run(
                     toolchain,
@@ -92,8 +283,13 @@ pub async fn main() -> Result<(), Error> {
                     &input_manager,
                 )
                 .await?;
+            match cargo.toolchain_version(toolchain).await {
+                Ok(version) => set_toolchain_version_outputs(&version),
+                Err(e) => warning!("Unable to determine toolchain version: {}", e),
+            }
         }
         ["cache"] => restore_cargo_cache(&input_manager).await?,
+        ["reset-cache"] => reset_cargo_cache(&input_manager).await?,
         _ => return Err(Error::UnknownCommand(command.to_string())),
     }
 
@@ -105,6 +301,8 @@ pub async fn main() -> Result<(), Error> {
 
 pub async fn post() -> Result<(), Error> {
     let input_manager = InputManager::build()?;
+    configure_logging(&input_manager)?;
+    configure_caching(&input_manager)?;
     let command = input_manager.get_required(Input::Command)?;
     let split: Vec<&str> = command.split_whitespace().collect();
     #[allow(clippy::single_match)]
@@ -114,3 +312,22 @@ pub async fn post() -> Result<(), Error> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::is_cargo_command;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn option_values_are_not_subcommands() {
+        assert!(is_cargo_command(&[], "build"));
+        assert!(is_cargo_command(&["-Z", "build-std"], "build"));
+        assert!(is_cargo_command(&["--config", "net.retry=3", "-v"], "test"));
+        assert!(is_cargo_command(&["-Zbuild-std"], "build"));
+        assert!(!is_cargo_command(&["-Z"], "build-std"));
+        assert!(!is_cargo_command(&["-v", "--config"], "net.retry=3"));
+        assert!(!is_cargo_command(&["-C"], "crates/foo"));
+        assert!(!is_cargo_command(&["build"], "--release"));
+        assert!(!is_cargo_command(&["-Z", "build-std", "build"], "x"));
+    }
+}
@@ -1,18 +1,157 @@
-use crate::actions::exec::Command;
+use crate::actions::exec::{Command, Stdio};
 use crate::actions::{core, io, tool_cache};
+use crate::cargo::{get_installed_toolchain_version, ToolchainVersion};
 use crate::node::path::Path;
-use crate::{debug, info, node, Error};
+use crate::{debug, info, node, warning, Error};
 use parking_lot::Mutex;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 const NO_DEFAULT_TOOLCHAIN_NAME: &str = "none";
 
-pub async fn install(toolchain_config: &ToolchainConfig) -> Result<(), Error> {
+// The number of similarly named targets suggested for an unknown target
+const NUM_TARGET_SUGGESTIONS: usize = 3;
+
+// Targets supported by each toolchain queried, with the empty string denoting
+// the default toolchain
+static TARGET_LISTS: Mutex<BTreeMap<String, Vec<String>>> = parking_lot::const_mutex(BTreeMap::new());
+
+/// Whether `entry`, as listed by rustup, refers to `name`. Rustup lists most
+/// toolchains and components with the host triple appended.
+fn is_listed_as(entry: &str, name: &str, host: &str) -> bool {
+    entry == name || entry.strip_prefix(name).and_then(|rest| rest.strip_prefix('-')) == Some(host)
+}
+
+/// The Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The known targets most similar to `target`
+fn suggest_targets<'a>(target: &str, known: &'a [String]) -> Vec<&'a str> {
+    let mut candidates: Vec<_> = known.iter().map(|k| (edit_distance(target, k), k.as_str())).collect();
+    candidates.sort_unstable();
+    candidates
+        .into_iter()
+        .take(NUM_TARGET_SUGGESTIONS)
+        .map(|(_, k)| k)
+        .collect()
+}
+
+/// Lists the targets known to `rustc` for `toolchain`, falling back to the
+/// default toolchain if `toolchain` is not installed. The flag returned
+/// alongside the targets is `true` if they came from `toolchain` itself.
+/// Returns `None` if no `rustc` could be run.
+async fn known_targets(toolchain: &str) -> Option<(Vec<String>, bool)> {
+    let rustc = io::which("rustc", true).await.ok()?;
+    for name in [toolchain, ""] {
+        let from_toolchain = !name.is_empty();
+        if let Some(targets) = TARGET_LISTS.lock().get(name) {
+            return Some((targets.clone(), from_toolchain));
+        }
+        let mut command = Command::from(&rustc);
+        if !name.is_empty() {
+            command.arg(format!("+{}", name).as_str());
+        }
+        match command
+            .args(["--print", "target-list"])
+            .stderr(Stdio::null())
+            .output()
+            .await
+        {
+            Ok(output) => {
+                let targets: Vec<String> = output
+                    .lines()
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(String::from)
+                    .collect();
+                TARGET_LISTS.lock().insert(name.to_string(), targets.clone());
+                return Some((targets, from_toolchain));
+            }
+            Err(e) => debug!("Unable to list targets supported by toolchain {}: {}", name, e),
+        }
+    }
+    None
+}
+
+/// Checks that every requested target is known to `rustc`. Unknown targets
+/// only produce warnings if they are allowed or if the requested toolchain
+/// is not yet installed, since the default toolchain may know fewer targets.
+async fn validate_targets(toolchain_config: &ToolchainConfig) -> Result<(), Error> {
+    use itertools::Itertools as _;
+
+    if toolchain_config.targets.is_empty() {
+        return Ok(());
+    }
+    let known = known_targets(&toolchain_config.name).await;
+    if known.is_none() {
+        debug!("Unable to run rustc so requested targets will not be validated");
+    }
+    let (known, from_toolchain) = known.unwrap_or_default();
+    for target in &toolchain_config.targets {
+        if !known.is_empty() && !known.contains(target) {
+            let suggestions = suggest_targets(target, &known).iter().join(", ");
+            if !from_toolchain {
+                warning!(
+                    "Target {} is not known to the default rustc, which was checked since toolchain {} is not yet installed. Similarly named targets are: {}",
+                    target,
+                    toolchain_config.name,
+                    suggestions
+                );
+            } else if toolchain_config.allow_unknown_targets {
+                warning!(
+                    "Target {} is not known to rustc. Similarly named targets are: {}",
+                    target,
+                    suggestions
+                );
+            } else {
+                return Err(Error::UnknownTarget(target.clone(), suggestions));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn install(toolchain_config: &ToolchainConfig) -> Result<Option<ToolchainVersion>, Error> {
     let rustup = Rustup::get_or_install().await?;
     debug!("Rustup installed at: {}", rustup.get_path());
-    rustup.update().await?;
+    validate_targets(toolchain_config).await?;
+    rustup.update(toolchain_config.update).await?;
     rustup.install_toolchain(toolchain_config).await?;
-    Ok(())
+    if toolchain_config.name == NO_DEFAULT_TOOLCHAIN_NAME {
+        Ok(None)
+    } else {
+        let bin_dir = rustup.get_path().parent();
+        let version = get_installed_toolchain_version(&bin_dir, Some(&toolchain_config.name)).await?;
+        Ok(Some(version))
+    }
+}
+
+/// What is updated before installing a toolchain
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::EnumString)]
+pub enum UpdatePolicy {
+    /// Rustup itself and all installed toolchains
+    #[strum(serialize = "always")]
+    Always,
+
+    /// Only rustup itself
+    #[strum(serialize = "self-only")]
+    SelfOnly,
+
+    #[strum(serialize = "never")]
+    Never,
 }
 
 #[derive(Clone, Debug)]
@@ -23,6 +162,8 @@ pub struct ToolchainConfig {
     pub targets: Vec<String>,
     pub set_default: bool,
     pub set_override: bool,
+    pub allow_unknown_targets: bool,
+    pub update: UpdatePolicy,
 }
 
 impl Default for ToolchainConfig {
@@ -34,6 +175,8 @@ impl Default for ToolchainConfig {
             targets: Vec::new(),
             set_default: true,
             set_override: false,
+            allow_unknown_targets: false,
+            update: UpdatePolicy::Always,
         }
     }
 }
@@ -73,18 +216,14 @@ impl Rustup {
                     .map_err(Error::Js)?;
                 info!("Downloaded to: {:?}", rustup_script);
                 node::fs::chmod(&rustup_script, 0x755).await.map_err(Error::Js)?;
-                Command::from(&rustup_script)
-                    .args(args)
-                    .exec()
-                    .await
-                    .map_err(Error::Js)?;
+                Command::from(&rustup_script).args(args).exec_checked().await?;
             }
             "windows" => {
                 let rustup_exe = tool_cache::download_tool("https://win.rustup.rs")
                     .await
                     .map_err(Error::Js)?;
                 info!("Downloaded to: {:?}", rustup_exe);
-                Command::from(&rustup_exe).args(args).exec().await.map_err(Error::Js)?;
+                Command::from(&rustup_exe).args(args).exec_checked().await?;
             }
             _ => return Err(Error::UnsupportedPlatform(platform)),
         }
@@ -94,12 +233,19 @@ impl Rustup {
         Self::get().await
     }
 
-    pub async fn update(&self) -> Result<(), Error> {
+    pub async fn update(&self, policy: UpdatePolicy) -> Result<(), Error> {
+        let args: &[&str] = match policy {
+            UpdatePolicy::Always => &["update"],
+            UpdatePolicy::SelfOnly => &["self", "update"],
+            UpdatePolicy::Never => {
+                debug!("Not updating rustup or installed toolchains");
+                return Ok(());
+            }
+        };
         Command::from(&self.path)
-            .arg("update")
-            .exec()
-            .await
-            .map_err(Error::Js)?;
+            .args(args.iter().copied())
+            .exec_checked()
+            .await?;
         Ok(())
     }
 
@@ -107,62 +253,165 @@ impl Rustup {
         if config.name == NO_DEFAULT_TOOLCHAIN_NAME {
             return Ok(());
         }
-        let mut args: Vec<_> = ["toolchain", "install"].into_iter().map(String::from).collect();
-        args.push(config.name.clone());
-        args.extend(["--profile".into(), config.profile.clone()]);
+        let host = self.host_triple().await?.unwrap_or_else(|| {
+            debug!("Unable to determine host triple so toolchain will be installed unconditionally");
+            String::new()
+        });
+        let installed = !host.is_empty()
+            && self
+                .installed_toolchains()
+                .await?
+                .iter()
+                .any(|t| is_listed_as(t, &config.name, &host));
+        let (installed_targets, installed_components) = if installed {
+            (
+                self.list_targets(&config.name).await?,
+                self.list_components(&config.name).await?,
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        let mut missing_targets = Vec::new();
         for target in &config.targets {
-            args.extend(["-t".into(), target.clone()]);
+            if installed_targets.contains(target) {
+                info!("Target {} is already installed for {}", target, config.name);
+            } else {
+                missing_targets.push(target);
+            }
         }
-        // It seems that components can take multiple arguments so the toolchain name
-        // must be present before this
-        for component in &config.components {
-            args.extend(["-c".into(), component.clone()]);
+        let missing_components: Vec<_> = config
+            .components
+            .iter()
+            .filter(|c| !installed_components.iter().any(|i| is_listed_as(i, c, &host)))
+            .collect();
+        if installed && missing_targets.is_empty() && missing_components.is_empty() {
+            info!(
+                "Toolchain {} is already installed with all requested components and targets",
+                config.name
+            );
+        } else {
+            let mut args: Vec<_> = ["toolchain", "install"].into_iter().map(String::from).collect();
+            args.push(config.name.clone());
+            args.extend(["--profile".into(), config.profile.clone()]);
+            for target in missing_targets {
+                args.extend(["-t".into(), target.clone()]);
+            }
+            // It seems that components can take multiple arguments so the toolchain name
+            // must be present before this
+            for component in &config.components {
+                args.extend(["-c".into(), component.clone()]);
+            }
+            Command::from(&self.path).args(args).exec_checked().await?;
         }
-        Command::from(&self.path).args(args).exec().await.map_err(Error::Js)?;
         for (flag, option_name) in [(config.set_default, "default"), (config.set_override, "override")] {
             if flag {
                 Command::from(&self.path)
                     .arg(option_name)
                     .arg(config.name.clone())
-                    .exec()
-                    .await
-                    .map_err(Error::Js)?;
+                    .exec_checked()
+                    .await?;
             }
         }
         Ok(())
     }
 
-    #[allow(dead_code)]
+    /// Lists the targets installed for `toolchain`
+    pub async fn list_targets(&self, toolchain: &str) -> Result<Vec<String>, Error> {
+        let targets: Arc<Mutex<Vec<String>>> = Arc::default();
+        {
+            let targets = Arc::clone(&targets);
+            Command::from(&self.path)
+                .args(["target", "list", "--installed", "--toolchain", toolchain])
+                .outline(move |line| {
+                    let target = line.trim();
+                    if !target.is_empty() {
+                        targets.lock().push(target.to_string());
+                    }
+                })
+                .stderr(Stdio::null())
+                .exec_checked()
+                .await?;
+        }
+        let targets = targets.lock().drain(..).collect();
+        Ok(targets)
+    }
+
+    /// Returns the host triple rustup installs toolchains for, if rustup
+    /// reports it
+    pub async fn host_triple(&self) -> Result<Option<String>, Error> {
+        let host: Arc<Mutex<Option<String>>> = Arc::default();
+        {
+            let host = Arc::clone(&host);
+            Command::from(&self.path)
+                .arg("show")
+                .outline(move |line| {
+                    if let Some(triple) = line.trim().strip_prefix("Default host:") {
+                        *host.lock() = Some(triple.trim().to_string());
+                    }
+                })
+                .stdout(Stdio::null())
+                .exec_checked()
+                .await?;
+        }
+        let host = host.lock().take();
+        Ok(host)
+    }
+
+    /// Lists the components installed for `toolchain`. Names generally
+    /// include the host triple.
+    pub async fn list_components(&self, toolchain: &str) -> Result<Vec<String>, Error> {
+        let components: Arc<Mutex<Vec<String>>> = Arc::default();
+        {
+            let components = Arc::clone(&components);
+            Command::from(&self.path)
+                .args(["component", "list", "--installed", "--toolchain", toolchain])
+                .outline(move |line| {
+                    let component = line.trim();
+                    if !component.is_empty() {
+                        components.lock().push(component.to_string());
+                    }
+                })
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .exec_checked()
+                .await?;
+        }
+        let components = components.lock().drain(..).collect();
+        Ok(components)
+    }
+
     pub async fn installed_toolchains(&self) -> Result<Vec<String>, Error> {
         let args: Vec<_> = ["toolchain", "list"].into_iter().map(String::from).collect();
 
         let toolchains: Arc<Mutex<Vec<String>>> = Arc::default();
         {
-            let match_default = regex::Regex::new(r" *\(default\) *$").expect("Regex compilation failed");
+            let match_default = regex::Regex::new(r" *\([[:alpha:], ]+\) *$").expect("Regex compilation failed");
             let toolchains = Arc::clone(&toolchains);
             Command::from(&self.path)
                 .args(args)
                 .outline(move |line| {
                     let toolchain = match_default.replace(line, "");
-                    toolchains.lock().push(toolchain.to_string());
+                    if !toolchain.trim().is_empty() {
+                        toolchains.lock().push(toolchain.trim().to_string());
+                    }
                 })
-                .exec()
-                .await
-                .map_err(Error::Js)?;
+                .stdout(Stdio::null())
+                .exec_checked()
+                .await?;
         }
         let toolchains = toolchains.lock().drain(..).collect();
         Ok(toolchains)
     }
 
-    #[allow(dead_code)]
-    pub async fn install_component(&self, name: &str) -> Result<(), Error> {
-        Command::from(&self.path)
-            .arg("component")
-            .arg("add")
-            .arg(name)
-            .exec()
-            .await
-            .map_err(Error::Js)?;
+    /// Installs a component for `toolchain`, or the active toolchain if not
+    /// specified. Components which are already installed are left alone.
+    pub async fn install_component(&self, name: &str, toolchain: Option<&str>) -> Result<(), Error> {
+        let mut command = Command::from(&self.path);
+        command.arg("component").arg("add").arg(name);
+        if let Some(toolchain) = toolchain {
+            command.arg("--toolchain").arg(toolchain);
+        }
+        command.exec_checked().await?;
         Ok(())
     }
 
@@ -170,3 +419,38 @@ impl Rustup {
         &self.path
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{edit_distance, is_listed_as, suggest_targets};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn listed_names_include_host_triple() {
+        let host = "x86_64-unknown-linux-gnu";
+        assert!(is_listed_as("stable-x86_64-unknown-linux-gnu", "stable", host));
+        assert!(is_listed_as("clippy-x86_64-unknown-linux-gnu", "clippy", host));
+        assert!(is_listed_as("rust-src", "rust-src", host));
+        assert!(!is_listed_as(
+            "nightly-2023-01-01-x86_64-unknown-linux-gnu",
+            "nightly",
+            host
+        ));
+        assert!(!is_listed_as("1.70.0-x86_64-unknown-linux-gnu", "1.70", host));
+        assert!(!is_listed_as("rustc-dev-x86_64-unknown-linux-gnu", "rustc", host));
+    }
+
+    #[wasm_bindgen_test]
+    fn similar_targets_are_suggested() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        let known: Vec<String> = ["wasm32-unknown-unknown", "wasm32-wasi", "x86_64-unknown-linux-gnu"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            suggest_targets("wasm32-unknown-unknwon", &known)[0],
+            "wasm32-unknown-unknown"
+        );
+    }
+}
@@ -1,6 +1,10 @@
 use base64::engine::general_purpose::GeneralPurpose;
 use base64::Engine as _;
 
+#[derive(Debug, thiserror::Error)]
+#[error("Unable to decode safely encoded data: {0}")]
+pub struct DecodeError(#[from] base64::DecodeError);
+
 fn build_engine() -> GeneralPurpose {
     let config = base64::engine::general_purpose::NO_PAD;
     let alphabet = &base64::alphabet::URL_SAFE;
@@ -12,8 +16,47 @@ pub fn encode<I: AsRef<[u8]>>(input: I) -> String {
     engine.encode(input.as_ref())
 }
 
-pub fn decode<I: AsRef<[u8]>>(input: I) -> Result<Vec<u8>, base64::DecodeError> {
+pub fn decode<I: AsRef<[u8]>>(input: I) -> Result<Vec<u8>, DecodeError> {
     let engine = build_engine();
     let result = engine.decode(input.as_ref())?;
     Ok(result)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash as _, Hasher as _};
+
+        (0..len)
+            .map(|i| {
+                let mut hasher = DefaultHasher::default();
+                (seed, i).hash(&mut hasher);
+                hasher.finish().to_le_bytes()[0]
+            })
+            .collect()
+    }
+
+    #[wasm_bindgen_test]
+    fn round_trip() {
+        for seed in 0..64 {
+            for len in 0..40 {
+                let bytes = random_bytes(seed, len);
+                let encoded = encode(&bytes);
+                assert!(encoded
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+                assert_eq!(decode(&encoded).expect("Failed to decode"), bytes);
+            }
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn invalid_input_is_rejected() {
+        assert!(decode("not+base64/").is_err());
+        assert!(decode("a").is_err());
+    }
+}
@@ -1,38 +1,91 @@
 use crate::action_paths::{get_action_cache_dir, get_action_share_dir};
-use crate::actions::cache::Entry as CacheEntry;
+use crate::actions::cache::{Entry as CacheEntry, SaveOutcome};
+use crate::actions::core;
+use crate::cargo::{get_installed_toolchain_version, ToolchainVersion};
+use crate::error::{Context as _, Location};
 use crate::node::path::Path;
 use crate::node::{self};
 use crate::rustup::ToolchainConfig;
-use crate::{actions, info, Error};
-use async_recursion::async_recursion;
+use crate::{actions, debug, dir_tree, info, warning, Error};
 use rustup_toolchain_manifest::manifest::Package as ManifestPackage;
-use rustup_toolchain_manifest::Toolchain;
+use rustup_toolchain_manifest::{HashValue, InstallSpec, Toolchain};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::str::FromStr;
 use target_lexicon::Triple;
 
-const MAX_CONCURRENT_PACKAGE_INSTALLS: usize = 4;
+pub const DEFAULT_CONCURRENT_PACKAGE_INSTALLS: usize = 4;
+const MAX_CONCURRENT_PACKAGE_INSTALLS: usize = 16;
+const MEMORY_PER_PACKAGE_INSTALL: u64 = 512 * 1024 * 1024;
+const HTTP_NOT_FOUND: u16 = 404;
+const HTTP_NOT_MODIFIED: u16 = 304;
+
+// Changed whenever the layout of the cached extracted packages changes
+const PACKAGE_LAYOUT_VERSION: u32 = 1;
+
+// Changed whenever the way packages are resolved from a manifest changes
+const RESOLUTION_VERSION: u32 = 1;
+
+/// Picks a number of concurrent package installs suitable for the runner
+pub fn auto_package_install_concurrency() -> usize {
+    let by_memory = usize::try_from(node::os::total_memory() / MEMORY_PER_PACKAGE_INSTALL).unwrap_or(usize::MAX);
+    node::os::cpu_count()
+        .min(by_memory)
+        .clamp(1, MAX_CONCURRENT_PACKAGE_INSTALLS)
+}
+
+/// The subset of a manifest package needed to install it. Unlike the
+/// manifest, a list of these is cheap to store and load.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ResolvedPackage {
+    name: String,
+    version: String,
+    supported_target: String,
+    identifier: HashValue,
+    tarball_url: String,
+}
+
+impl From<&ManifestPackage> for ResolvedPackage {
+    fn from(package: &ManifestPackage) -> ResolvedPackage {
+        use rustup_toolchain_manifest::manifest::Compression;
+
+        let remote_binary = &package
+            .tarballs
+            .iter()
+            .find(|(c, _)| *c == Compression::Gzip)
+            .expect("Unable to find tar.gz")
+            .1;
+        ResolvedPackage {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            supported_target: package.supported_target.to_string(),
+            identifier: package.unique_identifier(),
+            tarball_url: remote_binary.url.to_string(),
+        }
+    }
+}
 
 fn get_toolchain_home(toolchain: &Toolchain) -> Result<Path, Error> {
     let dir = get_action_share_dir()?.join("toolchains").join(&toolchain.to_string());
     Ok(dir)
 }
 
-fn get_package_decompress_path(package: &ManifestPackage) -> Result<Path, Error> {
+fn get_package_decompress_path(package: &ResolvedPackage) -> Result<Path, Error> {
     // We must not use base64 encoding for the folder name because that
     // implies the platform filename is case sensitive.
-    let package_hash = package.unique_identifier();
     let dir = get_action_cache_dir()?
         .join("package-decompression")
-        .join(&package_hash.to_string());
+        .join(&package.identifier.to_string());
     Ok(dir)
 }
 
-fn compute_package_cache_key(package: &ManifestPackage) -> CacheEntry {
+fn compute_package_cache_key(package: &ResolvedPackage) -> CacheEntry {
     use crate::cache_key_builder::{Attribute, CacheKeyBuilder};
 
     let mut builder = CacheKeyBuilder::new(&package.name);
-    builder.add_key_data(&package.unique_identifier());
-    builder.set_attribute(Attribute::Target, package.supported_target.to_string());
+    builder.add_key_data(&package.identifier);
+    builder.add_key_data(&PACKAGE_LAYOUT_VERSION);
+    builder.set_attribute(Attribute::Target, package.supported_target.clone());
     builder.set_attribute(Attribute::ToolchainVersion, package.version.clone());
     builder.into_entry()
 }
@@ -51,105 +104,421 @@ fn default_target_for_platform() -> Result<Triple, Error> {
     Ok(target)
 }
 
-#[async_recursion(?Send)]
-async fn overlay_and_move_dir(from: &Path, to: &Path) -> Result<(), Error> {
-    node::fs::create_dir_all(to).await?;
-    {
-        let dir = node::fs::read_dir(from).await?;
-        for entry in dir {
-            let from = entry.path();
-            let to = to.join(&entry.file_name());
-            let file_type = entry.file_type();
-            if file_type.is_dir() {
-                overlay_and_move_dir(&from, &to).await?;
-            } else {
-                node::fs::rename(&from, &to).await?;
-            }
-        }
-    }
-    node::fs::remove_dir(from).await?;
-    Ok(())
-}
-
-async fn install_components(toolchain: &Toolchain, package: &ManifestPackage) -> Result<(), Error> {
+async fn install_components(toolchain: &Toolchain, package: &ResolvedPackage) -> Result<(), Error> {
     use crate::package_manifest::{EntryType, PackageManifest};
 
     let cargo_home = get_toolchain_home(toolchain)?;
     node::fs::create_dir_all(&cargo_home).await?;
 
     let extract_path = get_package_decompress_path(package)?;
-    let dir = node::fs::read_dir(&extract_path).await?;
-    for entry in dir.filter(|d| d.file_type().is_dir()) {
-        let components_path = entry.path().join("components");
-        let components: Vec<String> = node::fs::read_file(&components_path)
+    let components_path = extract_path.join("components");
+    let components: Vec<String> = node::fs::read_file(&components_path)
+        .await
+        .map(|data| String::from_utf8_lossy(&data[..]).into_owned())?
+        .lines()
+        .map(String::from)
+        .collect();
+    for component in components {
+        let component_path = extract_path.join(&component);
+        let manifest_path = component_path.clone().join("manifest.in");
+        let manifest = node::fs::read_file(&manifest_path)
             .await
-            .map(|data| String::from_utf8_lossy(&data[..]).into_owned())?
-            .lines()
-            .map(String::from)
-            .collect();
-        for component in components {
-            let component_path = entry.path().join(&component);
-            let manifest_path = component_path.clone().join("manifest.in");
-            let manifest = node::fs::read_file(&manifest_path)
-                .await
-                .map(|data| String::from_utf8_lossy(&data[..]).into_owned())?;
-            let manifest = PackageManifest::from_str(manifest.as_str())?;
-            for (entry_type, path) in manifest.iter() {
-                let source = component_path.join(path);
-                let dest = cargo_home.join(path);
-                node::fs::create_dir_all(&dest.parent()).await?;
-
-                match *entry_type {
-                    EntryType::File => node::fs::rename(&source, &dest).await?,
-                    EntryType::Directory => overlay_and_move_dir(&source, &dest).await?,
-                }
+            .map(|data| String::from_utf8_lossy(&data[..]).into_owned())?;
+        let manifest = PackageManifest::from_str(manifest.as_str())
+            .map_err(|e| Location::new(&manifest_path).line(Some(e.line())).wrap(e))?;
+        for (entry_type, path) in manifest.iter() {
+            let source = component_path.join(path);
+            let dest = cargo_home.join(path);
+            node::fs::create_dir_all(&dest.parent()).await?;
+
+            match *entry_type {
+                EntryType::File => node::fs::rename(&source, &dest).await?,
+                EntryType::Directory => dir_tree::overlay_and_move_dir(&source, &dest).await?,
             }
         }
     }
     Ok(())
 }
 
-async fn cleanup_decompressed_package(package: &ManifestPackage) -> Result<(), Error> {
+async fn cleanup_decompressed_package(package: &ResolvedPackage) -> Result<(), Error> {
     let extract_path = get_package_decompress_path(package)?;
-    actions::io::rm_rf(&extract_path).await?;
+    actions::io::rm_rf(&extract_path.to_namespaced()).await?;
     Ok(())
 }
 
-async fn fetch_and_decompress_package(package: &ManifestPackage) -> Result<(), Error> {
+async fn fetch_and_decompress_package(package: &ResolvedPackage, log: &mut Vec<String>) -> Result<(), Error> {
     use actions::tool_cache::{self, StreamCompression};
-    use rustup_toolchain_manifest::manifest::Compression;
 
     let extract_path = get_package_decompress_path(package)?;
     let mut cache_entry = compute_package_cache_key(package);
     cache_entry.path(&extract_path);
-    if let Some(key) = cache_entry.restore().await? {
-        info!("Restored files from cache with key {}", key);
+    if let Some(key) = cache_entry
+        .restore()
+        .await
+        .context("while restoring toolchain package from cache")?
+    {
+        log.push(format!("Restored files from cache with key {}", key));
     } else {
-        let remote_binary = package
-            .tarballs
-            .iter()
-            .find(|(c, _)| *c == Compression::Gzip)
-            .expect("Unable to find tar.gz")
-            .1
-            .clone();
-        info!("Will need to download the following: {:#?}", remote_binary);
-        let tarball_path = tool_cache::download_tool(remote_binary.url.as_str())
+        let url = package.tarball_url.as_str();
+        log.push(format!("Will need to download {}", url));
+        let tarball_path = tool_cache::download_tool(url)
+            .await
+            .with_context(|| format!("while downloading toolchain tarball from {}", url))?;
+        log.push(format!("Downloaded tarball to {}", tarball_path));
+        log.push(format!("Will extract to {}", extract_path));
+        // Everything in a package is inside a top-level folder named for the package.
+        // Components such as rust-docs contain deeply nested paths.
+        tool_cache::extract_tar(
+            &tarball_path,
+            StreamCompression::Gzip,
+            Some(&extract_path.to_namespaced()),
+            1,
+        )
+        .await
+        .context("while extracting toolchain tarball")?;
+        log.push(format!("Extracted to {}", extract_path));
+        if let SaveOutcome::Saved(cache_id) = cache_entry
+            .save()
             .await
-            .map_err(Error::Js)?;
-        info!("Downloaded tarball to {}", tarball_path);
-        info!("Will extract to {}", extract_path);
-        tool_cache::extract_tar(&tarball_path, StreamCompression::Gzip, Some(&extract_path)).await?;
-        info!("Extracted to {}", extract_path);
-        let cache_id = cache_entry.save().await?;
-        info!("Saved as {}", cache_id);
+            .context("while saving toolchain package to cache")?
+        {
+            log.push(format!("Saved as {}", cache_id));
+        }
+    }
+    Ok(())
+}
+
+async fn install_package(toolchain: &Toolchain, package: &ResolvedPackage, log: &mut Vec<String>) -> Result<(), Error> {
+    fetch_and_decompress_package(package, log).await?;
+    install_components(toolchain, package)
+        .await
+        .with_context(|| format!("while installing components of package {}", package.name))?;
+    cleanup_decompressed_package(package)
+        .await
+        .with_context(|| format!("while cleaning up decompressed package {}", package.name))?;
+    log.push(format!("Installed {}", package.name));
+    Ok(())
+}
+
+/// Returns the names of packages in a channel manifest available for the
+/// specified target, including those only reachable via a rename
+fn available_packages(manifest: &toml::value::Table, target: &str) -> BTreeSet<String> {
+    let is_available = |package: &toml::Value| {
+        [target, "*"].into_iter().any(|t| {
+            package
+                .get("target")
+                .and_then(|targets| targets.get(t))
+                .and_then(|t| t.get("available"))
+                .and_then(toml::Value::as_bool)
+                .unwrap_or(false)
+        })
+    };
+    let mut result = BTreeSet::new();
+    if let Some(packages) = manifest.get("pkg").and_then(toml::Value::as_table) {
+        for (name, package) in packages {
+            if is_available(package) {
+                result.insert(name.clone());
+            }
+        }
+    }
+    if let Some(renames) = manifest.get("renames").and_then(toml::Value::as_table) {
+        for (from, rename) in renames {
+            let to = rename.get("to").and_then(toml::Value::as_str);
+            if matches!(to, Some(to) if result.contains(to)) {
+                result.insert(from.clone());
+            }
+        }
+    }
+    result
+}
+
+/// Returns the targets for which the standard library is available
+fn available_targets(manifest: &toml::value::Table) -> BTreeSet<String> {
+    manifest
+        .get("pkg")
+        .and_then(|p| p.get("rust-std"))
+        .and_then(|p| p.get("target"))
+        .and_then(toml::Value::as_table)
+        .map(|targets| {
+            targets
+                .iter()
+                .filter(|(_, t)| t.get("available").and_then(toml::Value::as_bool).unwrap_or(false))
+                .map(|(name, _)| name.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the names of packages which are the same for every target, such as
+/// `rust-src`
+fn target_independent_packages(manifest: &toml::value::Table) -> BTreeSet<String> {
+    manifest
+        .get("pkg")
+        .and_then(toml::Value::as_table)
+        .map(|packages| {
+            packages
+                .iter()
+                .filter(|(_, p)| p.get("target").and_then(|t| t.get("*")).is_some())
+                .map(|(name, _)| name.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Removes duplicate packages. As with rustup, a package built per target
+/// (e.g. `rust-std`) is installed once for each target whereas a
+/// target-independent package (e.g. `rust-src`) is installed only once, no
+/// matter how many targets were requested.
+fn deduplicate_packages(packages: Vec<ResolvedPackage>, target_independent: &BTreeSet<String>) -> Vec<ResolvedPackage> {
+    let mut seen = BTreeSet::new();
+    packages
+        .into_iter()
+        .filter(|package| {
+            let target = if target_independent.contains(&package.name) {
+                None
+            } else {
+                Some(package.supported_target.clone())
+            };
+            seen.insert((package.name.clone(), target))
+        })
+        .collect()
+}
+
+/// Checks that every requested component and target is present in the
+/// channel manifest
+fn validate_install_request(
+    manifest: &toml::value::Table,
+    host: &Triple,
+    toolchain_config: &ToolchainConfig,
+) -> Result<(), Error> {
+    use itertools::Itertools as _;
+
+    let components = available_packages(manifest, &host.to_string());
+    for component in &toolchain_config.components {
+        if !components.contains(component) {
+            return Err(Error::UnknownComponent(component.clone(), components.iter().join(", ")));
+        }
+    }
+    let targets = available_targets(manifest);
+    for target in &toolchain_config.targets {
+        if !targets.contains(target) {
+            return Err(Error::UnknownTarget(target.clone(), targets.iter().join(", ")));
+        }
+    }
+    Ok(())
+}
+
+fn is_url(location: &str) -> bool {
+    ["http://", "https://"]
+        .iter()
+        .any(|scheme| location.starts_with(scheme))
+}
+
+/// Where the channel manifest for `toolchain` is read from, which may be a
+/// URL or a local path
+fn manifest_source(toolchain: &Toolchain, location: Option<&str>) -> String {
+    location.map_or_else(|| toolchain.manifest_url().to_string(), String::from)
+}
+
+/// When a manifest stored in the action cache directory was downloaded, so
+/// that it is only downloaded again if it has since changed.
+/// `@actions/tool-cache` does not expose response headers so an `ETag`
+/// cannot be recorded.
+#[derive(Serialize, Deserialize)]
+struct StoredManifest {
+    url: String,
+    downloaded_secs: i64,
+}
+
+/// The paths of the stored copy of the manifest at `url` and its download
+/// time
+fn get_stored_manifest_paths(url: &str) -> Result<(Path, Path), Error> {
+    use crate::hasher::Blake3 as Blake3Hasher;
+    use std::hash::Hash as _;
+
+    let mut hasher = Blake3Hasher::default();
+    url.hash(&mut hasher);
+    let name = hasher.hash_value().to_string();
+    let manifest_path = get_action_cache_dir()?
+        .join("channel-manifests")
+        .join(&format!("{}.toml", name));
+    let info_path = manifest_path.with_extension("postcard");
+    Ok((manifest_path, info_path))
+}
+
+/// Formats `time` as an HTTP date
+fn http_date(time: &chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+async fn load_stored_manifest(url: &str) -> Option<(Path, chrono::DateTime<chrono::Utc>)> {
+    use chrono::TimeZone as _;
+
+    let (manifest_path, info_path) = get_stored_manifest_paths(url).ok()?;
+    let info = node::fs::read_file(&info_path).await.ok()?;
+    let info: StoredManifest = postcard::from_bytes(&info).ok()?;
+    if info.url != url || !manifest_path.exists().await {
+        return None;
+    }
+    let downloaded = chrono::Utc.timestamp_opt(info.downloaded_secs, 0).single()?;
+    Some((manifest_path, downloaded))
+}
+
+async fn store_manifest(
+    url: &str,
+    downloaded_path: &Path,
+    downloaded: &chrono::DateTime<chrono::Utc>,
+) -> Result<(), Error> {
+    let (manifest_path, info_path) = get_stored_manifest_paths(url)?;
+    node::fs::create_dir_all(&manifest_path.parent()).await?;
+    let manifest = node::fs::read_file(downloaded_path).await?;
+    node::fs::write_file(&manifest_path, &manifest).await?;
+    let info = StoredManifest {
+        url: url.to_string(),
+        downloaded_secs: downloaded.timestamp(),
+    };
+    node::fs::write_file(&info_path, &postcard::to_stdvec(&info)?).await?;
+    Ok(())
+}
+
+/// Downloads the manifest at `url`, or reuses a copy from an earlier run if
+/// the server reports it has not been modified since
+async fn download_manifest(url: &str) -> Result<Path, Error> {
+    use actions::tool_cache::{self, DownloadTool};
+
+    let stored = load_stored_manifest(url).await;
+    let mut download = DownloadTool::from(url);
+    if let Some((_, downloaded)) = &stored {
+        download.header("If-Modified-Since", http_date(downloaded));
+    }
+    let now = chrono::Utc::now();
+    match download.download().await {
+        Ok(manifest_path) => {
+            info!("Downloaded manifest to {}", manifest_path);
+            if let Err(e) = store_manifest(url, &manifest_path, &now).await {
+                warning!("Unable to store manifest for reuse: {}", e);
+            }
+            Ok(manifest_path)
+        }
+        Err(e) => match (tool_cache::http_status(&e), stored) {
+            (Some(HTTP_NOT_MODIFIED), Some((manifest_path, downloaded))) => {
+                info!(
+                    "Manifest has not changed since it was downloaded at {}. Using stored copy.",
+                    downloaded
+                );
+                Ok(manifest_path)
+            }
+            (Some(HTTP_NOT_FOUND), _) => Err(Error::ManifestNotFound(url.into())),
+            _ => Err(Error::Context(
+                "while downloading toolchain manifest".into(),
+                Box::new(e.into()),
+            )),
+        },
     }
+}
+
+/// Fetches the channel manifest for `toolchain` from `source`
+async fn fetch_manifest(toolchain: &Toolchain, source: &str) -> Result<String, Error> {
+    let manifest_path = if is_url(source) {
+        info!("Will download manifest for toolchain {} from {}", toolchain, source);
+        download_manifest(source).await?
+    } else {
+        info!("Will read manifest for toolchain {} from {}", toolchain, source);
+        Path::from(source)
+    };
+    let manifest = node::fs::read_file(&manifest_path)
+        .await
+        .context("while reading toolchain manifest")?;
+    let manifest = String::from_utf8(manifest).map_err(|_| Error::ManifestNotUtf8)?;
+    Ok(manifest)
+}
+
+fn get_resolved_packages_path(key: &HashValue) -> Result<Path, Error> {
+    let path = get_action_cache_dir()?.join("resolved-packages").join(&key.to_string());
+    Ok(path)
+}
+
+/// Identifies the packages resolved from `manifest` for an install
+fn compute_resolution_key(manifest: &str, target: &Triple, install_spec: &InstallSpec) -> HashValue {
+    use crate::hasher::Blake3 as Blake3Hasher;
+    use std::hash::Hash as _;
+
+    let mut hasher = Blake3Hasher::default();
+    RESOLUTION_VERSION.hash(&mut hasher);
+    manifest.hash(&mut hasher);
+    target.to_string().hash(&mut hasher);
+    install_spec.profile.hash(&mut hasher);
+    install_spec.components.hash(&mut hasher);
+    install_spec.targets.hash(&mut hasher);
+    hasher.hash_value()
+}
+
+async fn load_resolved_packages(path: &Path) -> Option<Vec<ResolvedPackage>> {
+    if !path.exists().await {
+        return None;
+    }
+    let packages = match node::fs::read_file(path).await {
+        Ok(data) => postcard::from_bytes(&data).map_err(Error::from),
+        Err(e) => Err(Error::from(e)),
+    };
+    match packages {
+        Ok(packages) => Some(packages),
+        Err(e) => {
+            debug!("Unable to load resolved packages from {}: {}", path, e);
+            None
+        }
+    }
+}
+
+async fn store_resolved_packages(path: &Path, packages: &[ResolvedPackage]) -> Result<(), Error> {
+    node::fs::create_dir_all(&path.parent()).await?;
+    let data = postcard::to_stdvec(packages)?;
+    node::fs::write_file(path, &data).await?;
     Ok(())
 }
 
-pub async fn install(toolchain_config: &ToolchainConfig) -> Result<(), Error> {
-    use actions::tool_cache;
+/// Finds the packages to install, reusing the result of a previous run with
+/// an identical manifest and install request to avoid parsing the manifest
+async fn resolve_packages(
+    manifest: &str,
+    target: &Triple,
+    toolchain_config: &ToolchainConfig,
+) -> Result<Vec<ResolvedPackage>, Error> {
+    use rustup_toolchain_manifest::Manifest;
+
+    let install_spec = InstallSpec {
+        profile: toolchain_config.profile.clone(),
+        components: toolchain_config.components.iter().cloned().collect(),
+        targets: toolchain_config.targets.iter().cloned().collect(),
+    };
+    let key = compute_resolution_key(manifest, target, &install_spec);
+    let path = get_resolved_packages_path(&key)?;
+    if let Some(packages) = load_resolved_packages(&path).await {
+        info!("Reusing packages previously resolved from an identical manifest");
+        return Ok(packages);
+    }
+    let table: toml::value::Table = toml::from_str(manifest)?;
+    validate_install_request(&table, target, toolchain_config).context("while checking toolchain manifest")?;
+    let manifest = Manifest::try_from(manifest).context("while parsing toolchain manifest")?;
+    info!("Attempting to find toolchain for target {}", target);
+    let packages: Vec<ResolvedPackage> = manifest
+        .find_downloads_for_install(target, &install_spec)?
+        .iter()
+        .map(ResolvedPackage::from)
+        .collect();
+    let packages = deduplicate_packages(packages, &target_independent_packages(&table));
+    if let Err(e) = store_resolved_packages(&path, &packages).await {
+        warning!("Unable to store resolved packages to {}: {}", path, e);
+    }
+    Ok(packages)
+}
+
+pub async fn install(
+    toolchain_config: &ToolchainConfig,
+    concurrency: usize,
+    manifest_location: Option<&str>,
+) -> Result<Option<ToolchainVersion>, Error> {
     use futures::{StreamExt as _, TryStreamExt as _};
-    use rustup_toolchain_manifest::{InstallSpec, Manifest};
 
     let toolchain = {
         let mut toolchain = Toolchain::from_str(&toolchain_config.name)?;
@@ -159,38 +528,37 @@ pub async fn install(toolchain_config: &ToolchainConfig) -> Result<(), Error> {
         });
         toolchain
     };
-    let manifest_url = toolchain.manifest_url();
-    info!(
-        "Will download manifest for toolchain {} from {}",
-        toolchain, manifest_url
-    );
-    let manifest_path = tool_cache::download_tool(manifest_url.as_str())
+    let group = core::Group::new(format!("Fetching manifest for toolchain {}", toolchain));
+    let source = manifest_source(&toolchain, manifest_location);
+    let describe_manifest = || format!("while installing toolchain {} using manifest {}", toolchain, source);
+    let manifest = fetch_manifest(&toolchain, &source)
         .await
-        .map_err(Error::Js)?;
-    info!("Downloaded manifest to {}", manifest_path);
-    let manifest = node::fs::read_file(&manifest_path).await?;
-    let manifest = String::from_utf8(manifest).map_err(|_| Error::ManifestNotUtf8)?;
-    let manifest = Manifest::try_from(manifest.as_str())?;
+        .with_context(describe_manifest)?;
     let target = toolchain.host.clone().expect("Toolchain target unexpectedly missing");
-    info!("Attempting to find toolchain for target {}", target);
-    let install_spec = InstallSpec {
-        profile: toolchain_config.profile.clone(),
-        components: toolchain_config.components.iter().cloned().collect(),
-        targets: toolchain_config.targets.iter().cloned().collect(),
-    };
-    let downloads = manifest.find_downloads_for_install(&target, &install_spec)?;
+    let downloads = resolve_packages(&manifest, &target, toolchain_config)
+        .await
+        .with_context(describe_manifest)?;
+    drop(group);
+
     let process_packages = futures::stream::iter(downloads.iter())
         .map(|download| async {
-            fetch_and_decompress_package(download).await?;
-            install_components(&toolchain, download).await?;
-            cleanup_decompressed_package(download).await?;
-            Ok::<_, Error>(())
+            // Packages are processed concurrently so we buffer each package's log and
+            // emit it as a single group once the package is done with.
+            let mut log = Vec::new();
+            let result = install_package(&toolchain, download, &mut log).await;
+            let _group = core::Group::new(format!("{} ({})", download.name, download.supported_target));
+            for line in log {
+                info!("{}", line);
+            }
+            result
         })
-        .buffer_unordered(MAX_CONCURRENT_PACKAGE_INSTALLS);
+        .buffer_unordered(concurrency);
     process_packages.try_collect().await?;
 
+    let cargo_bin = get_toolchain_home(&toolchain)?.join("bin");
+    dir_tree::ensure_executable(&cargo_bin).await?;
+
     if toolchain_config.set_default {
-        let cargo_bin = get_toolchain_home(&toolchain)?.join("bin");
         actions::core::add_path(&cargo_bin);
     } else {
         return Err(Error::ToolchainInstallFunctionality("default=false".into()));
@@ -198,5 +566,187 @@ pub async fn install(toolchain_config: &ToolchainConfig) -> Result<(), Error> {
     if toolchain_config.set_override {
         return Err(Error::ToolchainInstallFunctionality("override".into()));
     }
-    Ok(())
+    if downloads.iter().any(|package| package.name == "rustc") {
+        let version = get_installed_toolchain_version(&cargo_bin, None).await?;
+        Ok(Some(version))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{deduplicate_packages, target_independent_packages, validate_install_request, ResolvedPackage};
+    use crate::rustup::ToolchainConfig;
+    use crate::Error;
+    use std::str::FromStr as _;
+    use target_lexicon::Triple;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    const MANIFEST: &str = r#"
+        manifest-version = "2"
+        date = "2023-06-01"
+
+        [pkg.rust]
+        version = "1.70.0 (90c541806 2023-05-31)"
+
+        [pkg.rust.target.x86_64-unknown-linux-gnu]
+        available = true
+        url = "https://static.rust-lang.org/dist/2023-06-01/rust-1.70.0-x86_64-unknown-linux-gnu.tar.gz"
+        hash = "358c6b4dbd8ea830b032b239a450af49bcef4a3dcaa3618b4c496903452fa1c7"
+        xz_url = "https://static.rust-lang.org/dist/2023-06-01/rust-1.70.0-x86_64-unknown-linux-gnu.tar.xz"
+        xz_hash = "c63d992055b4b3c51a53ca5b399428228f62461886727e165c1424b20628dcdc"
+
+        [[pkg.rust.target.x86_64-unknown-linux-gnu.components]]
+        pkg = "rustc"
+        target = "x86_64-unknown-linux-gnu"
+
+        [[pkg.rust.target.x86_64-unknown-linux-gnu.components]]
+        pkg = "cargo"
+        target = "x86_64-unknown-linux-gnu"
+
+        [[pkg.rust.target.x86_64-unknown-linux-gnu.components]]
+        pkg = "rust-std"
+        target = "x86_64-unknown-linux-gnu"
+
+        [[pkg.rust.target.x86_64-unknown-linux-gnu.extensions]]
+        pkg = "rust-std"
+        target = "wasm32-unknown-unknown"
+
+        [[pkg.rust.target.x86_64-unknown-linux-gnu.extensions]]
+        pkg = "rust-src"
+        target = "*"
+
+        [[pkg.rust.target.x86_64-unknown-linux-gnu.extensions]]
+        pkg = "rustfmt-preview"
+        target = "x86_64-unknown-linux-gnu"
+
+        [pkg.rustc]
+        version = "1.70.0 (90c541806 2023-05-31)"
+
+        [pkg.rustc.target.x86_64-unknown-linux-gnu]
+        available = true
+        url = "https://static.rust-lang.org/dist/2023-06-01/rustc-1.70.0-x86_64-unknown-linux-gnu.tar.gz"
+        hash = "59f18541864c8d3b10d7f6e2317208f72b60ae66681cddad781e5b7ed259b319"
+        xz_url = "https://static.rust-lang.org/dist/2023-06-01/rustc-1.70.0-x86_64-unknown-linux-gnu.tar.xz"
+        xz_hash = "47804b3736d4c8ebf0c96211275eca3f57a95685a4b42ad798d3aaaa3ddab4ee"
+
+        [pkg.cargo]
+        version = "1.70.0 (90c541806 2023-05-31)"
+
+        [pkg.cargo.target.x86_64-unknown-linux-gnu]
+        available = true
+        url = "https://static.rust-lang.org/dist/2023-06-01/cargo-1.70.0-x86_64-unknown-linux-gnu.tar.gz"
+        hash = "bae74b886ee1f881c5ef0b9173fe2ada1c512f82f39618f3bbe09eef834d7654"
+        xz_url = "https://static.rust-lang.org/dist/2023-06-01/cargo-1.70.0-x86_64-unknown-linux-gnu.tar.xz"
+        xz_hash = "90913b64f9679eb38babbd31ba5141a0e66e7db6294f21eced542bcce1b837fc"
+
+        [pkg.rust-std]
+        version = "1.70.0 (90c541806 2023-05-31)"
+
+        [pkg.rust-std.target.x86_64-unknown-linux-gnu]
+        available = true
+        url = "https://static.rust-lang.org/dist/2023-06-01/rust-std-1.70.0-x86_64-unknown-linux-gnu.tar.gz"
+        hash = "2debf08733f16e20a5fc99f54453baaaf6cbb96ff6254f300ce664c85c137652"
+        xz_url = "https://static.rust-lang.org/dist/2023-06-01/rust-std-1.70.0-x86_64-unknown-linux-gnu.tar.xz"
+        xz_hash = "26579ace85080d702a31c822c029ba6e92517a62e9f531e630f38a868eb835e0"
+
+        [pkg.rust-std.target.wasm32-unknown-unknown]
+        available = true
+        url = "https://static.rust-lang.org/dist/2023-06-01/rust-std-1.70.0-wasm32-unknown-unknown.tar.gz"
+        hash = "233d51da0a66f9a38873db4d3e949d2df3c17e7f3b3fc57d4285eb631afcc8e8"
+        xz_url = "https://static.rust-lang.org/dist/2023-06-01/rust-std-1.70.0-wasm32-unknown-unknown.tar.xz"
+        xz_hash = "568d95c5e9d56cebb2d14af49b28c13d698ebebc8fd6881fff0f0f1218e6f976"
+
+        [pkg.rust-std.target.sparc64-unknown-linux-gnu]
+        available = false
+
+        [pkg.rust-src]
+        version = "1.70.0 (90c541806 2023-05-31)"
+
+        [pkg.rust-src.target."*"]
+        available = true
+        url = "https://static.rust-lang.org/dist/2023-06-01/rust-src-1.70.0.tar.gz"
+        hash = "09b60330788c105aabd941b18e42da8577d7c889ea354c9829f4d493dadb7526"
+        xz_url = "https://static.rust-lang.org/dist/2023-06-01/rust-src-1.70.0.tar.xz"
+        xz_hash = "e0beec6d3d4ca22da239cc16ce3cc2b49411962c97203f0053b33e93d435b08c"
+
+        [pkg.rustfmt-preview]
+        version = "1.70.0 (90c541806 2023-05-31)"
+
+        [pkg.rustfmt-preview.target.x86_64-unknown-linux-gnu]
+        available = true
+        url = "https://static.rust-lang.org/dist/2023-06-01/rustfmt-preview-1.70.0-x86_64-unknown-linux-gnu.tar.gz"
+        hash = "1c37790327789f491b9ac14cd391e211082c80f104c9f5939e4edef065fd54ad"
+        xz_url = "https://static.rust-lang.org/dist/2023-06-01/rustfmt-preview-1.70.0-x86_64-unknown-linux-gnu.tar.xz"
+        xz_hash = "364e8365d2fb4adeb1a8a74940985587cec266b8e76b2641c37dd20734f26f47"
+
+        [renames.rustfmt]
+        to = "rustfmt-preview"
+
+        [profiles]
+        minimal = ["rustc", "cargo", "rust-std"]
+    "#;
+
+    fn validate(components: &[&str], targets: &[&str]) -> Result<(), Error> {
+        let host = Triple::from_str("x86_64-unknown-linux-gnu").expect("Failed to parse triple");
+        let config = ToolchainConfig {
+            components: components.iter().copied().map(String::from).collect(),
+            targets: targets.iter().copied().map(String::from).collect(),
+            ..ToolchainConfig::default()
+        };
+        let manifest = toml::from_str(MANIFEST).expect("Failed to parse manifest");
+        validate_install_request(&manifest, &host, &config)
+    }
+
+    #[wasm_bindgen_test]
+    fn target_independent_packages_are_installed_once() {
+        use rustup_toolchain_manifest::{InstallSpec, Manifest};
+
+        let host = Triple::from_str("x86_64-unknown-linux-gnu").expect("Failed to parse triple");
+        let wasm = "wasm32-unknown-unknown";
+        let install_spec = InstallSpec {
+            profile: "minimal".into(),
+            components: ["rust-src".to_string()].into_iter().collect(),
+            targets: [wasm.to_string()].into_iter().collect(),
+        };
+        let manifest = Manifest::try_from(MANIFEST).expect("Failed to parse manifest");
+        let packages: Vec<ResolvedPackage> = manifest
+            .find_downloads_for_install(&host, &install_spec)
+            .expect("Failed to find downloads")
+            .iter()
+            .map(ResolvedPackage::from)
+            .collect();
+        let table = toml::from_str(MANIFEST).expect("Failed to parse manifest");
+        let selected = deduplicate_packages(packages, &target_independent_packages(&table));
+        let count = |name: &str| selected.iter().filter(|p| p.name == name).count();
+        assert_eq!(count("rust-src"), 1);
+        assert_eq!(count("rustc"), 1);
+        let std_targets: Vec<_> = selected
+            .iter()
+            .filter(|p| p.name == "rust-std")
+            .map(|p| p.supported_target.as_str())
+            .collect();
+        assert_eq!(std_targets.len(), 2);
+        assert!(std_targets.contains(&host.to_string().as_str()));
+        assert!(std_targets.contains(&wasm));
+    }
+
+    #[wasm_bindgen_test]
+    fn known_components_and_targets_are_accepted() {
+        assert!(validate(&["rust-src", "rustfmt", "rustfmt-preview"], &["wasm32-unknown-unknown"]).is_ok());
+    }
+
+    #[wasm_bindgen_test]
+    fn unknown_components_and_targets_are_rejected() {
+        assert!(matches!(
+            validate(&["clippy"], &[]),
+            Err(Error::UnknownComponent(name, _)) if name == "clippy"
+        ));
+        assert!(matches!(
+            validate(&[], &["sparc64-unknown-linux-gnu"]),
+            Err(Error::UnknownTarget(name, valid)) if name == "sparc64-unknown-linux-gnu"
+                && valid == "wasm32-unknown-unknown, x86_64-unknown-linux-gnu"
+        ));
+    }
 }